@@ -0,0 +1,153 @@
+use revm::primitives::{Address, SpecId, B256, U256 as rU256};
+
+use super::tycho_models::Chain;
+
+/// Hardforks of Ethereum mainnet by activation block
+const MAINNET_HARDFORKS: [(u64, SpecId); 16] = [
+    (0, SpecId::FRONTIER),
+    (1_150_000, SpecId::HOMESTEAD),
+    (1_920_000, SpecId::DAO_FORK),
+    (2_463_000, SpecId::TANGERINE),
+    (2_675_000, SpecId::SPURIOUS_DRAGON),
+    (4_370_000, SpecId::BYZANTIUM),
+    (7_280_000, SpecId::PETERSBURG),
+    (9_069_000, SpecId::ISTANBUL),
+    (9_200_000, SpecId::MUIR_GLACIER),
+    (12_244_000, SpecId::BERLIN),
+    (12_965_000, SpecId::LONDON),
+    (13_773_000, SpecId::ARROW_GLACIER),
+    (15_050_000, SpecId::GRAY_GLACIER),
+    (15_537_394, SpecId::MERGE),
+    (17_034_870, SpecId::SHANGHAI),
+    (19_426_587, SpecId::CANCUN),
+];
+
+/// The block environment, chain and hardfork simulations run with.
+///
+/// The defaults match the environment the engine has always used: Cancun, chain id 1, no base
+/// fee or gas price, a zero coinbase and prevrandao and an unlimited block gas limit.
+///
+/// If `base_fee` is set, `gas_price` has to be at least as high and callers need enough balance
+/// to pay for the gas, as on chain.
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct EvmConfig {
+    /// The hardfork to simulate with. If not set, it's resolved from the block number of the
+    /// simulation using `hardforks`.
+    pub spec_id: Option<SpecId>,
+    /// Activation blocks of the chain's hardforks, sorted by block
+    pub hardforks: Vec<(u64, SpecId)>,
+    pub chain_id: u64,
+    pub base_fee: rU256,
+    pub gas_price: rU256,
+    pub coinbase: Address,
+    pub prevrandao: B256,
+    pub block_gas_limit: rU256,
+    /// Gas limit of transactions that don't set one
+    pub default_gas_limit: u64,
+}
+
+impl Default for EvmConfig {
+    fn default() -> Self {
+        EvmConfig {
+            spec_id: None,
+            hardforks: Vec::new(),
+            chain_id: 1,
+            base_fee: rU256::ZERO,
+            gas_price: rU256::ZERO,
+            coinbase: Address::ZERO,
+            prevrandao: B256::ZERO,
+            block_gas_limit: rU256::MAX,
+            default_gas_limit: 8_000_000,
+        }
+    }
+}
+
+impl EvmConfig {
+    /// Sets the chain id and hardfork schedule of the given chain.
+    ///
+    /// Chains without a known schedule are simulated with Cancun unless `spec_id` is set.
+    pub fn with_chain(mut self, chain: Chain) -> Self {
+        match chain {
+            Chain::Ethereum => {
+                self.chain_id = 1;
+                self.hardforks = MAINNET_HARDFORKS.to_vec();
+            }
+            Chain::ZkSync => {
+                self.chain_id = 324;
+                self.hardforks = Vec::new();
+            }
+        }
+        self
+    }
+
+    pub fn with_spec_id(mut self, spec_id: SpecId) -> Self {
+        self.spec_id = Some(spec_id);
+        self
+    }
+
+    pub fn with_base_fee(mut self, base_fee: rU256) -> Self {
+        self.base_fee = base_fee;
+        self
+    }
+
+    pub fn with_gas_price(mut self, gas_price: rU256) -> Self {
+        self.gas_price = gas_price;
+        self
+    }
+
+    pub fn with_coinbase(mut self, coinbase: Address) -> Self {
+        self.coinbase = coinbase;
+        self
+    }
+
+    pub fn with_prevrandao(mut self, prevrandao: B256) -> Self {
+        self.prevrandao = prevrandao;
+        self
+    }
+
+    pub fn with_block_gas_limit(mut self, block_gas_limit: rU256) -> Self {
+        self.block_gas_limit = block_gas_limit;
+        self
+    }
+
+    /// Returns the hardfork active at the given block.
+    pub fn spec_id_at(&self, block_number: u64) -> SpecId {
+        self.spec_id.unwrap_or_else(|| {
+            self.hardforks
+                .iter()
+                .rev()
+                .find(|(activation_block, _)| *activation_block <= block_number)
+                .map(|(_, spec_id)| *spec_id)
+                .unwrap_or(SpecId::CANCUN)
+        })
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[rstest]
+    #[case::genesis(0, SpecId::FRONTIER)]
+    #[case::before_london(12_964_999, SpecId::BERLIN)]
+    #[case::london(12_965_000, SpecId::LONDON)]
+    #[case::merge(16_000_000, SpecId::MERGE)]
+    #[case::cancun(20_463_609, SpecId::CANCUN)]
+    fn test_mainnet_spec_id_at(#[case] block_number: u64, #[case] expected: SpecId) {
+        let config = EvmConfig::default().with_chain(Chain::Ethereum);
+
+        assert_eq!(config.spec_id_at(block_number), expected);
+    }
+
+    #[test]
+    fn test_spec_id_overrides_schedule() {
+        let config = EvmConfig::default()
+            .with_chain(Chain::Ethereum)
+            .with_spec_id(SpecId::SHANGHAI);
+
+        assert_eq!(config.spec_id_at(20_463_609), SpecId::SHANGHAI);
+        assert_eq!(EvmConfig::default().spec_id_at(0), SpecId::CANCUN);
+    }
+}
@@ -0,0 +1,285 @@
+use ethers::{
+    abi::{Event, RawLog, Token},
+    types::H256,
+};
+use revm::{
+    interpreter::{
+        CallInputs, CallOutcome, CreateInputs, CreateOutcome, EOFCreateInputs, Interpreter,
+    },
+    primitives::{Address, Bytes, Log, B256, U256 as rU256},
+    Database, EvmContext, Inspector,
+};
+
+/// An event emitted during a simulated transaction
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SimulationLog {
+    /// The contract that emitted the event
+    pub address: Address,
+    pub topics: Vec<B256>,
+    pub data: Bytes,
+    /// Depth of the call that emitted the event; the transaction's call has depth 0. Only known
+    /// for simulations recorded with a `LogInspector`, i.e. traced ones.
+    pub depth: Option<usize>,
+    /// The decoded event, if a matching ABI was given to `decode`
+    pub decoded: Option<DecodedLog>,
+}
+
+/// An event decoded against its ABI
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedLog {
+    pub name: String,
+    /// Parameter names and values, in declaration order
+    pub params: Vec<(String, Token)>,
+}
+
+impl SimulationLog {
+    /// Converts a log of an execution result, emitted by a call at `depth` if it is known.
+    pub fn new(log: &Log, depth: Option<usize>) -> Self {
+        SimulationLog {
+            address: log.address,
+            topics: log.topics().to_vec(),
+            data: log.data.data.clone(),
+            depth,
+            decoded: None,
+        }
+    }
+
+    /// Decodes the log against the first of the given events whose signature and parameters
+    /// match. Anonymous events are never matched, as their signature isn't part of the log.
+    pub fn decode(&mut self, events: &[Event]) {
+        let Some(topic0) = self.topics.first() else {
+            return;
+        };
+        let raw_log = RawLog {
+            topics: self
+                .topics
+                .iter()
+                .map(|topic| H256::from_slice(topic.as_slice()))
+                .collect(),
+            data: self.data.to_vec(),
+        };
+        self.decoded = events
+            .iter()
+            .filter(|event| !event.anonymous && event.signature().as_bytes() == topic0.as_slice())
+            .find_map(|event| {
+                let log = event.parse_log(raw_log.clone()).ok()?;
+                Some(DecodedLog {
+                    name: event.name.clone(),
+                    params: log
+                        .params
+                        .into_iter()
+                        .map(|param| (param.name, param.value))
+                        .collect(),
+                })
+            });
+    }
+}
+
+/// Records the logs emitted during execution together with the depth of the emitting call, and
+/// forwards all hooks to an inner inspector.
+///
+/// Logs of calls that revert are discarded, so the recorded logs match the ones of the
+/// transaction's receipt.
+#[derive(Debug, Default)]
+pub struct LogInspector<I> {
+    pub inner: I,
+    logs: Vec<SimulationLog>,
+    /// Number of logs recorded when each currently executing call started
+    checkpoints: Vec<usize>,
+}
+
+impl<I> LogInspector<I> {
+    pub fn new(inner: I) -> Self {
+        Self { inner, logs: Vec::new(), checkpoints: Vec::new() }
+    }
+
+    /// Returns the inner inspector and the recorded logs.
+    pub fn into_parts(self) -> (I, Vec<SimulationLog>) {
+        (self.inner, self.logs)
+    }
+
+    fn enter_frame(&mut self) {
+        self.checkpoints.push(self.logs.len());
+    }
+
+    fn exit_frame(&mut self, success: bool) {
+        if let Some(checkpoint) = self.checkpoints.pop() {
+            if !success {
+                self.logs.truncate(checkpoint);
+            }
+        }
+    }
+}
+
+impl<DB: Database, I: Inspector<DB>> Inspector<DB> for LogInspector<I> {
+    fn initialize_interp(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.inner
+            .initialize_interp(interp, context);
+    }
+
+    fn step(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.inner.step(interp, context);
+    }
+
+    fn step_end(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>) {
+        self.inner.step_end(interp, context);
+    }
+
+    fn log(&mut self, interp: &mut Interpreter, context: &mut EvmContext<DB>, log: &Log) {
+        let depth = self.checkpoints.len().saturating_sub(1);
+        self.logs
+            .push(SimulationLog::new(log, Some(depth)));
+        self.inner.log(interp, context, log);
+    }
+
+    fn call(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CallInputs,
+    ) -> Option<CallOutcome> {
+        self.enter_frame();
+        self.inner.call(context, inputs)
+    }
+
+    fn call_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &CallInputs,
+        outcome: CallOutcome,
+    ) -> CallOutcome {
+        let outcome = self
+            .inner
+            .call_end(context, inputs, outcome);
+        self.exit_frame(outcome.result.is_ok());
+        outcome
+    }
+
+    fn create(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut CreateInputs,
+    ) -> Option<CreateOutcome> {
+        self.enter_frame();
+        self.inner.create(context, inputs)
+    }
+
+    fn create_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &CreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        let outcome = self
+            .inner
+            .create_end(context, inputs, outcome);
+        self.exit_frame(outcome.result.is_ok());
+        outcome
+    }
+
+    fn eofcreate(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &mut EOFCreateInputs,
+    ) -> Option<CreateOutcome> {
+        self.enter_frame();
+        self.inner.eofcreate(context, inputs)
+    }
+
+    fn eofcreate_end(
+        &mut self,
+        context: &mut EvmContext<DB>,
+        inputs: &EOFCreateInputs,
+        outcome: CreateOutcome,
+    ) -> CreateOutcome {
+        let outcome = self
+            .inner
+            .eofcreate_end(context, inputs, outcome);
+        self.exit_frame(outcome.result.is_ok());
+        outcome
+    }
+
+    fn selfdestruct(&mut self, contract: Address, target: Address, value: rU256) {
+        self.inner
+            .selfdestruct(contract, target, value);
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ethers::{
+        abi::{encode, parse_abi},
+        types::{H160, U256},
+    };
+
+    #[test]
+    fn test_decode_log() {
+        let abi = parse_abi(&[
+            "event Approval(address indexed owner, address indexed spender, uint256 value)",
+            "event Transfer(address indexed from, address indexed to, uint256 value)",
+        ])
+        .unwrap();
+        let events: Vec<Event> = abi.events().cloned().collect();
+        let transfer = abi.event("Transfer").unwrap();
+        let from = H160::repeat_byte(0x11);
+        let to = H160::repeat_byte(0x22);
+        let mut log = SimulationLog {
+            address: Address::repeat_byte(0x33),
+            topics: vec![
+                B256::from(transfer.signature().0),
+                B256::left_padding_from(from.as_bytes()),
+                B256::left_padding_from(to.as_bytes()),
+            ],
+            data: encode(&[Token::Uint(U256::from(100))]).into(),
+            depth: Some(1),
+            decoded: None,
+        };
+
+        log.decode(&events);
+
+        assert_eq!(
+            log.decoded,
+            Some(DecodedLog {
+                name: "Transfer".to_string(),
+                params: vec![
+                    ("from".to_string(), Token::Address(from)),
+                    ("to".to_string(), Token::Address(to)),
+                    ("value".to_string(), Token::Uint(U256::from(100))),
+                ],
+            })
+        );
+    }
+
+    #[test]
+    fn test_logs_of_reverted_calls_are_discarded() {
+        let mut inspector = LogInspector::new(());
+        let log = |logs: &mut LogInspector<()>, byte: u8| {
+            logs.logs.push(SimulationLog {
+                address: Address::repeat_byte(byte),
+                topics: Vec::new(),
+                data: Bytes::new(),
+                depth: Some(logs.checkpoints.len() - 1),
+                decoded: None,
+            })
+        };
+
+        inspector.enter_frame();
+        log(&mut inspector, 1);
+        inspector.enter_frame();
+        log(&mut inspector, 2);
+        inspector.exit_frame(false);
+        inspector.enter_frame();
+        log(&mut inspector, 3);
+        inspector.exit_frame(true);
+        inspector.exit_frame(true);
+
+        let (_, logs) = inspector.into_parts();
+        assert_eq!(
+            logs.iter()
+                .map(|log| (log.address, log.depth))
+                .collect::<Vec<_>>(),
+            vec![(Address::repeat_byte(1), Some(0)), (Address::repeat_byte(3), Some(1))]
+        );
+    }
+}
@@ -1,7 +1,11 @@
 pub mod account_storage;
 pub mod engine_db_interface;
+pub mod evm_config;
+pub mod logs;
 pub mod simulation;
 pub mod simulation_db;
+#[cfg(test)]
+pub(crate) mod test_utils;
 pub mod traces;
 pub mod tycho_db;
 pub mod tycho_models;
@@ -1,28 +1,30 @@
 use std::{collections::HashMap, default::Default};
 
 use ethers::types::{Bytes, U256};
-use foundry_config::{Chain, Config};
 use foundry_evm::traces::{SparsedTraceArena, TraceKind};
 use revm::{
-    db::DatabaseRef,
+    db::{CacheDB, DatabaseRef},
     inspector_handle_register,
-    interpreter::{return_ok, InstructionResult},
+    interpreter::{gas::validate_initial_tx_gas, return_ok, InstructionResult},
     primitives::{
         alloy_primitives, bytes, Address, BlockEnv, EVMError, EVMResult, EvmState, ExecutionResult,
-        Output, ResultAndState, SpecId, TransactTo, TxEnv, U256 as rU256,
+        Output, ResultAndState, TransactTo, TxEnv, U256 as rU256,
     },
-    Evm,
+    DatabaseCommit, Evm,
 };
 use revm_inspectors::tracing::{TracingInspector, TracingInspectorConfig};
 use strum_macros::Display;
-use tokio::runtime::Runtime;
-use tracing::debug;
+use tracing::{debug, warn};
 
-use crate::evm::simulation_db::OverriddenSimulationDB;
+use crate::evm::{
+    evm_config::EvmConfig,
+    logs::{LogInspector, SimulationLog},
+    simulation_db::{AccountOverride, OverriddenSimulationDB},
+};
 
 use super::{
     account_storage::StateUpdate,
-    traces::{handle_traces, TraceResult},
+    traces::{handle_traces, CallTrace, TraceResult},
 };
 
 /// An error representing any transaction simulation result other than successful execution
@@ -34,8 +36,9 @@ pub enum SimulationEngineError {
     /// Gas limit has been reached. Retrying while increasing gas limit or waiting for a gas price
     /// reduction may help.
     OutOfGas(String, String),
-    /// Simulation didn't succeed; likely not related to network or gas, so retrying won't help
-    TransactionError { data: String, gas_used: Option<u64> },
+    /// Simulation didn't succeed; likely not related to network or gas, so retrying won't help.
+    /// If the engine traces, `trace` holds the call tree of the failed transaction.
+    TransactionError { data: String, gas_used: Option<u64>, trace: Option<Box<CallTrace>> },
 }
 
 /// A result of a successful transaction simulation
@@ -47,6 +50,55 @@ pub struct SimulationResult {
     pub state_updates: HashMap<Address, StateUpdate>,
     /// Gas used by the transaction (already reduced by the refunded gas)
     pub gas_used: u64,
+    /// The call tree of the transaction, recorded if the engine traces
+    pub trace: Option<CallTrace>,
+    /// Logs emitted by the transaction, in emission order
+    pub logs: Vec<SimulationLog>,
+}
+
+/// Results of a bundle of transactions simulated one after another
+#[derive(Debug, Clone, Default)]
+pub struct BundleSimulationResult {
+    /// Results of the executed transactions, in execution order. Transactions after a reverted
+    /// one are missing if the bundle stops on reverts.
+    pub results: Vec<Result<SimulationResult, SimulationEngineError>>,
+    /// Changes of the accounts touched by the executed transactions, all together
+    pub account_diffs: HashMap<Address, BundleAccountDiff>,
+}
+
+/// Changes of an account caused by the transactions of a bundle
+#[derive(Debug, Clone, Default, PartialEq, Eq)]
+pub struct BundleAccountDiff {
+    /// Balance after the last transaction touching the account
+    pub balance: Option<rU256>,
+    /// Nonce after the last transaction touching the account
+    pub nonce: Option<u64>,
+    /// Runtime code, if the account was created by the bundle
+    pub code: Option<revm::primitives::Bytes>,
+    /// Storage slots changed by the bundle and their new values
+    pub storage: Option<HashMap<rU256, rU256>>,
+}
+
+/// Gas needed by a transaction, see `SimulationEngine::estimate_gas`
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct GasEstimate {
+    /// The lowest gas limit the transaction succeeds with
+    pub gas_limit: u64,
+    /// Gas used by the transaction with that limit, after refunds
+    pub gas_used: u64,
+    /// Gas charged before execution: the base cost of a transaction and the cost of its calldata
+    pub intrinsic_gas: u64,
+    /// Gas used by the execution itself, after refunds
+    pub execution_gas: u64,
+}
+
+/// What a bundle simulation does once one of its transactions reverts
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum OnRevert {
+    /// Skip the remaining transactions
+    Stop,
+    /// Execute the remaining transactions on top of the reverted one's changes
+    Continue,
 }
 
 /// Simulation engine
@@ -56,7 +108,12 @@ where
     D: Clone,
 {
     pub state: D,
+    /// Whether to record the call tree of simulations in `SimulationResult::trace`
     pub trace: bool,
+    /// Whether to print the execution trace of recorded simulations
+    pub print_traces: bool,
+    /// Block environment and hardfork of the simulations
+    pub config: EvmConfig,
 }
 
 impl<D: DatabaseRef + std::clone::Clone> SimulationEngine<D>
@@ -68,9 +125,22 @@ where
     /// # Arguments
     ///
     /// * `state` - Database reference to be used for simulation
-    /// * `trace` - Whether to print the entire execution trace
+    /// * `trace` - Whether to record the call tree of simulations in `SimulationResult::trace` and
+    ///   print the execution trace. Use `with_print_traces` to only record it.
     pub fn new(state: D, trace: bool) -> Self {
-        Self { state, trace }
+        Self { state, trace, print_traces: trace, config: EvmConfig::default() }
+    }
+
+    /// Sets whether the execution trace of traced simulations is printed.
+    pub fn with_print_traces(mut self, print_traces: bool) -> Self {
+        self.print_traces = print_traces;
+        self
+    }
+
+    /// Sets the block environment and hardfork of the simulations, see `EvmConfig`.
+    pub fn with_config(mut self, config: EvmConfig) -> Self {
+        self.config = config;
+        self
     }
 
     /// Simulate a transaction
@@ -80,6 +150,141 @@ where
         &self,
         params: &SimulationParameters,
     ) -> Result<SimulationResult, SimulationEngineError> {
+        let (evm_result, trace, logs) = self.transact(&self.state, params, self.trace);
+        with_trace(interpret_evm_result(evm_result), trace, logs)
+    }
+
+    /// Simulate transactions one after another, each seeing the state changes of the previous
+    /// ones
+    ///
+    /// Changes of reverted transactions, such as the caller's nonce, are carried over as well, as
+    /// they would be on chain. Storage slots are only carried over if a transaction changes them,
+    /// so slots set by its `overrides` or `account_overrides` are not seen by later transactions
+    /// unless it writes to them. The balance, nonce and code of the accounts a transaction
+    /// touches are carried over as the transaction leaves them though, including values set by
+    /// its `account_overrides`.
+    ///
+    /// # Arguments
+    ///
+    /// * `transactions` - The transactions, in execution order
+    /// * `on_revert` - Whether to execute the remaining transactions after one reverts
+    ///
+    /// # Errors
+    ///
+    /// Returns a `SimulationEngineError::StorageError` if the state can't be read. Failures of
+    /// single transactions are returned in the bundle result instead.
+    pub fn simulate_bundle(
+        &self,
+        transactions: Vec<SimulationParameters>,
+        on_revert: OnRevert,
+    ) -> Result<BundleSimulationResult, SimulationEngineError> {
+        let mut db = CacheDB::new(&self.state);
+        let mut bundle_result = BundleSimulationResult::default();
+        for params in &transactions {
+            let (evm_result, trace, logs) = self.transact(&db, params, self.trace);
+            if let Ok(ResultAndState { state, .. }) = &evm_result {
+                merge_account_diffs(&mut bundle_result.account_diffs, state);
+                let mut changes = state.clone();
+                for account in changes.values_mut() {
+                    // slots that were only read may hold overridden values
+                    account
+                        .storage
+                        .retain(|_, slot| slot.is_changed());
+                }
+                db.commit(changes);
+            }
+
+            let result = match interpret_evm_result(evm_result) {
+                Err(err @ SimulationEngineError::StorageError(_)) => return Err(err),
+                result => with_trace(result, trace, logs),
+            };
+            let reverted = result.is_err();
+            bundle_result.results.push(result);
+            if reverted && on_revert == OnRevert::Stop {
+                break;
+            }
+        }
+        Ok(bundle_result)
+    }
+
+    /// Estimate the lowest gas limit the transaction succeeds with, like `eth_estimateGas`
+    ///
+    /// The limit is found by binary search between the gas spent before refunds and the
+    /// transaction's gas limit (or the default gas limit of the config), so gas withheld from
+    /// subcalls by the 63/64 rule and refunds only granted at the end of the transaction are
+    /// accounted for.
+    ///
+    /// # Errors
+    ///
+    /// Returns the error of the simulation with the maximum gas limit if the transaction fails
+    /// with it.
+    pub fn estimate_gas(
+        &self,
+        params: &SimulationParameters,
+    ) -> Result<GasEstimate, SimulationEngineError> {
+        let max_gas_limit = params
+            .gas_limit
+            .unwrap_or(self.config.default_gas_limit);
+        let (refunded, mut gas_used) = self.gas_used_with_limit(params, max_gas_limit)?;
+        // Execution needs the gas spent before refunds, so any lower limit fails
+        let mut lower = (gas_used + refunded).saturating_sub(1);
+        let mut upper = max_gas_limit;
+        while upper - lower > 1 {
+            let mid = lower + (upper - lower) / 2;
+            match self.gas_used_with_limit(params, mid) {
+                Ok((_, used)) => {
+                    upper = mid;
+                    gas_used = used;
+                }
+                Err(err @ SimulationEngineError::StorageError(_)) => return Err(err),
+                Err(_) => lower = mid,
+            }
+        }
+
+        let intrinsic_gas = validate_initial_tx_gas(
+            self.config
+                .spec_id_at(params.block_number),
+            &params.data,
+            params.to == Address::ZERO,
+            &[],
+            0,
+        );
+        Ok(GasEstimate {
+            gas_limit: upper,
+            gas_used,
+            intrinsic_gas,
+            execution_gas: gas_used.saturating_sub(intrinsic_gas),
+        })
+    }
+
+    /// Returns the gas refunded and the gas used after refunds if the transaction succeeds with
+    /// the given gas limit.
+    fn gas_used_with_limit(
+        &self,
+        params: &SimulationParameters,
+        gas_limit: u64,
+    ) -> Result<(u64, u64), SimulationEngineError> {
+        let params = SimulationParameters { gas_limit: Some(gas_limit), ..params.clone() };
+        let (evm_result, _, _) = self.transact(&self.state, &params, false);
+        match evm_result {
+            Ok(ResultAndState {
+                result: ExecutionResult::Success { gas_used, gas_refunded, .. },
+                ..
+            }) => Ok((gas_refunded, gas_used)),
+            evm_result => interpret_evm_result(evm_result).map(|result| (0, result.gas_used)),
+        }
+    }
+
+    /// Execute a transaction against `db` without committing its changes
+    fn transact<DB: DatabaseRef>(
+        &self,
+        db: &DB,
+        params: &SimulationParameters,
+        trace: bool,
+    ) -> (EVMResult<DB::Error>, Option<CallTrace>, Vec<SimulationLog>)
+    where
+        DB::Error: std::fmt::Debug,
+    {
         // We allocate a new EVM so we can work with a simple referenced DB instead of a fully
         // concurrently save shared reference and write locked object. Note that concurrently
         // calling this method is therefore not possible.
@@ -88,18 +293,20 @@ where
         // struct outlive this scope.
 
         // We protect the state from being consumed.
-        let db_ref = OverriddenSimulationDB {
-            inner_db: &self.state,
-            overrides: &params
-                .revm_overrides()
-                .unwrap_or_default(),
-        };
+        let overrides = params
+            .revm_overrides()
+            .unwrap_or_default();
+        let mut db_ref = OverriddenSimulationDB::new(db, &overrides);
+        if let Some(account_overrides) = &params.account_overrides {
+            db_ref = db_ref.with_account_overrides(account_overrides);
+        }
 
         let tx_env = TxEnv {
             caller: params.revm_caller(),
             gas_limit: params
                 .revm_gas_limit()
-                .unwrap_or(8_000_000),
+                .unwrap_or(self.config.default_gas_limit),
+            gas_price: self.config.gas_price,
             transact_to: params.revm_to(),
             value: params.revm_value(),
             data: params.revm_data(),
@@ -109,20 +316,30 @@ where
         let block_env = BlockEnv {
             number: params.revm_block_number(),
             timestamp: params.revm_timestamp(),
+            coinbase: self.config.coinbase,
+            gas_limit: self.config.block_gas_limit,
+            basefee: self.config.base_fee,
+            prevrandao: Some(self.config.prevrandao),
             ..Default::default()
         };
 
+        let chain_id = self.config.chain_id;
         let default_builder = Evm::builder()
-            .with_spec_id(SpecId::CANCUN)
+            .with_spec_id(
+                self.config
+                    .spec_id_at(params.block_number),
+            )
+            .modify_cfg_env(|cfg| cfg.chain_id = chain_id)
             .with_ref_db(db_ref)
             .with_block_env(block_env)
             .with_tx_env(tx_env);
 
-        let evm_result = if self.trace {
-            let mut tracer = TracingInspector::new(TracingInspectorConfig::default());
+        if trace {
+            let mut inspector =
+                LogInspector::new(TracingInspector::new(TracingInspectorConfig::default()));
             let res = {
                 let mut vm = default_builder
-                    .with_external_context(&mut tracer)
+                    .with_external_context(&mut inspector)
                     .append_handler_register(inspector_handle_register)
                     .build();
 
@@ -130,20 +347,31 @@ where
                 vm.transact()
             };
 
-            if let Ok(result) = res.as_ref() {
-                Self::print_traces(tracer, result)
+            let trace = CallTrace::from_arena(inspector.inner.traces());
+            let (tracer, logs) = inspector.into_parts();
+            if self.print_traces {
+                if let Ok(result) = res.as_ref() {
+                    Self::print_traces(tracer, result)
+                }
             }
 
-            res
+            (res, trace, logs)
         } else {
             let mut vm = default_builder.build();
 
             debug!("Starting simulation with tx parameters: {:#?} {:#?}", vm.tx(), vm.block());
+            let res = vm.transact();
 
-            vm.transact()
-        };
-
-        interpret_evm_result(evm_result)
+            // Without an inspector the depths of the emitting calls are unknown
+            let logs = match &res {
+                Ok(ResultAndState { result: ExecutionResult::Success { logs, .. }, .. }) => logs
+                    .iter()
+                    .map(|log| SimulationLog::new(log, None))
+                    .collect(),
+                _ => Vec::new(),
+            };
+            (res, None, logs)
+        }
     }
 
     fn print_traces(tracer: TracingInspector, res: &ResultAndState) {
@@ -173,14 +401,11 @@ where
             gas_used,
         };
 
-        let runtime = tokio::runtime::Handle::try_current()
-            .is_err()
-            .then(|| Runtime::new().unwrap())
-            .unwrap();
-
-        runtime
-            .block_on(handle_traces(trace_res, &Config::default(), Some(Chain::default()), true))
-            .expect("failure handling traces");
+        // Decoding the traces doesn't wait on any IO, so the future is driven on the current
+        // thread, whether it is inside a tokio runtime or not.
+        if let Err(err) = futures::executor::block_on(handle_traces(trace_res)) {
+            warn!("Failed to print traces: {err}");
+        }
     }
 }
 
@@ -204,19 +429,21 @@ fn interpret_evm_result<DBError: std::fmt::Debug>(
 ) -> Result<SimulationResult, SimulationEngineError> {
     match evm_result {
         Ok(result_and_state) => match result_and_state.result {
-            ExecutionResult::Success { gas_used, gas_refunded, output, .. } => {
-                Ok(interpret_evm_success(gas_used, gas_refunded, output, result_and_state.state))
+            ExecutionResult::Success { gas_used, output, .. } => {
+                Ok(interpret_evm_success(gas_used, output, result_and_state.state))
             }
             ExecutionResult::Revert { output, gas_used } => {
                 Err(SimulationEngineError::TransactionError {
                     data: format!("0x{}", hex::encode(output)),
                     gas_used: Some(gas_used),
+                    trace: None,
                 })
             }
             ExecutionResult::Halt { reason, gas_used } => {
                 Err(SimulationEngineError::TransactionError {
                     data: format!("{:?}", reason),
                     gas_used: Some(gas_used),
+                    trace: None,
                 })
             }
         },
@@ -224,6 +451,7 @@ fn interpret_evm_result<DBError: std::fmt::Debug>(
             EVMError::Transaction(invalid_tx) => Err(SimulationEngineError::TransactionError {
                 data: format!("EVM error: {invalid_tx:?}"),
                 gas_used: None,
+                trace: None,
             }),
             EVMError::Database(db_error) => {
                 Err(SimulationEngineError::StorageError(format!("Storage error: {:?}", db_error)))
@@ -231,72 +459,127 @@ fn interpret_evm_result<DBError: std::fmt::Debug>(
             EVMError::Custom(err) => Err(SimulationEngineError::TransactionError {
                 data: format!("Unexpected error {}", err),
                 gas_used: None,
+                trace: None,
             }),
             EVMError::Header(err) => Err(SimulationEngineError::TransactionError {
                 data: format!("Unexpected error {}", err),
                 gas_used: None,
+                trace: None,
             }),
             EVMError::Precompile(err) => Err(SimulationEngineError::TransactionError {
                 data: format!("Unexpected error {}", err),
                 gas_used: None,
+                trace: None,
             }),
         },
     }
 }
 
-// Helper function to extract some details from a successful transaction execution
-fn interpret_evm_success(
-    gas_used: u64,
-    gas_refunded: u64,
-    output: Output,
-    state: EvmState,
-) -> SimulationResult {
+/// Adds the trace and logs recorded during execution to the interpreted result. The trace of a
+/// failed transaction is attached to its `TransactionError`.
+fn with_trace(
+    result: Result<SimulationResult, SimulationEngineError>,
+    trace: Option<CallTrace>,
+    logs: Vec<SimulationLog>,
+) -> Result<SimulationResult, SimulationEngineError> {
+    match result {
+        Ok(result) => Ok(SimulationResult { trace, logs, ..result }),
+        Err(SimulationEngineError::TransactionError { data, gas_used, .. }) => {
+            Err(SimulationEngineError::TransactionError {
+                data,
+                gas_used,
+                trace: trace.map(Box::new),
+            })
+        }
+        Err(err) => Err(err),
+    }
+}
+
+// Helper function to extract some details from a successful transaction execution. revm already
+// deducts the refunded gas from `gas_used`.
+fn interpret_evm_success(gas_used: u64, output: Output, state: EvmState) -> SimulationResult {
     SimulationResult {
         result: output.into_data().into(),
-        state_updates: {
-            // For each account mentioned in state updates in REVM output, we will have
-            // one record in our hashmap. Such record contains *new* values of account's
-            // state. This record's optional `storage` field will contain
-            // account's storage changes (as a hashmap from slot index to slot value),
-            // unless REVM output doesn't contain any storage for this account, in which case
-            // we set this field to None. If REVM did return storage, we return one record
-            // per *modified* slot (sometimes REVM returns a storage record for an account
-            // even if the slots are not modified).
-            let mut account_updates: HashMap<Address, StateUpdate> = HashMap::new();
-            for (address, account) in state {
-                account_updates.insert(
-                    address,
-                    StateUpdate {
-                        // revm doesn't say if the balance was actually changed
-                        balance: Some(account.info.balance),
-                        // revm doesn't say if the code was actually changed
-                        storage: {
-                            if account.storage.is_empty() {
-                                None
-                            } else {
-                                let mut slot_updates: HashMap<rU256, rU256> = HashMap::new();
-                                for (index, slot) in account.storage {
-                                    if slot.is_changed() {
-                                        slot_updates.insert(index, slot.present_value);
-                                    }
-                                }
-                                if slot_updates.is_empty() {
-                                    None
-                                } else {
-                                    Some(slot_updates)
-                                }
+        state_updates: state_updates(state),
+        gas_used,
+        trace: None,
+        logs: Vec::new(),
+    }
+}
+
+/// Adds the changes of the accounts a transaction touched to the diffs of earlier transactions,
+/// newer values winning
+fn merge_account_diffs(diffs: &mut HashMap<Address, BundleAccountDiff>, state: &EvmState) {
+    for (address, account) in state {
+        if !account.is_touched() {
+            continue;
+        }
+        let diff = diffs.entry(*address).or_default();
+        diff.balance = Some(account.info.balance);
+        diff.nonce = Some(account.info.nonce);
+        if account.is_created() {
+            diff.code = account
+                .info
+                .code
+                .as_ref()
+                .map(|code| code.original_bytes());
+        }
+        let changed_slots: HashMap<rU256, rU256> = account
+            .storage
+            .iter()
+            .filter(|(_, slot)| slot.is_changed())
+            .map(|(index, slot)| (*index, slot.present_value))
+            .collect();
+        if !changed_slots.is_empty() {
+            diff.storage
+                .get_or_insert_with(HashMap::new)
+                .extend(changed_slots);
+        }
+    }
+}
+
+/// Converts the accounts touched by a transaction into state updates
+fn state_updates(state: EvmState) -> HashMap<Address, StateUpdate> {
+    // For each account mentioned in state updates in REVM output, we will have
+    // one record in our hashmap. Such record contains *new* values of account's
+    // state. This record's optional `storage` field will contain
+    // account's storage changes (as a hashmap from slot index to slot value),
+    // unless REVM output doesn't contain any storage for this account, in which case
+    // we set this field to None. If REVM did return storage, we return one record
+    // per *modified* slot (sometimes REVM returns a storage record for an account
+    // even if the slots are not modified).
+    let mut account_updates: HashMap<Address, StateUpdate> = HashMap::new();
+    for (address, account) in state {
+        account_updates.insert(
+            address,
+            StateUpdate {
+                // revm doesn't say if the balance was actually changed
+                balance: Some(account.info.balance),
+                // revm doesn't say if the code was actually changed
+                storage: {
+                    if account.storage.is_empty() {
+                        None
+                    } else {
+                        let mut slot_updates: HashMap<rU256, rU256> = HashMap::new();
+                        for (index, slot) in account.storage {
+                            if slot.is_changed() {
+                                slot_updates.insert(index, slot.present_value);
                             }
-                        },
-                    },
-                );
-            }
-            account_updates
-        },
-        gas_used: gas_used - gas_refunded,
+                        }
+                        if slot_updates.is_empty() {
+                            None
+                        } else {
+                            Some(slot_updates)
+                        }
+                    }
+                },
+            },
+        );
     }
+    account_updates
 }
 
-#[derive(Debug)]
+#[derive(Debug, Clone)]
 /// Data needed to invoke a transaction simulation
 pub struct SimulationParameters {
     /// Address of the sending account
@@ -310,6 +593,9 @@ pub struct SimulationParameters {
     /// EVM state overrides.
     /// Will be merged with existing state. Will take effect only for current simulation.
     pub overrides: Option<HashMap<Address, HashMap<U256, U256>>>,
+    /// Overrides of balances, nonces, code and storage of accounts, as in `eth_call`. Slots
+    /// in `overrides` take precedence over the storage overridden here.
+    pub account_overrides: Option<HashMap<Address, AccountOverride>>,
     /// Limit of gas to be used by the transaction
     pub gas_limit: Option<u64>,
     /// The block number to be used by the transaction. This is independent of the states block.
@@ -381,12 +667,373 @@ mod tests {
         types::U256,
     };
     use revm::primitives::{
-        bytes, hex, Account, AccountInfo, AccountStatus, Address, Bytecode, Bytes,
+        bytes, hex, keccak256, Account, AccountInfo, AccountStatus, Address, Bytecode, Bytes,
         EvmState as rState, EvmStorageSlot, ExecutionResult, HaltReason, InvalidTransaction,
         OutOfGasError, Output, ResultAndState, SuccessReason, B256,
     };
+    use rstest::rstest;
+
+    use crate::{
+        evm::{
+            engine_db_interface::EngineDatabaseInterface,
+            simulation_db::SimulationDB,
+            test_utils::{call_params, db_with_contract, deploy, CALLER, CONTRACT},
+            tycho_models::Chain,
+        },
+        protocol::vm::utils::load_erc20_bytecode,
+    };
+
+    #[test]
+    fn test_simulate_with_evm_config() {
+        // returns CHAINID, BASEFEE and COINBASE as three words
+        let db = db_with_contract(&hex!("46600052486020524160405260606000f3"));
+        let coinbase = Address::from_str("0x0000000000000000000000000000000000009abc").unwrap();
+        let engine = SimulationEngine::new(db, false).with_config(
+            EvmConfig::default()
+                .with_chain(Chain::ZkSync)
+                .with_base_fee(rU256::from(7))
+                .with_gas_price(rU256::from(7))
+                .with_coinbase(coinbase),
+        );
+
+        let result = engine
+            .simulate(&call_params(CONTRACT))
+            .unwrap();
+
+        assert_eq!(rU256::from_be_slice(&result.result[..32]), rU256::from(324));
+        assert_eq!(rU256::from_be_slice(&result.result[32..64]), rU256::from(7));
+        assert_eq!(Address::from_slice(&result.result[76..96]), coinbase);
+    }
+
+    #[test]
+    fn test_simulate_returns_call_trace() {
+        // returns CHAINID, BASEFEE and COINBASE as three words
+        let db = db_with_contract(&hex!("46600052486020524160405260606000f3"));
+        let params = SimulationParameters {
+            data: ethers::types::Bytes::from_static(&[0xab]),
+            ..call_params(CONTRACT)
+        };
+
+        let traced = SimulationEngine::new(db.clone(), true)
+            .with_print_traces(false)
+            .simulate(&params)
+            .unwrap();
+        let untraced = SimulationEngine::new(db, false)
+            .simulate(&params)
+            .unwrap();
+
+        let trace = traced.trace.expect("trace is recorded");
+        assert_eq!(trace.from, CALLER);
+        assert_eq!(trace.to, CONTRACT);
+        assert_eq!(trace.input, Bytes::from_static(&[0xab]));
+        assert_eq!(trace.output, traced.result);
+        assert_eq!(trace.depth, 0);
+        assert!(trace.success);
+        assert!(trace.calls.is_empty());
+        assert!(untraced.trace.is_none());
+    }
+
+    #[test]
+    fn test_simulate_attaches_trace_to_revert() {
+        // reverts with Error("no")
+        let db = db_with_contract(&hex!(
+            "6308c379a060e01b60005260206004526002602452616e6f60f01b60445260646000fd"
+        ));
+
+        let err = SimulationEngine::new(db, true)
+            .with_print_traces(false)
+            .simulate(&call_params(CONTRACT))
+            .unwrap_err();
+
+        let SimulationEngineError::TransactionError { trace: Some(trace), .. } = err else {
+            panic!("Expected a transaction error with a trace, got {err:?}");
+        };
+        assert!(!trace.success);
+        assert_eq!(trace.revert_reason, Some("no".to_string()));
+    }
+
+    #[tokio::test]
+    async fn test_print_traces_inside_runtime() {
+        let db = db_with_contract(&hex!("46600052486020524160405260606000f3"));
+
+        let result = SimulationEngine::new(db, true).simulate(&call_params(CONTRACT));
+
+        assert!(result.unwrap().trace.is_some());
+    }
+
+    #[test]
+    fn test_simulate_returns_logs() {
+        // emits LOG1 with topic 0xaa and no data
+        let db = db_with_contract(&hex!("60aa60006000a100"));
+        let log = |depth| SimulationLog {
+            address: CONTRACT,
+            topics: vec![B256::with_last_byte(0xaa)],
+            data: Bytes::new(),
+            depth,
+            decoded: None,
+        };
 
-    use crate::evm::{engine_db_interface::EngineDatabaseInterface, simulation_db::SimulationDB};
+        let untraced = SimulationEngine::new(db.clone(), false)
+            .simulate(&call_params(CONTRACT))
+            .unwrap();
+        let traced = SimulationEngine::new(db, true)
+            .with_print_traces(false)
+            .simulate(&call_params(CONTRACT))
+            .unwrap();
+
+        assert_eq!(untraced.logs, vec![log(None)]);
+        assert_eq!(traced.logs, vec![log(Some(0))]);
+    }
+
+    #[test]
+    fn test_simulate_bundle_carries_over_state() {
+        // increments the counter in slot 0 and returns its new value
+        let db = db_with_contract(&hex!("6000546001018060005560005260206000f3"));
+        let reverting = Address::from_str("0x0000000000000000000000000000000000004321").unwrap();
+        deploy(&db, reverting, &hex!("60006000fd"));
+        let engine = SimulationEngine::new(db, false);
+        let bundle = || vec![call_params(CONTRACT), call_params(reverting), call_params(CONTRACT)];
+
+        let continued = engine
+            .simulate_bundle(bundle(), OnRevert::Continue)
+            .unwrap();
+        let stopped = engine
+            .simulate_bundle(bundle(), OnRevert::Stop)
+            .unwrap();
+
+        let counter_values: Vec<_> = continued
+            .results
+            .iter()
+            .map(|result| {
+                result
+                    .as_ref()
+                    .map(|result| rU256::from_be_slice(&result.result))
+                    .ok()
+            })
+            .collect();
+        assert_eq!(counter_values, vec![Some(rU256::from(1)), None, Some(rU256::from(2))]);
+        assert_eq!(
+            continued.account_diffs[&CONTRACT].storage,
+            Some(HashMap::from([(rU256::ZERO, rU256::from(2))]))
+        );
+        // the reverted transaction increments the nonce as well
+        assert_eq!(continued.account_diffs[&CALLER].nonce, Some(3));
+        assert_eq!(stopped.results.len(), 2);
+        assert!(stopped.results[1].is_err());
+        assert_eq!(
+            stopped.account_diffs[&CONTRACT].storage,
+            Some(HashMap::from([(rU256::ZERO, rU256::from(1))]))
+        );
+    }
+
+    #[test]
+    fn test_simulate_bundle_approve_and_transfer_from() {
+        let erc20 = BaseContract::from(
+            parse_abi(&[
+                "function approve(address spender, uint256 amount) returns (bool)",
+                "function transferFrom(address from, address to, uint256 amount) returns (bool)",
+            ])
+            .unwrap(),
+        );
+        let spender = Address::from_str("0x0000000000000000000000000000000000000aaa").unwrap();
+        let recipient = Address::from_str("0x0000000000000000000000000000000000000bbb").unwrap();
+        // the token stores balances in mapping 0 and allowances in mapping 1
+        let mapping_slot =
+            |key: Address, slot: B256| keccak256([key.into_word().0, slot.0].concat());
+        let balance_slot = |owner| rU256::from_be_bytes(mapping_slot(owner, B256::ZERO).0);
+        let allowance_slot = rU256::from_be_bytes(
+            mapping_slot(spender, mapping_slot(CALLER, B256::with_last_byte(1))).0,
+        );
+        let code = load_erc20_bytecode().unwrap();
+        let db = db_with_contract(&[]);
+        db.init_account(
+            CONTRACT,
+            AccountInfo { code_hash: code.hash_slow(), code: Some(code), ..Default::default() },
+            Some(HashMap::from([(balance_slot(CALLER), rU256::from(100))])),
+            true,
+        );
+        db.init_account(spender, AccountInfo::default(), None, true);
+        db.init_account(recipient, AccountInfo::default(), None, true);
+        let h160 = |address: Address| ethers::types::Address::from(address.into_array());
+        let approve = SimulationParameters {
+            data: erc20
+                .encode("approve", (h160(spender), U256::from(100)))
+                .unwrap(),
+            ..call_params(CONTRACT)
+        };
+        let transfer_from = SimulationParameters {
+            caller: spender,
+            data: erc20
+                .encode("transferFrom", (h160(CALLER), h160(recipient), U256::from(60)))
+                .unwrap(),
+            ..call_params(CONTRACT)
+        };
+
+        let result = SimulationEngine::new(db, false)
+            .simulate_bundle(
+                vec![approve, transfer_from.clone(), transfer_from],
+                OnRevert::Continue,
+            )
+            .unwrap();
+
+        assert!(result.results[0].is_ok());
+        assert!(result.results[1].is_ok());
+        // the second transfer exceeds the remaining allowance
+        assert!(result.results[2].is_err());
+        let token_diff = &result.account_diffs[&CONTRACT];
+        assert_eq!(
+            token_diff.storage,
+            Some(HashMap::from([
+                (allowance_slot, rU256::from(40)),
+                (balance_slot(CALLER), rU256::from(40)),
+                (balance_slot(recipient), rU256::from(60)),
+            ]))
+        );
+        assert_eq!(token_diff.code, None);
+        assert_eq!(result.account_diffs[&CALLER].nonce, Some(1));
+        assert_eq!(result.account_diffs[&spender].nonce, Some(2));
+    }
+
+    #[test]
+    fn test_simulate_bundle_calls_created_contract() {
+        // increments the counter in slot 0 and returns its new value
+        let runtime = hex!("6000546001018060005560005260206000f3");
+        // returns the 18 bytes of the runtime code, stored right-aligned in the first word
+        let initcode = [&hex!("71")[..], &runtime[..], &hex!("6000526012600ef3")[..]].concat();
+        let db = db_with_contract(&[]);
+        let created = CALLER.create(0);
+        db.init_account(created, AccountInfo::default(), None, true);
+        let create = SimulationParameters {
+            to: Address::ZERO,
+            data: initcode.into(),
+            ..call_params(CONTRACT)
+        };
+
+        let result = SimulationEngine::new(db, false)
+            .simulate_bundle(vec![create, call_params(created)], OnRevert::Stop)
+            .unwrap();
+
+        assert_eq!(
+            rU256::from_be_slice(
+                &result.results[1]
+                    .as_ref()
+                    .unwrap()
+                    .result
+            ),
+            rU256::from(1)
+        );
+        let created_diff = &result.account_diffs[&created];
+        assert_eq!(created_diff.code, Some(Bytes::from(runtime.to_vec())));
+        assert_eq!(created_diff.nonce, Some(1));
+        assert_eq!(created_diff.storage, Some(HashMap::from([(rU256::ZERO, rU256::from(1))])));
+        assert_eq!(result.account_diffs[&CALLER].nonce, Some(2));
+    }
+
+    #[test]
+    fn test_simulate_bundle_discards_overridden_slots() {
+        // stores slot 1 incremented by one in slot 0 and returns it
+        let db = db_with_contract(&hex!("6001546001018060005560005260206000f3"));
+        let overridden = SimulationParameters {
+            overrides: Some(HashMap::from([(
+                CONTRACT,
+                HashMap::from([(U256::one(), U256::from(5))]),
+            )])),
+            ..call_params(CONTRACT)
+        };
+
+        let result = SimulationEngine::new(db, false)
+            .simulate_bundle(vec![overridden, call_params(CONTRACT)], OnRevert::Stop)
+            .unwrap();
+
+        let outputs: Vec<_> = result
+            .results
+            .iter()
+            .map(|result| rU256::from_be_slice(&result.as_ref().unwrap().result))
+            .collect();
+        assert_eq!(outputs, vec![rU256::from(6), rU256::from(1)]);
+        assert_eq!(
+            result.account_diffs[&CONTRACT].storage,
+            Some(HashMap::from([(rU256::ZERO, rU256::from(1))]))
+        );
+    }
+
+    #[test]
+    fn test_estimate_gas() {
+        // increments the counter in slot 0 and returns its new value
+        let db = db_with_contract(&hex!("6000546001018060005560005260206000f3"));
+        let params = SimulationParameters {
+            data: ethers::types::Bytes::from_static(&[0xab, 0x00]),
+            gas_limit: None,
+            ..call_params(CONTRACT)
+        };
+        let engine = SimulationEngine::new(db, false);
+
+        let estimate = engine.estimate_gas(&params).unwrap();
+
+        // base cost, one non-zero and one zero calldata byte
+        assert_eq!(estimate.intrinsic_gas, 21_000 + 16 + 4);
+        assert_eq!(estimate.execution_gas, estimate.gas_used - estimate.intrinsic_gas);
+        let with_limit =
+            |gas_limit| SimulationParameters { gas_limit: Some(gas_limit), ..params.clone() };
+        assert!(engine
+            .simulate(&with_limit(estimate.gas_limit))
+            .is_ok());
+        assert!(engine
+            .simulate(&with_limit(estimate.gas_limit - 1))
+            .is_err());
+    }
+
+    #[test]
+    fn test_estimate_gas_of_create() {
+        let db = db_with_contract(&[]);
+        db.init_account(CALLER.create(0), AccountInfo::default(), None, true);
+        // deploys an empty contract
+        let params = SimulationParameters {
+            to: Address::ZERO,
+            data: ethers::types::Bytes::from_static(&[0x00]),
+            gas_limit: None,
+            ..call_params(CONTRACT)
+        };
+
+        let estimate = SimulationEngine::new(db, false)
+            .estimate_gas(&params)
+            .unwrap();
+
+        // base and creation cost, one zero calldata byte and one word of initcode
+        assert_eq!(estimate.intrinsic_gas, 21_000 + 32_000 + 4 + 2);
+    }
+
+    #[rstest]
+    // sets slot 0 and clears it again, which refunds most of the gas of setting it
+    #[case::sstore_refund("6001600055600060005500")]
+    // calls the callee with all gas and reverts if it fails, so 1/64 of the gas is withheld
+    #[case::subcall(
+        "600060006000600060007300000000000000000000000000000000000043215af115602657005b600080fd"
+    )]
+    fn test_estimate_gas_above_gas_used(#[case] code: &str) {
+        let db = db_with_contract(&hex::decode(code).unwrap());
+        let callee = Address::from_str("0x0000000000000000000000000000000000004321").unwrap();
+        // sets slot 0
+        deploy(&db, callee, &hex!("600160005500"));
+        let params = SimulationParameters { gas_limit: None, ..call_params(CONTRACT) };
+        let engine = SimulationEngine::new(db, false);
+
+        let estimate = engine.estimate_gas(&params).unwrap();
+
+        assert!(estimate.gas_limit > estimate.gas_used);
+        let with_limit =
+            |gas_limit| SimulationParameters { gas_limit: Some(gas_limit), ..params.clone() };
+        assert_eq!(
+            engine
+                .simulate(&with_limit(estimate.gas_limit))
+                .unwrap()
+                .gas_used,
+            estimate.gas_used
+        );
+        assert!(engine
+            .simulate(&with_limit(estimate.gas_limit - 1))
+            .is_err());
+    }
 
     #[test]
     fn test_converting_to_revm() {
@@ -408,6 +1055,7 @@ mod tests {
                 .cloned()
                 .collect(),
             ),
+            account_overrides: None,
             gas_limit: Some(33),
             block_number: 0,
             timestamp: 0,
@@ -449,6 +1097,7 @@ mod tests {
             data: ethers::types::Bytes::new(),
             value: U256::zero(),
             overrides: None,
+            account_overrides: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
@@ -529,7 +1178,8 @@ mod tests {
         .cloned()
         .collect();
         assert_eq!(simulation_result.state_updates, expected_state_updates);
-        assert_eq!(simulation_result.gas_used, 90);
+        // the refund is already deducted from the gas used
+        assert_eq!(simulation_result.gas_used, 100);
     }
 
     #[test]
@@ -547,7 +1197,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.err().unwrap();
         match err {
-            SimulationEngineError::TransactionError { data: _, gas_used } => {
+            SimulationEngineError::TransactionError { gas_used, .. } => {
                 assert_eq!(
                     format!("0x{}", hex::encode::<Vec<u8>>("output".into())),
                     "0x6f7574707574"
@@ -573,7 +1223,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.err().unwrap();
         match err {
-            SimulationEngineError::TransactionError { data, gas_used } => {
+            SimulationEngineError::TransactionError { data, gas_used, .. } => {
                 assert_eq!(data, "OutOfGas(Basic)");
                 assert_eq!(gas_used, Some(100));
             }
@@ -591,7 +1241,7 @@ mod tests {
         assert!(result.is_err());
         let err = result.err().unwrap();
         match err {
-            SimulationEngineError::TransactionError { data, gas_used } => {
+            SimulationEngineError::TransactionError { data, gas_used, .. } => {
                 assert_eq!(data, "EVM error: PriorityFeeGreaterThanMaxFee");
                 assert_eq!(gas_used, None);
             }
@@ -658,6 +1308,7 @@ mod tests {
             data: encoded,
             value: U256::zero(),
             overrides: None,
+            account_overrides: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
@@ -793,6 +1444,7 @@ mod tests {
             data: calldata,
             value: U256::zero(),
             overrides: Some(overrides),
+            account_overrides: None,
             gas_limit: None,
             block_number: 0,
             timestamp: 0,
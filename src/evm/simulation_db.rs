@@ -1,5 +1,6 @@
 use std::{cell::RefCell, collections::HashMap, sync::Arc};
 
+use alloy_primitives::U64;
 use ethers::{
     providers::Middleware,
     types::{BlockId, H160, H256},
@@ -7,21 +8,142 @@ use ethers::{
 use revm::{
     db::DatabaseRef,
     interpreter::analysis::to_analysed,
-    primitives::{AccountInfo, Address, Bytecode, B256, U256 as rU256},
+    primitives::{keccak256, AccountInfo, Address, Bytecode, Bytes, B256, U256 as rU256},
 };
+use serde::{Deserialize, Serialize};
 use tracing::{debug, info};
 
 use crate::evm::engine_db_interface::EngineDatabaseInterface;
 
 use super::account_storage::{AccountStorage, StateUpdate};
 
-/// A wrapper over an actual SimulationDB that allows overriding specific storage slots
+/// How the storage of an account is overridden
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum StorageOverride {
+    /// Replaces the whole storage, slots that are not given are empty. This is `state` in
+    /// `eth_call`.
+    Replace(HashMap<rU256, rU256>),
+    /// Overrides only the given slots. This is `stateDiff` in `eth_call`.
+    Diff(HashMap<rU256, rU256>),
+}
+
+/// Overrides of an account's state, with the semantics of `eth_call`'s `stateOverride`.
+///
+/// Serializes to and from geth's JSON format, so a `HashMap<Address, AccountOverride>` can be
+/// read from or written to the `stateOverride` parameter of an `eth_call` directly.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+#[serde(try_from = "GethAccountOverride", into = "GethAccountOverride")]
+pub struct AccountOverride {
+    pub balance: Option<rU256>,
+    pub nonce: Option<u64>,
+    /// Runtime bytecode replacing the account's code
+    pub code: Option<Bytes>,
+    pub storage: Option<StorageOverride>,
+}
+
+impl AccountOverride {
+    /// Whether the override sets all of the account's info, so the account doesn't have to
+    /// exist in the wrapped database.
+    fn replaces_account_info(&self) -> bool {
+        self.balance.is_some() && self.nonce.is_some() && self.code.is_some()
+    }
+
+    /// Applies the override to `info`. `code_hash` is the hash of the overriding code, if any.
+    fn apply(&self, info: &mut AccountInfo, code_hash: Option<B256>) {
+        if let Some(balance) = self.balance {
+            info.balance = balance;
+        }
+        if let Some(nonce) = self.nonce {
+            info.nonce = nonce;
+        }
+        if let (Some(code), Some(code_hash)) = (&self.code, code_hash) {
+            info.code_hash = code_hash;
+            info.code = Some(to_analysed(Bytecode::new_raw(code.clone())));
+        }
+    }
+}
+
+/// An account override in geth's JSON format
+#[derive(Clone, Serialize, Deserialize)]
+#[serde(rename_all = "camelCase")]
+struct GethAccountOverride {
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    balance: Option<rU256>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    nonce: Option<U64>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    code: Option<Bytes>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state: Option<HashMap<B256, B256>>,
+    #[serde(default, skip_serializing_if = "Option::is_none")]
+    state_diff: Option<HashMap<B256, B256>>,
+}
+
+impl TryFrom<GethAccountOverride> for AccountOverride {
+    type Error = String;
+
+    fn try_from(value: GethAccountOverride) -> Result<Self, Self::Error> {
+        let to_storage = |slots: HashMap<B256, B256>| {
+            slots
+                .into_iter()
+                .map(|(slot, value)| (rU256::from_be_bytes(slot.0), rU256::from_be_bytes(value.0)))
+                .collect()
+        };
+        let storage = match (value.state, value.state_diff) {
+            (Some(_), Some(_)) => {
+                return Err("Account override has both state and stateDiff".to_string())
+            }
+            (Some(state), None) => Some(StorageOverride::Replace(to_storage(state))),
+            (None, Some(state_diff)) => Some(StorageOverride::Diff(to_storage(state_diff))),
+            (None, None) => None,
+        };
+        Ok(AccountOverride {
+            balance: value.balance,
+            nonce: value
+                .nonce
+                .map(|nonce| nonce.to::<u64>()),
+            code: value.code,
+            storage,
+        })
+    }
+}
+
+impl From<AccountOverride> for GethAccountOverride {
+    fn from(value: AccountOverride) -> Self {
+        let to_geth = |slots: HashMap<rU256, rU256>| {
+            slots
+                .into_iter()
+                .map(|(slot, value)| (B256::from(slot), B256::from(value)))
+                .collect()
+        };
+        let (state, state_diff) = match value.storage {
+            Some(StorageOverride::Replace(storage)) => (Some(to_geth(storage)), None),
+            Some(StorageOverride::Diff(storage)) => (None, Some(to_geth(storage))),
+            None => (None, None),
+        };
+        GethAccountOverride {
+            balance: value.balance,
+            nonce: value.nonce.map(U64::from),
+            code: value.code,
+            state,
+            state_diff,
+        }
+    }
+}
+
+/// A wrapper over an actual SimulationDB that allows overriding specific storage slots and
+/// accounts
 pub struct OverriddenSimulationDB<'a, DB: DatabaseRef> {
     /// Wrapped database. Will be queried if a requested item is not found in the overrides.
     pub inner_db: &'a DB,
     /// A mapping from account address to storage.
     /// Storage is a mapping from slot index to slot value.
     pub overrides: &'a HashMap<Address, HashMap<rU256, rU256>>,
+    /// Overrides of whole accounts. Slots in `overrides` take precedence over the storage
+    /// overrides given here.
+    account_overrides: Option<&'a HashMap<Address, AccountOverride>>,
+    /// Hashes of the codes in `account_overrides` by account, computed once per database
+    override_code_hashes: HashMap<Address, B256>,
 }
 
 impl<'a, DB: DatabaseRef> OverriddenSimulationDB<'a, DB> {
@@ -36,7 +158,32 @@ impl<'a, DB: DatabaseRef> OverriddenSimulationDB<'a, DB> {
     ///
     /// A new instance of OverriddenSimulationDB.
     pub fn new(inner_db: &'a DB, overrides: &'a HashMap<Address, HashMap<rU256, rU256>>) -> Self {
-        OverriddenSimulationDB { inner_db, overrides }
+        OverriddenSimulationDB {
+            inner_db,
+            overrides,
+            account_overrides: None,
+            override_code_hashes: HashMap::new(),
+        }
+    }
+
+    /// Sets the account overrides, see `AccountOverride`.
+    pub fn with_account_overrides(
+        mut self,
+        account_overrides: &'a HashMap<Address, AccountOverride>,
+    ) -> Self {
+        self.account_overrides = Some(account_overrides);
+        self.override_code_hashes = account_overrides
+            .iter()
+            .filter_map(|(address, account_override)| {
+                Some((*address, keccak256(account_override.code.as_ref()?)))
+            })
+            .collect();
+        self
+    }
+
+    fn account_override(&self, address: &Address) -> Option<&'a AccountOverride> {
+        self.account_overrides
+            .and_then(|overrides| overrides.get(address))
     }
 }
 
@@ -44,28 +191,67 @@ impl<'a, DB: DatabaseRef> DatabaseRef for OverriddenSimulationDB<'a, DB> {
     type Error = DB::Error;
 
     fn basic_ref(&self, address: Address) -> Result<Option<AccountInfo>, Self::Error> {
-        self.inner_db.basic_ref(address)
+        let account_override = match self.account_override(&address) {
+            Some(account_override) => account_override,
+            None => return self.inner_db.basic_ref(address),
+        };
+        let mut info = if account_override.replaces_account_info() {
+            AccountInfo::default()
+        } else {
+            self.inner_db
+                .basic_ref(address)?
+                .unwrap_or_default()
+        };
+        account_override.apply(
+            &mut info,
+            self.override_code_hashes
+                .get(&address)
+                .copied(),
+        );
+        Ok(Some(info))
     }
 
     fn code_by_hash_ref(&self, code_hash: B256) -> Result<Bytecode, Self::Error> {
-        self.inner_db
-            .code_by_hash_ref(code_hash)
+        let overridden_code = self
+            .override_code_hashes
+            .iter()
+            .find(|(_, hash)| **hash == code_hash)
+            .and_then(|(address, _)| {
+                self.account_override(address)?
+                    .code
+                    .as_ref()
+            });
+        match overridden_code {
+            Some(code) => Ok(to_analysed(Bytecode::new_raw(code.clone()))),
+            None => self
+                .inner_db
+                .code_by_hash_ref(code_hash),
+        }
     }
 
     fn storage_ref(&self, address: Address, index: rU256) -> Result<rU256, Self::Error> {
-        match self.overrides.get(&address) {
-            None => self
+        if let Some(value) = self
+            .overrides
+            .get(&address)
+            .and_then(|slot_overrides| slot_overrides.get(&index))
+        {
+            debug!(%address, %index, %value, "Requested storage of account {:x?} slot {}", address, index);
+            return Ok(*value);
+        }
+        match self
+            .account_override(&address)
+            .and_then(|account_override| account_override.storage.as_ref())
+        {
+            Some(StorageOverride::Replace(storage)) => Ok(storage
+                .get(&index)
+                .copied()
+                .unwrap_or_default()),
+            Some(StorageOverride::Diff(storage)) if storage.contains_key(&index) => {
+                Ok(storage[&index])
+            }
+            _ => self
                 .inner_db
                 .storage_ref(address, index),
-            Some(slot_overrides) => match slot_overrides.get(&index) {
-                Some(value) => {
-                    debug!(%address, %index, %value, "Requested storage of account {:x?} slot {}", address, index);
-                    Ok(*value)
-                }
-                None => self
-                    .inner_db
-                    .storage_ref(address, index),
-            },
         }
     }
 
@@ -74,7 +260,7 @@ impl<'a, DB: DatabaseRef> DatabaseRef for OverriddenSimulationDB<'a, DB> {
     }
 }
 
-#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Default)]
+#[derive(Debug, Clone, Copy, Eq, Hash, PartialEq, Default, Serialize, Deserialize)]
 pub struct BlockHeader {
     pub number: u64,
     pub hash: H256,
@@ -790,4 +976,121 @@ mod tests {
 
         Ok(())
     }
+
+    #[rstest]
+    fn test_overridden_db_account_overrides(
+        mock_sim_db: SimulationDB<Provider<MockProvider>>,
+    ) -> Result<(), Box<dyn Error>> {
+        let slot1 = rU256::from(1);
+        let slot2 = rU256::from(2);
+        let original_storage: HashMap<rU256, rU256> =
+            HashMap::from([(slot1, rU256::from(100)), (slot2, rU256::from(200))]);
+        let replaced = Address::from_str("0000000000000000000000000000000000000001").unwrap();
+        let diffed = Address::from_str("0000000000000000000000000000000000000002").unwrap();
+        let injected = Address::from_str("0000000000000000000000000000000000000003").unwrap();
+        for address in [replaced, diffed] {
+            mock_sim_db.init_account(
+                address,
+                AccountInfo { nonce: 3, ..Default::default() },
+                Some(original_storage.clone()),
+                false,
+            );
+        }
+        let code = Bytes::from_static(&[0x60, 0x00, 0x60, 0x00, 0xf3]);
+        let account_overrides = HashMap::from([
+            (
+                replaced,
+                AccountOverride {
+                    balance: Some(rU256::from(5)),
+                    storage: Some(StorageOverride::Replace(HashMap::from([(
+                        slot2,
+                        rU256::from(7),
+                    )]))),
+                    ..Default::default()
+                },
+            ),
+            (
+                diffed,
+                AccountOverride {
+                    storage: Some(StorageOverride::Diff(HashMap::from([(slot1, rU256::from(9))]))),
+                    ..Default::default()
+                },
+            ),
+            (
+                injected,
+                AccountOverride {
+                    balance: Some(rU256::from(1)),
+                    nonce: Some(1),
+                    code: Some(code.clone()),
+                    storage: None,
+                },
+            ),
+        ]);
+        let slot_overrides = HashMap::from([(diffed, HashMap::from([(slot2, rU256::from(11))]))]);
+
+        let overriden_db = OverriddenSimulationDB::new(&mock_sim_db, &slot_overrides)
+            .with_account_overrides(&account_overrides);
+
+        let replaced_info = overriden_db
+            .basic_ref(replaced)?
+            .unwrap();
+        assert_eq!((replaced_info.balance, replaced_info.nonce), (rU256::from(5), 3));
+        assert_eq!(overriden_db.storage_ref(replaced, slot1)?, rU256::ZERO);
+        assert_eq!(overriden_db.storage_ref(replaced, slot2)?, rU256::from(7));
+        assert_eq!(overriden_db.storage_ref(diffed, slot1)?, rU256::from(9));
+        assert_eq!(overriden_db.storage_ref(diffed, slot2)?, rU256::from(11));
+        // the injected account is fully overridden, so the node is not queried for it
+        let injected_info = overriden_db
+            .basic_ref(injected)?
+            .unwrap();
+        assert_eq!(injected_info.code_hash, keccak256(&code));
+        assert_eq!(
+            overriden_db
+                .code_by_hash_ref(injected_info.code_hash)?
+                .original_bytes(),
+            code
+        );
+        Ok(())
+    }
+
+    #[test]
+    fn test_account_override_geth_json() {
+        let json = r#"{
+            "0x0000000000000000000000000000000000000001": {
+                "balance": "0x64",
+                "nonce": "0x2",
+                "code": "0x6000",
+                "stateDiff": {
+                    "0x0000000000000000000000000000000000000000000000000000000000000001":
+                        "0x000000000000000000000000000000000000000000000000000000000000000a"
+                }
+            },
+            "0x0000000000000000000000000000000000000002": {
+                "state": {}
+            }
+        }"#;
+
+        let overrides: HashMap<Address, AccountOverride> = serde_json::from_str(json).unwrap();
+
+        let account =
+            &overrides[&Address::from_str("0000000000000000000000000000000000000001").unwrap()];
+        assert_eq!(account.balance, Some(rU256::from(100)));
+        assert_eq!(account.nonce, Some(2));
+        assert_eq!(account.code, Some(Bytes::from_static(&[0x60, 0x00])));
+        assert_eq!(
+            account.storage,
+            Some(StorageOverride::Diff(HashMap::from([(rU256::from(1), rU256::from(10))])))
+        );
+        assert_eq!(
+            overrides[&Address::from_str("0000000000000000000000000000000000000002").unwrap()]
+                .storage,
+            Some(StorageOverride::Replace(HashMap::new()))
+        );
+        let roundtrip: HashMap<Address, AccountOverride> =
+            serde_json::from_value(serde_json::to_value(&overrides).unwrap()).unwrap();
+        assert_eq!(roundtrip, overrides);
+        assert!(
+            serde_json::from_str::<AccountOverride>(r#"{"state": {}, "stateDiff": {}}"#).is_err()
+        );
+    }
 }
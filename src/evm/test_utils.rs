@@ -0,0 +1,71 @@
+//! Fixtures shared by tests simulating hand-written contracts on a `PreCachedDB`
+use std::str::FromStr;
+
+use ethers::types::{Bytes, H256, U256};
+use revm::primitives::{keccak256, AccountInfo, Address, Bytecode, U256 as rU256};
+
+use crate::evm::{
+    engine_db_interface::EngineDatabaseInterface, simulation::SimulationParameters,
+    simulation_db::BlockHeader, tycho_db::PreCachedDB,
+};
+
+/// Address the contract under test is deployed at
+pub(crate) const CONTRACT: Address =
+    Address::new([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x12, 0x34]);
+
+/// Sender of the simulated calls, holding one ether
+pub(crate) const CALLER: Address =
+    Address::new([0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0x56, 0x78]);
+
+/// The block the tests simulate at
+pub(crate) fn test_block() -> BlockHeader {
+    BlockHeader {
+        number: 20463609,
+        hash: H256::from_str("0x4315fd1afc25cc2ebc72029c543293f9fd833eeb305e2e30159459c827733b1b")
+            .unwrap(),
+        timestamp: 1722875891,
+    }
+}
+
+/// Creates a database with `code` deployed at `CONTRACT` and the `CALLER` account.
+pub(crate) fn db_with_contract(code: &[u8]) -> PreCachedDB {
+    let db = PreCachedDB::new().expect("Failed to create PreCachedDB");
+    deploy(&db, CONTRACT, code);
+    db.init_account(
+        CALLER,
+        AccountInfo { balance: rU256::from(10).pow(rU256::from(18)), ..Default::default() },
+        None,
+        true,
+    );
+    db
+}
+
+/// Deploys `code` at `address`.
+pub(crate) fn deploy(db: &PreCachedDB, address: Address, code: &[u8]) {
+    db.init_account(
+        address,
+        AccountInfo {
+            code_hash: keccak256(code),
+            code: Some(Bytecode::new_raw(code.to_vec().into())),
+            ..Default::default()
+        },
+        None,
+        true,
+    );
+}
+
+/// Parameters of a call from `CALLER` to `to` without calldata, at the test block
+pub(crate) fn call_params(to: Address) -> SimulationParameters {
+    let block = test_block();
+    SimulationParameters {
+        caller: CALLER,
+        to,
+        data: Bytes::new(),
+        value: U256::zero(),
+        overrides: None,
+        account_overrides: None,
+        gas_limit: Some(100_000),
+        block_number: block.number,
+        timestamp: block.timestamp,
+    }
+}
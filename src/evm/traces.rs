@@ -1,10 +1,134 @@
-use foundry_config::{Chain, Config};
+use std::collections::HashMap;
+
+use ethers::abi::{decode, Abi, ParamType, Token};
 use foundry_evm::traces::{
-    decode_trace_arena,
-    identifier::{EtherscanIdentifier, SignaturesIdentifier},
-    render_trace_arena, CallTraceDecoder, CallTraceDecoderBuilder, DebugTraceIdentifier, Traces,
+    decode_trace_arena, render_trace_arena, CallTraceDecoder, CallTraceDecoderBuilder, Traces,
+};
+use revm::primitives::{Address, Bytes, U256 as rU256};
+use revm_inspectors::tracing::{
+    types::{CallKind, CallTraceNode},
+    CallTraceArena,
 };
 
+/// Selector of `Error(string)`, used by `require` and `revert` with a message
+const ERROR_SELECTOR: [u8; 4] = [0x08, 0xc3, 0x79, 0xa0];
+/// Selector of `Panic(uint256)`, used by failing assertions and arithmetic errors
+const PANIC_SELECTOR: [u8; 4] = [0x4e, 0x48, 0x7b, 0x71];
+
+/// A call made during a simulated transaction, together with the calls it made in turn
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct CallTrace {
+    pub kind: CallKind,
+    pub from: Address,
+    /// The called contract, or the created contract for creations
+    pub to: Address,
+    pub value: rU256,
+    pub input: Bytes,
+    pub output: Bytes,
+    pub gas_limit: u64,
+    pub gas_used: u64,
+    pub success: bool,
+    /// The message of a failed call, if it reverted with `Error(string)` or `Panic(uint256)`
+    pub revert_reason: Option<String>,
+    /// Depth of the call; the transaction's call has depth 0
+    pub depth: usize,
+    /// The decoded call, if an ABI of the called contract was given to `decode`
+    pub decoded: Option<DecodedCall>,
+    pub calls: Vec<CallTrace>,
+}
+
+/// A call decoded against the ABI of the called contract
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct DecodedCall {
+    /// The called function's signature, e.g. `transfer(address,uint256)`
+    pub signature: String,
+    pub inputs: Vec<Token>,
+    /// The decoded return values; `None` for failed calls or undecodable output
+    pub outputs: Option<Vec<Token>>,
+}
+
+impl CallTrace {
+    /// Builds the call tree recorded by a tracing inspector. Returns `None` if nothing was
+    /// recorded.
+    pub fn from_arena(arena: &CallTraceArena) -> Option<Self> {
+        (!arena.nodes().is_empty()).then(|| Self::from_node(arena.nodes(), 0))
+    }
+
+    fn from_node(nodes: &[CallTraceNode], idx: usize) -> Self {
+        let node = &nodes[idx];
+        let trace = &node.trace;
+        CallTrace {
+            kind: trace.kind,
+            from: trace.caller,
+            to: trace.address,
+            value: trace.value,
+            input: trace.data.clone(),
+            output: trace.output.clone(),
+            gas_limit: trace.gas_limit,
+            gas_used: trace.gas_used,
+            success: trace.success,
+            revert_reason: if trace.success { None } else { decode_revert_reason(&trace.output) },
+            depth: trace.depth,
+            decoded: None,
+            calls: node
+                .children
+                .iter()
+                .map(|child| Self::from_node(nodes, *child))
+                .collect(),
+        }
+    }
+
+    /// Decodes this call and all its subcalls against the given ABIs, keyed by contract address.
+    ///
+    /// Only the given ABIs are used; calls to other contracts or to functions missing from the
+    /// ABI stay undecoded.
+    pub fn decode(&mut self, abis: &HashMap<Address, Abi>) {
+        self.decoded = abis
+            .get(&self.to)
+            .and_then(|abi| decode_call(abi, &self.input, self.success.then_some(&self.output)));
+        for call in &mut self.calls {
+            call.decode(abis);
+        }
+    }
+}
+
+fn decode_call(abi: &Abi, input: &[u8], output: Option<&Bytes>) -> Option<DecodedCall> {
+    if input.len() < 4 {
+        return None;
+    }
+    let (selector, args) = input.split_at(4);
+    let function = abi
+        .functions()
+        .find(|function| function.short_signature() == selector)?;
+    Some(DecodedCall {
+        signature: function.signature(),
+        inputs: function.decode_input(args).ok()?,
+        outputs: output.and_then(|output| function.decode_output(output).ok()),
+    })
+}
+
+/// Returns the message of a revert with `Error(string)` or the code of a `Panic(uint256)`.
+pub fn decode_revert_reason(output: &[u8]) -> Option<String> {
+    if output.len() < 4 {
+        return None;
+    }
+    let (selector, data) = output.split_at(4);
+    if selector == ERROR_SELECTOR {
+        decode(&[ParamType::String], data)
+            .ok()?
+            .pop()?
+            .into_string()
+    } else if selector == PANIC_SELECTOR {
+        let code = decode(&[ParamType::Uint(256)], data)
+            .ok()?
+            .pop()?
+            .into_uint()?;
+        Some(format!("Panic({:#x})", code))
+    } else {
+        None
+    }
+}
+
 /// A slimmed down return from the executor used for returning minimal trace + gas metering info
 #[derive(Debug)]
 pub struct TraceResult {
@@ -13,40 +137,10 @@ pub struct TraceResult {
     pub gas_used: u64,
 }
 
-pub async fn handle_traces(
-    mut result: TraceResult,
-    config: &Config,
-    chain: Option<Chain>,
-    decode_internal: bool,
-) -> Result<(), Box<dyn std::error::Error>> {
-    let mut decoder = CallTraceDecoderBuilder::new()
-        .with_signature_identifier(SignaturesIdentifier::new(
-            Config::foundry_cache_dir(),
-            config.offline,
-        )?)
-        .build();
-
-    let mut etherscan_identifier = EtherscanIdentifier::new(config, chain)?;
-    if let Some(etherscan_identifier) = &mut etherscan_identifier {
-        for (_, trace) in result
-            .traces
-            .as_deref_mut()
-            .unwrap_or_default()
-        {
-            decoder.identify(trace, etherscan_identifier);
-        }
-    }
-
-    if decode_internal {
-        let sources = if let Some(etherscan_identifier) = &etherscan_identifier {
-            etherscan_identifier
-                .get_compiled_contracts()
-                .await?
-        } else {
-            Default::default()
-        };
-        decoder.debug_identifier = Some(DebugTraceIdentifier::new(sources));
-    }
+/// Prints the traces, decoded with the built-in decoder only. No network requests are made,
+/// neither to Etherscan nor to signature databases.
+pub async fn handle_traces(mut result: TraceResult) -> Result<(), Box<dyn std::error::Error>> {
+    let decoder = CallTraceDecoderBuilder::new().build();
 
     print_traces(&mut result, &decoder).await?;
 
@@ -78,3 +172,77 @@ pub async fn print_traces(
     println!("Gas used: {}", result.gas_used);
     Ok(())
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use ethers::{
+        abi::{encode, parse_abi},
+        types::{H160, U256},
+        utils::id,
+    };
+    use rstest::rstest;
+
+    fn call_trace(to: Address, input: Vec<u8>, output: Vec<u8>, success: bool) -> CallTrace {
+        CallTrace {
+            kind: CallKind::Call,
+            from: Address::ZERO,
+            to,
+            value: rU256::ZERO,
+            input: input.into(),
+            output: output.into(),
+            gas_limit: 100_000,
+            gas_used: 21_000,
+            success,
+            revert_reason: None,
+            depth: 0,
+            decoded: None,
+            calls: Vec::new(),
+        }
+    }
+
+    #[rstest]
+    #[case::error(
+        [ERROR_SELECTOR.to_vec(), encode(&[Token::String("not enough".to_string())])].concat(),
+        Some("not enough".to_string())
+    )]
+    #[case::panic(
+        [PANIC_SELECTOR.to_vec(), encode(&[Token::Uint(U256::from(0x11))])].concat(),
+        Some("Panic(0x11)".to_string())
+    )]
+    #[case::custom_error(id("Unauthorized()").to_vec(), None)]
+    #[case::empty(Vec::new(), None)]
+    fn test_decode_revert_reason(#[case] output: Vec<u8>, #[case] expected: Option<String>) {
+        assert_eq!(decode_revert_reason(&output), expected);
+    }
+
+    #[test]
+    fn test_decode_with_local_abis() {
+        let token = Address::repeat_byte(0x11);
+        let unknown = Address::repeat_byte(0x22);
+        let abi =
+            parse_abi(&["function transfer(address to, uint256 amount) returns (bool)"]).unwrap();
+        let input = [
+            id("transfer(address,uint256)").to_vec(),
+            encode(&[Token::Address(H160::repeat_byte(0x33)), Token::Uint(U256::from(100))]),
+        ]
+        .concat();
+        let mut trace = call_trace(token, input.clone(), encode(&[Token::Bool(true)]), true);
+        trace
+            .calls
+            .push(call_trace(unknown, input, Vec::new(), true));
+
+        trace.decode(&HashMap::from([(token, abi)]));
+
+        assert_eq!(
+            trace.decoded,
+            Some(DecodedCall {
+                signature: "transfer(address,uint256)".to_string(),
+                inputs: vec![Token::Address(H160::repeat_byte(0x33)), Token::Uint(U256::from(100))],
+                outputs: Some(vec![Token::Bool(true)]),
+            })
+        );
+        assert_eq!(trace.calls[0].decoded, None);
+    }
+}
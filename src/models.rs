@@ -18,6 +18,35 @@ use tycho_core::dto::ResponseToken;
 
 use crate::protocol::BytesConvertible;
 
+/// Basis points, i.e. 1/10_000
+const BPS: u32 = 10_000;
+
+/// How a token's transfers deviate from plain ERC20 transfers
+///
+/// Taxes are charged on the transferred amount, so the receiver gets less than was sent. Sells
+/// are transfers of the token into a pool, buys are transfers out of a pool to the trader.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct TransferBehaviour {
+    /// Tax on transfers out of a pool, in basis points
+    pub buy_tax_bps: u32,
+    /// Tax on transfers into a pool, in basis points
+    pub sell_tax_bps: u32,
+    /// Whether balances change without transfers, e.g. through rebases. Pool states can't
+    /// account for such changes, so their quotes are only valid until the next rebase.
+    pub rebasing: bool,
+    /// The largest amount a single transfer is allowed to move, if the token limits it
+    pub max_transfer_amount: Option<U256>,
+    /// Whether some transfers of the token revert, e.g. because of a blacklist
+    pub restricted: bool,
+}
+
+impl TransferBehaviour {
+    /// Whether transfers of the token are taxed
+    pub fn has_tax(&self) -> bool {
+        self.buy_tax_bps > 0 || self.sell_tax_bps > 0
+    }
+}
+
 #[derive(Clone, Debug, Eq)]
 pub struct ERC20Token {
     /// The address of the token on the blockchain network
@@ -28,6 +57,8 @@ pub struct ERC20Token {
     pub symbol: String,
     /// The amount of gas it takes to transfer the token
     pub gas: U256,
+    /// Transfer taxes and rebasing behaviour of the token
+    pub transfer_behaviour: TransferBehaviour,
 }
 
 impl ERC20Token {
@@ -49,7 +80,44 @@ impl ERC20Token {
     pub fn new(address: &str, decimals: usize, symbol: &str, gas: U256) -> Self {
         let addr = H160::from_str(address).expect("Failed to parse token address");
         let sym = symbol.to_string();
-        ERC20Token { address: addr, decimals, symbol: sym, gas }
+        ERC20Token {
+            address: addr,
+            decimals,
+            symbol: sym,
+            gas,
+            transfer_behaviour: TransferBehaviour::default(),
+        }
+    }
+
+    /// Sets the transfer taxes and rebasing behaviour of the token.
+    pub fn with_transfer_behaviour(mut self, transfer_behaviour: TransferBehaviour) -> Self {
+        self.transfer_behaviour = transfer_behaviour;
+        self
+    }
+
+    /// Returns the amount a pool receives when `amount` of this token is sold into it.
+    pub fn amount_after_sell_tax(&self, amount: U256) -> U256 {
+        apply_tax(amount, self.transfer_behaviour.sell_tax_bps)
+    }
+
+    /// Returns the largest amount that can be sold into a pool without the pool receiving more
+    /// than `pool_amount` of this token after the sell tax.
+    pub fn max_amount_before_sell_tax(&self, pool_amount: U256) -> U256 {
+        let tax_bps = self
+            .transfer_behaviour
+            .sell_tax_bps
+            .min(BPS);
+        if tax_bps == BPS {
+            // the pool receives nothing, whatever is sold
+            return U256::MAX;
+        }
+        let amount = pool_amount.full_mul(U256::from(BPS)) / U256::from(BPS - tax_bps);
+        U256::try_from(amount).unwrap_or(U256::MAX)
+    }
+
+    /// Returns the amount the trader receives when a pool sends out `amount` of this token.
+    pub fn amount_after_buy_tax(&self, amount: U256) -> U256 {
+        apply_tax(amount, self.transfer_behaviour.buy_tax_bps)
     }
 
     /// One
@@ -62,6 +130,15 @@ impl ERC20Token {
     }
 }
 
+fn apply_tax(amount: U256, tax_bps: u32) -> U256 {
+    if tax_bps == 0 {
+        return amount;
+    }
+    // the tax is rounded down, as tokens usually compute it as `amount * tax / denominator`
+    let tax = amount.full_mul(U256::from(tax_bps.min(BPS))) / U256::from(BPS);
+    amount - U256::try_from(tax).expect("Tax can't exceed the amount")
+}
+
 impl PartialOrd for ERC20Token {
     fn partial_cmp(&self, other: &Self) -> Option<std::cmp::Ordering> {
         self.address.partial_cmp(&other.address)
@@ -88,6 +165,7 @@ impl TryFrom<ResponseToken> for ERC20Token {
             address: H160::from_bytes(&value.address),
             decimals: value.decimals.try_into()?,
             symbol: value.symbol,
+            transfer_behaviour: TransferBehaviour::default(),
             gas: U256::from(
                 value
                     .gas
@@ -211,6 +289,30 @@ mod tests {
         assert_eq!(format!("{:#x}", token.address), "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48");
     }
 
+    #[test]
+    fn test_transfer_taxes() {
+        let token = ERC20Token::new(
+            "0xa0b86991c6218b36c1d19d4a2e9eb0ce3606eb48",
+            6,
+            "TAX",
+            U256::from(10000),
+        )
+        .with_transfer_behaviour(TransferBehaviour {
+            buy_tax_bps: 100,
+            sell_tax_bps: 500,
+            ..Default::default()
+        });
+
+        assert!(token.transfer_behaviour.has_tax());
+        assert_eq!(token.amount_after_sell_tax(U256::from(1000)), U256::from(950));
+        assert_eq!(token.amount_after_buy_tax(U256::from(1000)), U256::from(990));
+        // the tax is rounded down
+        assert_eq!(token.amount_after_buy_tax(U256::from(199)), U256::from(198));
+        assert_eq!(token.max_amount_before_sell_tax(U256::from(950)), U256::from(1000));
+        assert_eq!(token.max_amount_before_sell_tax(U256::from(951)), U256::from(1001));
+        assert_eq!(token.amount_after_sell_tax(U256::from(1002)), U256::from(952));
+    }
+
     #[test]
     fn test_cmp() {
         let usdc = ERC20Token::new(
@@ -1,6 +1,7 @@
 //! Protocol generic errors
 use std::fmt;
 
+use ethers::types::{H160, H256};
 use thiserror::Error;
 
 use crate::{
@@ -8,7 +9,7 @@ use crate::{
     protocol::vm::errors::{FileError, RpcError},
 };
 
-use super::models::GetAmountOutResult;
+use super::models::{GetAmountOutResult, PartialFillResult};
 
 impl fmt::Display for GetAmountOutResult {
     fn fmt(&self, f: &mut fmt::Formatter) -> fmt::Result {
@@ -22,6 +23,48 @@ pub enum TransitionError<T> {
     MissingAttribute(String),
     DecodeError(String),
     InvalidEventType(),
+    InconsistentEvent(String),
+}
+
+/// Errors that can occur when decoding raw EVM logs into protocol events.
+#[derive(Debug, Error)]
+pub enum LogDecodingError {
+    #[error("Unknown event signature {0:?}")]
+    UnknownEvent(H256),
+    #[error("Missing log attribute {0}")]
+    MissingAttribute(String),
+    #[error("Invalid log data: {0}")]
+    InvalidData(String),
+}
+
+/// Errors that can occur when maintaining states from EVM logs.
+#[derive(Debug, Error)]
+pub enum LogTrackingError {
+    #[error("Log decoding error: {0}")]
+    DecodingError(#[from] LogDecodingError),
+    #[error("Failed to apply event to state of {0:?}: {1}")]
+    TransitionError(H160, String),
+    #[error("Log of block {actual} fed as part of block {expected}")]
+    UnexpectedBlock { expected: u64, actual: u64 },
+    #[error("Can't revert block {0}, it is older than the oldest checkpoint")]
+    ReorgTooDeep(u64),
+    #[error("Block {actual} doesn't follow the latest applied block {latest}")]
+    MissingBlocks { latest: u64, actual: u64 },
+    #[error("Parent {actual:?} of block {number} doesn't match the tracked block {expected:?}")]
+    ParentHashMismatch { number: u64, expected: H256, actual: H256 },
+}
+
+/// Errors that can occur when serializing or restoring protocol state snapshots.
+#[derive(Debug, Error)]
+pub enum StateSnapshotError {
+    #[error("No state type registered for protocol {0}")]
+    UnknownProtocol(String),
+    #[error("State type is not registered")]
+    UnregisteredType(),
+    #[error("Unsupported snapshot version {0}")]
+    UnsupportedVersion(u32),
+    #[error("Serialization error: {0}")]
+    SerializationError(#[from] serde_json::Error),
 }
 
 #[derive(Debug, Error)]
@@ -59,8 +102,11 @@ impl From<SimulationError> for InvalidSnapshotError {
 /// - `NoLiquidity`: Error indicating that there is no liquidity in the venue to complete the trade.
 /// - `InsufficientAmount`: Error indicating that the amount provided for the trade is too low.
 /// - `ArithmeticOverflow`: Error indicating that an arithmetic operation got an U256 to overflow
+/// - `InvalidInput`: Error indicating that the parameters passed to the simulation are invalid.
 /// - `Unknown`: Error indicating that an unknown error occurred during the simulation.
-/// - `SellAmountTooHigh`: Indicates an error when the sell amount is higher than the sell limit.
+/// - `NotImplemented`: Indicates that a protocol doesn't support the requested operation.
+/// - `SellAmountTooHigh`: Indicates an error when the sell amount is higher than the sell limit. It
+///   returns the result of selling up to the limit.
 #[derive(Error, Debug)]
 pub enum SimulationError {
     #[error("ABI loading error: {0}")]
@@ -85,8 +131,12 @@ pub enum SimulationError {
     InsufficientAmount(),
     #[error("U256 overflow")]
     ArithmeticOverflow(),
+    #[error("Invalid input: {0}")]
+    InvalidInput(String),
     #[error("Unknown error")]
     Unknown(),
-    #[error("Sell amount is higher than sell limit")]
-    SellAmountTooHigh(), // TODO: Make it recoverable
+    #[error("Not implemented: {0}")]
+    NotImplemented(String),
+    #[error("Sell amount is higher than sell limit {}", .0.sell_amount)]
+    SellAmountTooHigh(PartialFillResult),
 }
@@ -1,9 +1,9 @@
 use ethers::{
     prelude::LogMeta,
-    types::{H160, H256},
+    types::{Log, H160, H256},
 };
 
-use super::errors::TransitionError;
+use super::errors::{LogDecodingError, TransitionError};
 
 pub type LogIndex = (u64, u32);
 
@@ -60,6 +60,36 @@ impl From<LogMeta> for EVMLogMeta {
     }
 }
 
+impl TryFrom<&Log> for EVMLogMeta {
+    type Error = LogDecodingError;
+
+    /// Fails for pending logs, which are not part of a block yet.
+    fn try_from(log: &Log) -> Result<Self, Self::Error> {
+        let missing = |attr: &str| LogDecodingError::MissingAttribute(attr.to_string());
+        Ok(EVMLogMeta {
+            from: log.address,
+            block_number: log
+                .block_number
+                .ok_or_else(|| missing("block_number"))?
+                .as_u64(),
+            block_hash: log
+                .block_hash
+                .ok_or_else(|| missing("block_hash"))?,
+            transaction_index: log
+                .transaction_index
+                .ok_or_else(|| missing("transaction_index"))?
+                .as_u32(),
+            transaction_hash: log
+                .transaction_hash
+                .ok_or_else(|| missing("transaction_hash"))?,
+            log_index: log
+                .log_index
+                .ok_or_else(|| missing("log_index"))?
+                .as_u32(),
+        })
+    }
+}
+
 impl EVMLogMeta {
     pub fn index(&self) -> (u64, u32) {
         (self.block_number, self.log_index)
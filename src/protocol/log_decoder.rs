@@ -0,0 +1,280 @@
+//! Decoding of raw EVM logs
+//!
+//! The `LogDecoder` turns raw `ethers` logs, e.g. from a local node or a recorded file, into
+//! protocol events that can be passed straight to `ProtocolSim::event_transition`. Decoders are
+//! registered per event signature; the default decoder knows about all events of the protocols
+//! supported by this crate.
+//!
+//! # Examples
+//! ```
+//! use ethers::{abi::{encode, Token}, types::{Log, U256, U64}};
+//! use tycho_simulation::protocol::{
+//!     log_decoder::{event_signature, LogDecoder},
+//!     uniswap_v2::events::UniswapV2Sync,
+//! };
+//!
+//! let log = Log {
+//!     topics: vec![event_signature("Sync(uint112,uint112)")],
+//!     data: encode(&[Token::Uint(U256::from(1)), Token::Uint(U256::from(2))]).into(),
+//!     block_number: Some(U64::from(1)),
+//!     block_hash: Some(Default::default()),
+//!     transaction_index: Some(U64::from(0)),
+//!     transaction_hash: Some(Default::default()),
+//!     log_index: Some(U256::from(0)),
+//!     ..Default::default()
+//! };
+//!
+//! let (event, _meta) = LogDecoder::default().decode(&log).unwrap();
+//! let sync = event.as_any().downcast_ref::<UniswapV2Sync>().unwrap();
+//! assert_eq!(sync.reserve1, U256::from(2));
+//! ```
+use std::collections::HashMap;
+
+use ethers::{
+    abi::{decode, ParamType, Token},
+    types::{Log, H256, U256},
+    utils::keccak256,
+};
+
+use super::{
+    errors::LogDecodingError, events::EVMLogMeta, state::ProtocolEvent, uniswap_v2, uniswap_v3,
+};
+
+/// Decodes a log whose signature matched into a protocol event.
+pub type DecodeLogFn = fn(&Log) -> Result<Box<dyn ProtocolEvent>, LogDecodingError>;
+
+/// Returns the topic identifying an event, e.g. `event_signature("Sync(uint112,uint112)")`.
+pub fn event_signature(signature: &str) -> H256 {
+    H256::from(keccak256(signature.as_bytes()))
+}
+
+/// Registry of log decoders keyed by event signature.
+#[derive(Clone)]
+pub struct LogDecoder {
+    decoders: HashMap<H256, DecodeLogFn>,
+}
+
+impl LogDecoder {
+    /// Creates a decoder without any registered events.
+    pub fn new() -> Self {
+        LogDecoder { decoders: HashMap::new() }
+    }
+
+    /// Registers the decoder for an event, given its canonical signature, e.g.
+    /// `"Sync(uint112,uint112)"`. Replaces any decoder previously registered for it.
+    pub fn register(&mut self, signature: &str, decode: DecodeLogFn) {
+        self.decoders
+            .insert(event_signature(signature), decode);
+    }
+
+    /// Whether there is a decoder for the log's event.
+    pub fn can_decode(&self, log: &Log) -> bool {
+        log.topics
+            .first()
+            .is_some_and(|signature| self.decoders.contains_key(signature))
+    }
+
+    /// Decodes a log into a protocol event and its metadata.
+    pub fn decode(
+        &self,
+        log: &Log,
+    ) -> Result<(Box<dyn ProtocolEvent>, EVMLogMeta), LogDecodingError> {
+        let signature = topic(log, 0)?;
+        let decode = self
+            .decoders
+            .get(&signature)
+            .ok_or(LogDecodingError::UnknownEvent(signature))?;
+        let event = decode(log)?;
+        Ok((event, EVMLogMeta::try_from(log)?))
+    }
+}
+
+impl Default for LogDecoder {
+    /// Creates a decoder for the events of all protocols supported by this crate.
+    fn default() -> Self {
+        let mut decoder = LogDecoder::new();
+        uniswap_v2::events::register_log_decoders(&mut decoder);
+        uniswap_v3::events::register_log_decoders(&mut decoder);
+        decoder
+    }
+}
+
+impl std::fmt::Debug for LogDecoder {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("LogDecoder")
+            .field("events", &self.decoders.keys())
+            .finish()
+    }
+}
+
+/// Returns the topic at `index`, where index 0 is the event signature.
+pub fn topic(log: &Log, index: usize) -> Result<H256, LogDecodingError> {
+    log.topics
+        .get(index)
+        .copied()
+        .ok_or_else(|| LogDecodingError::MissingAttribute(format!("topic {}", index)))
+}
+
+/// Decodes an indexed `int24` from the topic at `index`.
+pub fn topic_to_i32(log: &Log, index: usize) -> Result<i32, LogDecodingError> {
+    let topic = topic(log, index)?;
+    // values are sign extended to 32 bytes, so the last 4 bytes hold the two's complement
+    Ok(i32::from_be_bytes(topic.0[28..].try_into().unwrap()))
+}
+
+/// Decodes the non-indexed parameters of a log.
+pub fn decode_data(log: &Log, params: &[ParamType]) -> Result<Vec<Token>, LogDecodingError> {
+    decode(params, &log.data).map_err(|err| LogDecodingError::InvalidData(err.to_string()))
+}
+
+/// Returns the value of a decoded `uint` or `int` token. For `int`, this is the two's complement.
+pub fn token_to_u256(token: &Token) -> Result<U256, LogDecodingError> {
+    match token {
+        Token::Uint(value) | Token::Int(value) => Ok(*value),
+        _ => Err(LogDecodingError::InvalidData(format!("Expected a number, got {:?}", token))),
+    }
+}
+
+/// Returns the value of a decoded `int24` token.
+pub fn token_to_i32(token: &Token) -> Result<i32, LogDecodingError> {
+    Ok(token_to_u256(token)?.low_u32() as i32)
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::{
+        abi::encode,
+        types::{H160, U64},
+    };
+
+    use crate::protocol::{uniswap_v2::events::UniswapV2Sync, uniswap_v3::events::UniswapV3Event};
+
+    use super::*;
+
+    fn log(topics: Vec<H256>, data: Vec<Token>) -> Log {
+        Log {
+            address: H160::from_low_u64_be(1),
+            topics,
+            data: encode(&data).into(),
+            block_number: Some(U64::from(10)),
+            block_hash: Some(H256::from_low_u64_be(2)),
+            transaction_index: Some(U64::from(3)),
+            transaction_hash: Some(H256::from_low_u64_be(4)),
+            log_index: Some(U256::from(5)),
+            ..Default::default()
+        }
+    }
+
+    fn tick_topic(tick: i32) -> H256 {
+        let mut topic = if tick < 0 { [0xff; 32] } else { [0; 32] };
+        topic[28..].copy_from_slice(&tick.to_be_bytes());
+        H256::from(topic)
+    }
+
+    #[test]
+    fn test_decode_sync() {
+        let log = log(
+            vec![event_signature("Sync(uint112,uint112)")],
+            vec![Token::Uint(U256::from(100)), Token::Uint(U256::from(200))],
+        );
+
+        let (event, meta) = LogDecoder::default()
+            .decode(&log)
+            .unwrap();
+
+        let sync = event
+            .as_any()
+            .downcast_ref::<UniswapV2Sync>()
+            .unwrap();
+        assert_eq!(sync.reserve0, U256::from(100));
+        assert_eq!(sync.reserve1, U256::from(200));
+        assert_eq!(meta.from, H160::from_low_u64_be(1));
+        assert_eq!(meta.index(), (10, 5));
+    }
+
+    #[test]
+    fn test_decode_v3_mint() {
+        let log = log(
+            vec![
+                event_signature("Mint(address,address,int24,int24,uint128,uint256,uint256)"),
+                H256::from(H160::from_low_u64_be(7)),
+                tick_topic(-600),
+                tick_topic(1200),
+            ],
+            vec![
+                Token::Address(H160::from_low_u64_be(7)),
+                Token::Uint(U256::from(1000)),
+                Token::Uint(U256::from(1)),
+                Token::Uint(U256::from(2)),
+            ],
+        );
+
+        let (event, _) = LogDecoder::default()
+            .decode(&log)
+            .unwrap();
+
+        match event
+            .as_any()
+            .downcast_ref::<UniswapV3Event>()
+            .unwrap()
+        {
+            UniswapV3Event::Mint(data) => {
+                assert_eq!(data.tick_lower, -600);
+                assert_eq!(data.tick_upper, 1200);
+                assert_eq!(data.amount, 1000);
+            }
+            other => panic!("Unexpected event {:?}", other),
+        }
+    }
+
+    #[test]
+    fn test_decode_v3_set_fee_protocol_overflow() {
+        let log = log(
+            vec![event_signature("SetFeeProtocol(uint8,uint8,uint8,uint8)")],
+            vec![
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::zero()),
+                Token::Uint(U256::from(4)),
+                Token::Uint(U256::from(260)),
+            ],
+        );
+
+        let res = LogDecoder::default().decode(&log);
+
+        assert!(matches!(res, Err(LogDecodingError::InvalidData(_))));
+    }
+
+    #[test]
+    fn test_decode_v3_observation_cardinality_overflow() {
+        let log = log(
+            vec![event_signature("IncreaseObservationCardinalityNext(uint16,uint16)")],
+            vec![Token::Uint(U256::from(1)), Token::Uint(U256::from(65_537))],
+        );
+
+        let res = LogDecoder::default().decode(&log);
+
+        assert!(matches!(res, Err(LogDecodingError::InvalidData(_))));
+    }
+
+    #[test]
+    fn test_decode_unknown_event() {
+        let log = log(vec![event_signature("Transfer(address,address,uint256)")], vec![]);
+        let decoder = LogDecoder::default();
+
+        assert!(!decoder.can_decode(&log));
+        assert!(matches!(decoder.decode(&log), Err(LogDecodingError::UnknownEvent(_))));
+    }
+
+    #[test]
+    fn test_decode_pending_log() {
+        let mut log = log(
+            vec![event_signature("Sync(uint112,uint112)")],
+            vec![Token::Uint(U256::from(100)), Token::Uint(U256::from(200))],
+        );
+        log.block_number = None;
+
+        let res = LogDecoder::default().decode(&log);
+
+        assert!(matches!(res, Err(LogDecodingError::MissingAttribute(_))));
+    }
+}
@@ -5,8 +5,11 @@ use tycho_core::Bytes;
 
 pub mod errors;
 pub mod events;
+pub mod log_decoder;
 pub mod models;
+pub mod snapshot;
 pub mod state;
+pub mod state_tracker;
 pub mod uniswap_v2;
 pub mod uniswap_v3;
 pub mod vm;
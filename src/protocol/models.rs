@@ -5,7 +5,10 @@
 //! represents a trading pair with its properties and corresponding state.
 //!
 //! Additionally, it contains the `GetAmountOutResult` struct, which
-//! represents the result of getting the amount out of a trading pair.
+//! represents the result of getting the amount out of a trading pair, and
+//! the `QuoteResult` struct, its lightweight counterpart without the
+//! resulting state. `PartialFillResult` is the result of a trade that
+//! could only be executed up to the pool's sell limit.
 //!
 //! The `ProtocolComponent` struct has two fields: `address` and `tokens`.
 //! `address` is the address of the trading pair and `tokens` is a vector
@@ -92,3 +95,61 @@ impl GetAmountOutResult {
         self.gas += other.gas;
     }
 }
+
+/// PartialFillResult struct represents the part of a trade that a pool can execute when the
+/// requested sell amount exceeds its sell limit
+///
+/// # Fields
+///
+/// * `sell_amount`: U256, the amount the trader sells, i.e. the pool's sell limit plus the sell tax
+///   of the token
+/// * `amount`: U256, the amount bought for `sell_amount`
+/// * `gas`: U256, the gas of the partial trade
+/// * `new_state`: the state of the pool after the partial trade
+#[derive(Debug)]
+pub struct PartialFillResult {
+    pub sell_amount: U256,
+    pub amount: U256,
+    pub gas: U256,
+    pub new_state: Box<dyn ProtocolSim>,
+}
+
+impl PartialFillResult {
+    /// Constructs a new PartialFillResult struct
+    pub fn new(
+        sell_amount: U256,
+        amount: U256,
+        gas: U256,
+        new_state: Box<dyn ProtocolSim>,
+    ) -> Self {
+        PartialFillResult { sell_amount, amount, gas, new_state }
+    }
+}
+
+/// QuoteResult struct represents the result of quoting the amount out of a trading pair
+///
+/// Unlike `GetAmountOutResult` it does not carry the state resulting from the trade, which makes
+/// it cheap to produce when the new state is not needed (e.g. during route searches).
+///
+/// # Fields
+///
+/// * `amount`: U256, the amount of the trading pair
+/// * `gas`: U256, the gas of the trading pair
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QuoteResult {
+    pub amount: U256,
+    pub gas: U256,
+}
+
+impl QuoteResult {
+    /// Constructs a new QuoteResult struct with the given amount and gas
+    pub fn new(amount: U256, gas: U256) -> Self {
+        QuoteResult { amount, gas }
+    }
+}
+
+impl From<GetAmountOutResult> for QuoteResult {
+    fn from(value: GetAmountOutResult) -> Self {
+        QuoteResult { amount: value.amount, gas: value.gas }
+    }
+}
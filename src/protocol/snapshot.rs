@@ -0,0 +1,359 @@
+//! Serializable protocol state snapshots
+//!
+//! A `StateSnapshot` wraps the serialized state of a pool together with the protocol it belongs
+//! to, so that a `Box<dyn ProtocolSim>` can be persisted and restored without knowing its
+//! concrete type upfront, e.g. for warm restarts or to share states between services.
+//!
+//! States are (de)serialized through a `SnapshotRegistry`, which maps protocol names to state
+//! types. The default registry knows about the states of all native protocols of this crate;
+//! third-party `ProtocolSim` implementations can register themselves as long as they implement
+//! `Serialize` and `Deserialize`.
+//!
+//! How the state inside a snapshot is encoded is set by the registry's `SnapshotFormat`. The
+//! default `Json` format keeps states as JSON values, which only formats that are
+//! self-describing can write. To persist snapshots with other formats, such as bincode, use
+//! `JsonText` or a format of your own.
+//!
+//! VM pools hold a simulation engine and can't be restored synchronously, so `VMPoolState` is not
+//! registered. Persist its `VMPoolState::descriptor` instead and restore it with
+//! `VMPoolState::from_descriptor`.
+//!
+//! # Examples
+//! ```
+//! use ethers::types::U256;
+//! use tycho_simulation::protocol::{
+//!     snapshot::SnapshotRegistry, state::ProtocolSim, uniswap_v2::state::UniswapV2State,
+//! };
+//!
+//! let registry = SnapshotRegistry::default();
+//! let state: Box<dyn ProtocolSim> =
+//!     Box::new(UniswapV2State::new(U256::from(1000), U256::from(2000)));
+//!
+//! let json = registry.to_json(state.as_ref()).unwrap();
+//! let restored = registry.from_json(&json).unwrap();
+//! assert!(restored.eq(state.as_ref()));
+//! ```
+use std::{
+    any::{Any, TypeId},
+    collections::HashMap,
+    marker::PhantomData,
+};
+
+use serde::{de::DeserializeOwned, Deserialize, Serialize};
+use serde_json::Value;
+
+use super::{
+    errors::StateSnapshotError, state::ProtocolSim, uniswap_v2::state::UniswapV2State,
+    uniswap_v3::state::UniswapV3State,
+};
+
+/// Version of the snapshot format, increased on breaking changes of the serialized states
+pub const SNAPSHOT_VERSION: u32 = 1;
+
+/// The serialized state of a pool, tagged with its protocol.
+#[derive(Debug, Clone, PartialEq, Serialize, Deserialize)]
+pub struct StateSnapshot<P = Value> {
+    /// Version of the snapshot format
+    pub version: u32,
+    /// Name of the protocol the state was registered with, e.g. `uniswap_v2`
+    pub protocol: String,
+    /// The serialized state, encoded by the registry's `SnapshotFormat`
+    pub state: P,
+}
+
+/// Encoding of the states inside snapshots
+pub trait SnapshotFormat {
+    /// The encoded state
+    type Payload: Serialize + DeserializeOwned;
+
+    fn encode<T: Serialize>(state: &T) -> Result<Self::Payload, StateSnapshotError>;
+
+    fn decode<T: DeserializeOwned>(payload: Self::Payload) -> Result<T, StateSnapshotError>;
+}
+
+/// Encodes states as JSON values. Snapshots can only be written with self-describing formats.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct Json;
+
+impl SnapshotFormat for Json {
+    type Payload = Value;
+
+    fn encode<T: Serialize>(state: &T) -> Result<Value, StateSnapshotError> {
+        Ok(serde_json::to_value(state)?)
+    }
+
+    fn decode<T: DeserializeOwned>(payload: Value) -> Result<T, StateSnapshotError> {
+        Ok(serde_json::from_value(payload)?)
+    }
+}
+
+/// Encodes states as JSON strings. Snapshots can be written with any format, including ones that
+/// are not self-describing.
+#[derive(Debug, Clone, Copy, Default)]
+pub struct JsonText;
+
+impl SnapshotFormat for JsonText {
+    type Payload = String;
+
+    fn encode<T: Serialize>(state: &T) -> Result<String, StateSnapshotError> {
+        Ok(serde_json::to_string(state)?)
+    }
+
+    fn decode<T: DeserializeOwned>(payload: String) -> Result<T, StateSnapshotError> {
+        Ok(serde_json::from_str(&payload)?)
+    }
+}
+
+type SerializeStateFn<P> = fn(&dyn ProtocolSim) -> Result<P, StateSnapshotError>;
+type DeserializeStateFn<P> = fn(P) -> Result<Box<dyn ProtocolSim>, StateSnapshotError>;
+
+fn serialize_state<T, F>(state: &dyn ProtocolSim) -> Result<F::Payload, StateSnapshotError>
+where
+    T: ProtocolSim + Serialize,
+    F: SnapshotFormat,
+{
+    let state = state
+        .as_any()
+        .downcast_ref::<T>()
+        .ok_or(StateSnapshotError::UnregisteredType())?;
+    F::encode(state)
+}
+
+fn deserialize_state<T, F>(payload: F::Payload) -> Result<Box<dyn ProtocolSim>, StateSnapshotError>
+where
+    T: ProtocolSim + DeserializeOwned,
+    F: SnapshotFormat,
+{
+    Ok(Box::new(F::decode::<T>(payload)?))
+}
+
+/// Registry of the state types that can be snapshotted, keyed by protocol name. States are
+/// encoded with the format `F`.
+pub struct SnapshotRegistry<F: SnapshotFormat = Json> {
+    deserializers: HashMap<String, DeserializeStateFn<F::Payload>>,
+    serializers: HashMap<TypeId, (String, SerializeStateFn<F::Payload>)>,
+    format: PhantomData<F>,
+}
+
+impl SnapshotRegistry {
+    /// Creates a registry without any registered state types.
+    pub fn new() -> Self {
+        SnapshotRegistry::with_format()
+    }
+}
+
+impl<F: SnapshotFormat> SnapshotRegistry<F> {
+    /// Creates a registry without any registered state types, encoding states with `F`.
+    pub fn with_format() -> Self {
+        SnapshotRegistry {
+            deserializers: HashMap::new(),
+            serializers: HashMap::new(),
+            format: PhantomData,
+        }
+    }
+
+    /// Creates a registry for the states of all native protocols of this crate, encoding states
+    /// with `F`.
+    pub fn native() -> Self {
+        let mut registry = Self::with_format();
+        registry.register::<UniswapV2State>("uniswap_v2");
+        registry.register::<UniswapV3State>("uniswap_v3");
+        registry
+    }
+
+    /// Registers a state type under the given protocol name. Replaces any type previously
+    /// registered under the name.
+    ///
+    /// The name is part of the snapshots, so it must not change once snapshots were persisted.
+    pub fn register<T>(&mut self, protocol: &str)
+    where
+        T: ProtocolSim + Serialize + DeserializeOwned,
+    {
+        self.deserializers
+            .insert(protocol.to_string(), deserialize_state::<T, F>);
+        self.serializers
+            .insert(TypeId::of::<T>(), (protocol.to_string(), serialize_state::<T, F>));
+    }
+
+    /// Creates a snapshot of a state whose type is registered.
+    ///
+    /// Returns `StateSnapshotError::UnregisteredType` for VM pools, see the module documentation.
+    pub fn snapshot(
+        &self,
+        state: &dyn ProtocolSim,
+    ) -> Result<StateSnapshot<F::Payload>, StateSnapshotError> {
+        let (protocol, serialize) = self
+            .serializers
+            .get(&Any::type_id(state.as_any()))
+            .ok_or(StateSnapshotError::UnregisteredType())?;
+        Ok(StateSnapshot {
+            version: SNAPSHOT_VERSION,
+            protocol: protocol.clone(),
+            state: serialize(state)?,
+        })
+    }
+
+    /// Restores the state contained in a snapshot.
+    pub fn restore(
+        &self,
+        snapshot: StateSnapshot<F::Payload>,
+    ) -> Result<Box<dyn ProtocolSim>, StateSnapshotError> {
+        if snapshot.version != SNAPSHOT_VERSION {
+            return Err(StateSnapshotError::UnsupportedVersion(snapshot.version));
+        }
+        let deserialize = self
+            .deserializers
+            .get(&snapshot.protocol)
+            .ok_or(StateSnapshotError::UnknownProtocol(snapshot.protocol))?;
+        deserialize(snapshot.state)
+    }
+
+    /// Serializes a state into a JSON snapshot.
+    pub fn to_json(&self, state: &dyn ProtocolSim) -> Result<String, StateSnapshotError> {
+        Ok(serde_json::to_string(&self.snapshot(state)?)?)
+    }
+
+    /// Restores a state from a JSON snapshot.
+    pub fn from_json(&self, json: &str) -> Result<Box<dyn ProtocolSim>, StateSnapshotError> {
+        self.restore(serde_json::from_str(json)?)
+    }
+}
+
+impl<F: SnapshotFormat> Clone for SnapshotRegistry<F> {
+    fn clone(&self) -> Self {
+        SnapshotRegistry {
+            deserializers: self.deserializers.clone(),
+            serializers: self.serializers.clone(),
+            format: PhantomData,
+        }
+    }
+}
+
+impl Default for SnapshotRegistry {
+    /// Creates a registry for the states of all native protocols of this crate.
+    fn default() -> Self {
+        SnapshotRegistry::native()
+    }
+}
+
+impl<F: SnapshotFormat> std::fmt::Debug for SnapshotRegistry<F> {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        f.debug_struct("SnapshotRegistry")
+            .field("protocols", &self.deserializers.keys())
+            .finish()
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use ethers::types::U256;
+
+    use crate::protocol::uniswap_v3::{enums::FeeAmount, tick_list::TickInfo};
+
+    use super::*;
+
+    fn v3_state() -> UniswapV3State {
+        UniswapV3State::new(
+            8330443394424070888454257,
+            U256::from_dec_str("188562464004052255423565206602").unwrap(),
+            FeeAmount::Medium,
+            17342,
+            vec![
+                TickInfo::new(0, 8330443394424070888454257),
+                TickInfo::new(46080, -8330443394424070888454257),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_roundtrip_native_states() {
+        let registry = SnapshotRegistry::default();
+        let v2 = UniswapV2State::new(U256::from(1000), U256::from(2000)).with_cumulative_prices(
+            U256::from(10),
+            U256::from(20),
+            1000,
+        );
+        let v3 = v3_state();
+
+        let restored_v2 = registry
+            .from_json(&registry.to_json(&v2).unwrap())
+            .unwrap();
+        let restored_v3 = registry
+            .from_json(&registry.to_json(&v3).unwrap())
+            .unwrap();
+
+        assert_eq!(
+            restored_v2
+                .as_any()
+                .downcast_ref::<UniswapV2State>(),
+            Some(&v2)
+        );
+        assert_eq!(
+            restored_v3
+                .as_any()
+                .downcast_ref::<UniswapV3State>(),
+            Some(&v3)
+        );
+    }
+
+    #[test]
+    fn test_snapshot_format() {
+        let snapshot = SnapshotRegistry::default()
+            .snapshot(&v3_state())
+            .unwrap();
+
+        assert_eq!(snapshot.version, SNAPSHOT_VERSION);
+        assert_eq!(snapshot.protocol, "uniswap_v3");
+        // u128 values don't fit into JSON numbers and are written as strings
+        assert_eq!(snapshot.state["liquidity"], "8330443394424070888454257");
+    }
+
+    #[test]
+    fn test_roundtrip_json_text_snapshot() {
+        let registry = SnapshotRegistry::<JsonText>::native();
+        let state = v3_state();
+
+        let snapshot = registry.snapshot(&state).unwrap();
+        assert!(snapshot
+            .state
+            .contains("\"liquidity\":\"8330443394424070888454257\""));
+        let restored = registry.restore(snapshot).unwrap();
+
+        assert_eq!(
+            restored
+                .as_any()
+                .downcast_ref::<UniswapV3State>(),
+            Some(&state)
+        );
+    }
+
+    #[test]
+    fn test_register_custom_protocol() {
+        let mut registry = SnapshotRegistry::new();
+        let state = UniswapV2State::new(U256::from(1000), U256::from(2000));
+        assert!(matches!(registry.snapshot(&state), Err(StateSnapshotError::UnregisteredType())));
+
+        registry.register::<UniswapV2State>("my_fork");
+        let snapshot = registry.snapshot(&state).unwrap();
+
+        assert_eq!(snapshot.protocol, "my_fork");
+        assert!(registry
+            .restore(snapshot)
+            .unwrap()
+            .eq(&state));
+    }
+
+    #[test]
+    fn test_restore_errors() {
+        let registry = SnapshotRegistry::default();
+        let snapshot = registry
+            .snapshot(&UniswapV2State::new(U256::from(1000), U256::from(2000)))
+            .unwrap();
+
+        let res = registry.restore(StateSnapshot { version: 0, ..snapshot.clone() });
+        assert!(matches!(res, Err(StateSnapshotError::UnsupportedVersion(0))));
+
+        let res = registry.restore(StateSnapshot { protocol: "unknown".into(), ..snapshot });
+        assert!(matches!(res, Err(StateSnapshotError::UnknownProtocol(_))));
+    }
+}
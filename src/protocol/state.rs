@@ -8,6 +8,9 @@
 //!  - `fee`: Returns the protocol's fee as a ratio.
 //!  - `spot_price`: Returns the current spot price between two tokens.
 //!  - `get_amount_out`: Returns the amount of output tokens given an amount of input tokens.
+//!  - `quote_amount_out`: Same as `get_amount_out`, but without building the resulting state.
+//!  - `get_amounts_out`: Quotes several amounts in of the same trade at once.
+//!  - `get_limits`: Returns the maximum amounts that can be traded between two tokens.
 //!  - `delta_transition`: Applies a state delta to the protocol sim.
 //!  - `event_transition`: Applies an event transition to the protocol sim.
 //!  - `clone_box`: Clones the protocol sim as a trait object.
@@ -52,7 +55,7 @@ use crate::{
     protocol::{
         errors::{SimulationError, TransitionError},
         events::{EVMLogMeta, LogIndex},
-        models::GetAmountOutResult,
+        models::{GetAmountOutResult, QuoteResult},
     },
 };
 
@@ -101,6 +104,86 @@ pub trait ProtocolSim: std::fmt::Debug + Send + Sync + 'static {
         token_out: &ERC20Token,
     ) -> Result<GetAmountOutResult, SimulationError>;
 
+    /// Returns the amount out given an amount in and input/output tokens, without computing the
+    /// state resulting from the trade.
+    ///
+    /// Use this over `get_amount_out` whenever the new state is not needed, e.g. when searching
+    /// over many candidate amounts. The default implementation delegates to `get_amount_out` and
+    /// discards the new state; implementations should override it if they can skip building it.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount_in` - The amount in of the input token.
+    /// * `token_in` - The input token ERC20 token.
+    /// * `token_out` - The output token ERC20 token.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing a `QuoteResult` struct on success or a `SimulationError` on failure.
+    fn quote_amount_out(
+        &self,
+        amount_in: U256,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<QuoteResult, SimulationError> {
+        self.get_amount_out(amount_in, token_in, token_out)
+            .map(QuoteResult::from)
+    }
+
+    /// Quotes the amount out for each of the given amounts in, e.g. to build a depth curve.
+    ///
+    /// The default implementation calls `quote_amount_out` for every amount; implementations
+    /// should override it if they can share work between the amounts. Passing the amounts sorted
+    /// in ascending order allows them to do so.
+    ///
+    /// # Arguments
+    ///
+    /// * `amounts_in` - The amounts in of the input token.
+    /// * `token_in` - The input token ERC20 token.
+    /// * `token_out` - The output token ERC20 token.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing the result of quoting each amount in, in the same order, so that an
+    /// amount the pool can't take (e.g. one above its sell limit) doesn't fail the others. The
+    /// outer `SimulationError` is only returned for failures shared by all amounts.
+    fn get_amounts_out(
+        &self,
+        amounts_in: &[U256],
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<Vec<Result<QuoteResult, SimulationError>>, SimulationError> {
+        Ok(amounts_in
+            .iter()
+            .map(|amount_in| self.quote_amount_out(*amount_in, token_in, token_out))
+            .collect())
+    }
+
+    /// Returns the maximum amount of `token_in` the pool can take and the maximum amount of
+    /// `token_out` it can give out in a single trade.
+    ///
+    /// Use these as upper bounds before searching over trade amounts: selling more than `max_in`
+    /// either fails or is capped. Both amounts are the ones the trader sends and receives, i.e.
+    /// they account for the transfer taxes of the tokens.
+    ///
+    /// The default implementation returns `SimulationError::NotImplemented`.
+    ///
+    /// # Arguments
+    ///
+    /// * `token_in` - The input token ERC20 token.
+    /// * `token_out` - The output token ERC20 token.
+    ///
+    /// # Returns
+    ///
+    /// A `Result` containing `(max_in, max_out)` on success or a `SimulationError` on failure.
+    fn get_limits(
+        &self,
+        _token_in: &ERC20Token,
+        _token_out: &ERC20Token,
+    ) -> Result<(U256, U256), SimulationError> {
+        Err(SimulationError::NotImplemented("get_limits".to_string()))
+    }
+
     /// Decodes and applies a protocol state delta to the state
     ///
     /// Will error if the provided delta is missing any required attributes or if any of the
@@ -0,0 +1,427 @@
+//! Event-sourced state maintenance
+//!
+//! The `LogStateTracker` keeps a set of protocol states up to date purely from EVM logs, without
+//! depending on the Tycho indexer. Logs are fed block by block; each log is routed to the state of
+//! the pool that emitted it, decoded with a `LogDecoder` and applied through
+//! `ProtocolSim::event_transition`.
+//!
+//! To handle chain reorganisations, the tracker checkpoints the states modified by each block, so
+//! that it can roll back to the state before any of the most recent blocks. Blocks are linked by
+//! their parent hash, so that a block that doesn't extend the tracked chain is rejected instead of
+//! being applied on top of states it doesn't belong to.
+use std::collections::{hash_map::Entry, HashMap, HashSet, VecDeque};
+
+use ethers::types::{Log, H160, H256};
+use tracing::debug;
+
+use super::{errors::LogTrackingError, log_decoder::LogDecoder, state::ProtocolSim};
+
+/// The states of the pools modified by a block, as they were before the block was applied.
+#[derive(Debug)]
+struct BlockCheckpoint {
+    number: u64,
+    hash: H256,
+    parent_hash: H256,
+    previous_states: HashMap<H160, Box<dyn ProtocolSim>>,
+}
+
+#[derive(Debug)]
+pub struct LogStateTracker {
+    decoder: LogDecoder,
+    states: HashMap<H160, Box<dyn ProtocolSim>>,
+    /// Checkpoints of the most recent blocks, oldest first
+    checkpoints: VecDeque<BlockCheckpoint>,
+    /// Number of blocks that can be rolled back
+    max_reorg_depth: usize,
+    /// Number and hash of the most recently applied block
+    latest_block: Option<(u64, H256)>,
+}
+
+impl LogStateTracker {
+    /// Creates a tracker without any states.
+    ///
+    /// ## Parameters
+    /// - `decoder`: decoder for the events emitted by the tracked pools
+    /// - `max_reorg_depth`: the number of most recent blocks that can be rolled back
+    pub fn new(decoder: LogDecoder, max_reorg_depth: usize) -> Self {
+        LogStateTracker {
+            decoder,
+            states: HashMap::new(),
+            checkpoints: VecDeque::new(),
+            max_reorg_depth,
+            latest_block: None,
+        }
+    }
+
+    /// Starts tracking the state of the pool at `address`, replacing any state tracked for it.
+    pub fn insert_state(&mut self, address: H160, state: Box<dyn ProtocolSim>) {
+        self.states.insert(address, state);
+    }
+
+    /// Stops tracking the state of the pool at `address` and returns it.
+    pub fn remove_state(&mut self, address: &H160) -> Option<Box<dyn ProtocolSim>> {
+        self.states.remove(address)
+    }
+
+    pub fn get_state(&self, address: &H160) -> Option<&dyn ProtocolSim> {
+        self.states
+            .get(address)
+            .map(|state| state.as_ref())
+    }
+
+    pub fn states(&self) -> &HashMap<H160, Box<dyn ProtocolSim>> {
+        &self.states
+    }
+
+    /// Returns the number and hash of the block the states are at, if any block was applied.
+    pub fn latest_block(&self) -> Option<(u64, H256)> {
+        self.latest_block
+    }
+
+    /// Applies the logs of a block, ordered by log index, to the tracked states.
+    ///
+    /// The block has to follow the latest applied block, or replace one of the blocks that can
+    /// still be rolled back, in which case the chain reorganised and the states are rolled back to
+    /// before the replaced block. Either way, `parent_hash` has to match the hash of the block it
+    /// is applied on. Logs emitted by untracked addresses and logs of unknown events are skipped.
+    ///
+    /// If the block can't be applied, the states and checkpoints are left as they were.
+    ///
+    /// Returns the addresses of the pools whose state changed.
+    pub fn apply_block(
+        &mut self,
+        number: u64,
+        hash: H256,
+        parent_hash: H256,
+        logs: &[Log],
+    ) -> Result<HashSet<H160>, LogTrackingError> {
+        let reorg = self.check_parent(number, parent_hash)?;
+
+        // Apply the logs on copies of the states the block builds on, so that a failing log
+        // leaves all states as they were before the block.
+        let mut updated: HashMap<H160, Box<dyn ProtocolSim>> = HashMap::new();
+        for log in logs {
+            if log.removed == Some(true) || !self.decoder.can_decode(log) {
+                continue;
+            }
+            let state = match updated.entry(log.address) {
+                Entry::Occupied(entry) => entry.into_mut(),
+                Entry::Vacant(entry) => match self.state_before(&log.address, number) {
+                    Some(state) => entry.insert(state.clone_box()),
+                    None => continue,
+                },
+            };
+            let (event, log_meta) = self.decoder.decode(log)?;
+            if log_meta.block_number != number {
+                return Err(LogTrackingError::UnexpectedBlock {
+                    expected: number,
+                    actual: log_meta.block_number,
+                });
+            }
+            state
+                .event_transition(event, &log_meta)
+                .map_err(|err| {
+                    LogTrackingError::TransitionError(log.address, format!("{:?}", err))
+                })?;
+        }
+
+        if reorg {
+            debug!(number, ?hash, "Reorg detected, reverting states");
+            self.revert_from(number)?;
+        }
+        let mut previous_states = HashMap::new();
+        for (address, state) in updated {
+            if let Some(previous) = self.states.insert(address, state) {
+                previous_states.insert(address, previous);
+            }
+        }
+        let changed = previous_states
+            .keys()
+            .copied()
+            .collect();
+        self.checkpoints
+            .push_back(BlockCheckpoint { number, hash, parent_hash, previous_states });
+        while self.checkpoints.len() > self.max_reorg_depth {
+            self.checkpoints.pop_front();
+        }
+        self.latest_block = Some((number, hash));
+        Ok(changed)
+    }
+
+    /// Rolls the states back to how they were after block `number`, discarding all later blocks.
+    ///
+    /// Fails if the blocks after `number` can't all be rolled back anymore, in which case the
+    /// states have to be rebuilt from a snapshot.
+    pub fn revert_to(&mut self, number: u64) -> Result<(), LogTrackingError> {
+        match number.checked_add(1) {
+            Some(first_reverted) => self.revert_from(first_reverted),
+            // no block can follow the last representable block number
+            None => Ok(()),
+        }
+    }
+
+    /// Checks that block `number` with parent `parent_hash` can be applied on the tracked chain.
+    ///
+    /// Returns whether applying it requires rolling back already applied blocks.
+    fn check_parent(&self, number: u64, parent_hash: H256) -> Result<bool, LogTrackingError> {
+        let Some((latest_number, latest_hash)) = self.latest_block else {
+            return Ok(false);
+        };
+        let (reorg, expected) = if latest_number.checked_add(1) == Some(number) {
+            (false, latest_hash)
+        } else if number <= latest_number {
+            let checkpoint = self
+                .checkpoint(number)
+                .ok_or(LogTrackingError::ReorgTooDeep(number))?;
+            (true, checkpoint.parent_hash)
+        } else {
+            return Err(LogTrackingError::MissingBlocks { latest: latest_number, actual: number });
+        };
+        if parent_hash != expected {
+            return Err(LogTrackingError::ParentHashMismatch {
+                number,
+                expected,
+                actual: parent_hash,
+            });
+        }
+        Ok(reorg)
+    }
+
+    fn checkpoint(&self, number: u64) -> Option<&BlockCheckpoint> {
+        self.checkpoints
+            .iter()
+            .find(|checkpoint| checkpoint.number == number)
+    }
+
+    /// Returns the state of the pool at `address` as it was before block `number`.
+    fn state_before(&self, address: &H160, number: u64) -> Option<&dyn ProtocolSim> {
+        self.checkpoints
+            .iter()
+            .filter(|checkpoint| checkpoint.number >= number)
+            .find_map(|checkpoint| checkpoint.previous_states.get(address))
+            .or_else(|| self.states.get(address))
+            .map(|state| state.as_ref())
+    }
+
+    /// Rolls back block `first_reverted` and all later blocks.
+    fn revert_from(&mut self, first_reverted: u64) -> Result<(), LogTrackingError> {
+        match self.latest_block {
+            Some((latest, _)) if latest >= first_reverted => {}
+            _ => return Ok(()),
+        }
+        if !self
+            .checkpoints
+            .front()
+            .is_some_and(|oldest| oldest.number <= first_reverted)
+        {
+            return Err(LogTrackingError::ReorgTooDeep(first_reverted));
+        }
+
+        while self
+            .checkpoints
+            .back()
+            .is_some_and(|latest| latest.number >= first_reverted)
+        {
+            let checkpoint = self.checkpoints.pop_back().unwrap();
+            self.states
+                .extend(checkpoint.previous_states);
+            self.latest_block = checkpoint
+                .number
+                .checked_sub(1)
+                .map(|number| (number, checkpoint.parent_hash));
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod tests {
+    use std::{fs, str::FromStr};
+
+    use ethers::types::U256;
+    use serde::Deserialize;
+
+    use crate::protocol::uniswap_v2::state::UniswapV2State;
+
+    use super::*;
+
+    #[derive(Deserialize)]
+    struct RecordedBlock {
+        number: u64,
+        hash: H256,
+        parent_hash: H256,
+        logs: Vec<Log>,
+    }
+
+    fn recorded_blocks() -> Vec<RecordedBlock> {
+        let data = fs::read_to_string("src/protocol/assets/uniswap_v2_sync_logs.json").unwrap();
+        serde_json::from_str(&data).unwrap()
+    }
+
+    fn pair() -> H160 {
+        H160::from_str("0xb4e16d0168e52d35cacd2c6185b44281ec28c9dc").unwrap()
+    }
+
+    fn reserves(tracker: &LogStateTracker) -> (U256, U256) {
+        let state = tracker
+            .get_state(&pair())
+            .unwrap()
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap();
+        (state.reserve0, state.reserve1)
+    }
+
+    fn tracker(max_reorg_depth: usize) -> LogStateTracker {
+        let mut tracker = LogStateTracker::new(LogDecoder::default(), max_reorg_depth);
+        tracker.insert_state(pair(), Box::new(UniswapV2State::new(U256::zero(), U256::zero())));
+        tracker
+    }
+
+    #[test]
+    fn test_apply_recorded_blocks() {
+        let mut tracker = tracker(10);
+        let blocks = recorded_blocks();
+
+        let changed = tracker
+            .apply_block(blocks[0].number, blocks[0].hash, blocks[0].parent_hash, &blocks[0].logs)
+            .unwrap();
+        assert_eq!(changed, HashSet::from([pair()]));
+        assert_eq!(reserves(&tracker), (U256::from(1000), U256::from(2000)));
+
+        tracker
+            .apply_block(blocks[1].number, blocks[1].hash, blocks[1].parent_hash, &blocks[1].logs)
+            .unwrap();
+        assert_eq!(reserves(&tracker), (U256::from(1100), U256::from(1900)));
+
+        // block 101 is replaced by a block with a different hash
+        tracker
+            .apply_block(blocks[2].number, blocks[2].hash, blocks[2].parent_hash, &blocks[2].logs)
+            .unwrap();
+        assert_eq!(reserves(&tracker), (U256::from(900), U256::from(2100)));
+
+        tracker
+            .apply_block(blocks[3].number, blocks[3].hash, blocks[3].parent_hash, &blocks[3].logs)
+            .unwrap();
+        assert_eq!(reserves(&tracker), (U256::from(960), U256::from(2040)));
+        assert_eq!(tracker.latest_block(), Some((102, blocks[3].hash)));
+    }
+
+    #[test]
+    fn test_revert_to() {
+        let mut tracker = tracker(10);
+        let blocks = recorded_blocks();
+        for block in blocks.iter().take(2) {
+            tracker
+                .apply_block(block.number, block.hash, block.parent_hash, &block.logs)
+                .unwrap();
+        }
+
+        tracker.revert_to(100).unwrap();
+        assert_eq!(reserves(&tracker), (U256::from(1000), U256::from(2000)));
+
+        tracker.revert_to(99).unwrap();
+        assert_eq!(reserves(&tracker), (U256::zero(), U256::zero()));
+        assert_eq!(tracker.latest_block(), Some((99, blocks[0].parent_hash)));
+    }
+
+    #[test]
+    fn test_reorg_too_deep() {
+        let mut tracker = tracker(1);
+        for block in recorded_blocks().iter().take(2) {
+            tracker
+                .apply_block(block.number, block.hash, block.parent_hash, &block.logs)
+                .unwrap();
+        }
+
+        let res = tracker.revert_to(99);
+
+        assert!(matches!(res, Err(LogTrackingError::ReorgTooDeep(100))));
+        assert_eq!(reserves(&tracker), (U256::from(1100), U256::from(1900)));
+    }
+
+    #[test]
+    fn test_failing_log_leaves_states_untouched() {
+        let mut tracker = tracker(10);
+        let blocks = recorded_blocks();
+        tracker
+            .apply_block(blocks[0].number, blocks[0].hash, blocks[0].parent_hash, &blocks[0].logs)
+            .unwrap();
+
+        // the logs of block 102 are fed as part of block 101
+        let res = tracker.apply_block(101, blocks[1].hash, blocks[1].parent_hash, &blocks[3].logs);
+
+        assert!(matches!(
+            res,
+            Err(LogTrackingError::UnexpectedBlock { expected: 101, actual: 102 })
+        ));
+        assert_eq!(reserves(&tracker), (U256::from(1000), U256::from(2000)));
+    }
+
+    #[test]
+    fn test_revert_to_last_block_number() {
+        let mut tracker = tracker(10);
+        let blocks = recorded_blocks();
+        tracker
+            .apply_block(blocks[0].number, blocks[0].hash, blocks[0].parent_hash, &blocks[0].logs)
+            .unwrap();
+
+        tracker.revert_to(u64::MAX).unwrap();
+
+        assert_eq!(reserves(&tracker), (U256::from(1000), U256::from(2000)));
+        assert_eq!(tracker.latest_block(), Some((100, blocks[0].hash)));
+    }
+
+    #[test]
+    fn test_reject_block_on_other_parent() {
+        let mut tracker = tracker(10);
+        let blocks = recorded_blocks();
+        for block in blocks.iter().take(2) {
+            tracker
+                .apply_block(block.number, block.hash, block.parent_hash, &block.logs)
+                .unwrap();
+        }
+
+        // block 102 builds on the replacement of block 101, which wasn't applied
+        let res = tracker.apply_block(blocks[3].number, blocks[3].hash, blocks[3].parent_hash, &[]);
+
+        assert!(matches!(res, Err(LogTrackingError::ParentHashMismatch { number: 102, .. })));
+        assert_eq!(reserves(&tracker), (U256::from(1100), U256::from(1900)));
+        assert_eq!(tracker.latest_block(), Some((101, blocks[1].hash)));
+    }
+
+    #[test]
+    fn test_reject_missing_blocks() {
+        let mut tracker = tracker(10);
+        let blocks = recorded_blocks();
+        tracker
+            .apply_block(blocks[0].number, blocks[0].hash, blocks[0].parent_hash, &blocks[0].logs)
+            .unwrap();
+
+        let res = tracker.apply_block(blocks[3].number, blocks[3].hash, blocks[3].parent_hash, &[]);
+
+        assert!(matches!(res, Err(LogTrackingError::MissingBlocks { latest: 100, actual: 102 })));
+        assert_eq!(tracker.latest_block(), Some((100, blocks[0].hash)));
+    }
+
+    #[test]
+    fn test_failing_reorg_block_keeps_replaced_block() {
+        let mut tracker = tracker(10);
+        let blocks = recorded_blocks();
+        for block in blocks.iter().take(2) {
+            tracker
+                .apply_block(block.number, block.hash, block.parent_hash, &block.logs)
+                .unwrap();
+        }
+
+        // the replacement of block 101 carries the logs of block 100
+        let res = tracker.apply_block(101, blocks[2].hash, blocks[2].parent_hash, &blocks[0].logs);
+
+        assert!(matches!(
+            res,
+            Err(LogTrackingError::UnexpectedBlock { expected: 101, actual: 100 })
+        ));
+        assert_eq!(reserves(&tracker), (U256::from(1100), U256::from(1900)));
+        assert_eq!(tracker.latest_block(), Some((101, blocks[1].hash)));
+    }
+}
@@ -1,8 +1,15 @@
 use std::any::Any;
 
-use ethers::types::U256;
+use ethers::{
+    abi::ParamType,
+    types::{Log, U256},
+};
 
-use crate::protocol::state::ProtocolEvent;
+use crate::protocol::{
+    errors::LogDecodingError,
+    log_decoder::{decode_data, token_to_u256, LogDecoder},
+    state::ProtocolEvent,
+};
 
 #[derive(Debug, Clone)]
 pub struct UniswapV2Sync {
@@ -24,3 +31,139 @@ impl ProtocolEvent for UniswapV2Sync {
         Box::new(self.clone())
     }
 }
+
+impl TryFrom<&Log> for UniswapV2Sync {
+    type Error = LogDecodingError;
+
+    // Solidity spec: event Sync(uint112 reserve0, uint112 reserve1)
+    fn try_from(log: &Log) -> Result<Self, Self::Error> {
+        let data = decode_data(log, &[ParamType::Uint(112), ParamType::Uint(112)])?;
+        Ok(UniswapV2Sync::new(token_to_u256(&data[0])?, token_to_u256(&data[1])?))
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct UniswapV2Swap {
+    pub amount0_in: U256,
+    pub amount1_in: U256,
+    pub amount0_out: U256,
+    pub amount1_out: U256,
+}
+
+impl UniswapV2Swap {
+    pub fn new(amount0_in: U256, amount1_in: U256, amount0_out: U256, amount1_out: U256) -> Self {
+        UniswapV2Swap { amount0_in, amount1_in, amount0_out, amount1_out }
+    }
+}
+
+impl ProtocolEvent for UniswapV2Swap {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn clone_box(&self) -> Box<dyn ProtocolEvent> {
+        Box::new(self.clone())
+    }
+}
+
+impl TryFrom<&Log> for UniswapV2Swap {
+    type Error = LogDecodingError;
+
+    // Solidity spec: event Swap(address indexed sender, uint amount0In, uint amount1In, uint
+    // amount0Out, uint amount1Out, address indexed to)
+    fn try_from(log: &Log) -> Result<Self, Self::Error> {
+        let data = decode_data(
+            log,
+            &[
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+            ],
+        )?;
+        Ok(UniswapV2Swap::new(
+            token_to_u256(&data[0])?,
+            token_to_u256(&data[1])?,
+            token_to_u256(&data[2])?,
+            token_to_u256(&data[3])?,
+        ))
+    }
+}
+
+/// Liquidity added to the pair
+#[derive(Debug, Clone)]
+pub struct UniswapV2Mint {
+    pub amount0: U256,
+    pub amount1: U256,
+}
+
+impl UniswapV2Mint {
+    pub fn new(amount0: U256, amount1: U256) -> Self {
+        UniswapV2Mint { amount0, amount1 }
+    }
+}
+
+impl ProtocolEvent for UniswapV2Mint {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn clone_box(&self) -> Box<dyn ProtocolEvent> {
+        Box::new(self.clone())
+    }
+}
+
+impl TryFrom<&Log> for UniswapV2Mint {
+    type Error = LogDecodingError;
+
+    // Solidity spec: event Mint(address indexed sender, uint amount0, uint amount1)
+    fn try_from(log: &Log) -> Result<Self, Self::Error> {
+        let data = decode_data(log, &[ParamType::Uint(256), ParamType::Uint(256)])?;
+        Ok(UniswapV2Mint::new(token_to_u256(&data[0])?, token_to_u256(&data[1])?))
+    }
+}
+
+/// Liquidity removed from the pair
+#[derive(Debug, Clone)]
+pub struct UniswapV2Burn {
+    pub amount0: U256,
+    pub amount1: U256,
+}
+
+impl UniswapV2Burn {
+    pub fn new(amount0: U256, amount1: U256) -> Self {
+        UniswapV2Burn { amount0, amount1 }
+    }
+}
+
+impl ProtocolEvent for UniswapV2Burn {
+    fn as_any(&self) -> &dyn Any {
+        self
+    }
+    fn clone_box(&self) -> Box<dyn ProtocolEvent> {
+        Box::new(self.clone())
+    }
+}
+
+impl TryFrom<&Log> for UniswapV2Burn {
+    type Error = LogDecodingError;
+
+    // Solidity spec: event Burn(address indexed sender, uint amount0, uint amount1, address
+    // indexed to)
+    fn try_from(log: &Log) -> Result<Self, Self::Error> {
+        let data = decode_data(log, &[ParamType::Uint(256), ParamType::Uint(256)])?;
+        Ok(UniswapV2Burn::new(token_to_u256(&data[0])?, token_to_u256(&data[1])?))
+    }
+}
+
+/// Registers the decoders for all Uniswap V2 events.
+pub fn register_log_decoders(decoder: &mut LogDecoder) {
+    decoder.register("Sync(uint112,uint112)", |log| Ok(Box::new(UniswapV2Sync::try_from(log)?)));
+    decoder.register("Swap(address,uint256,uint256,uint256,uint256,address)", |log| {
+        Ok(Box::new(UniswapV2Swap::try_from(log)?))
+    });
+    decoder.register("Mint(address,uint256,uint256)", |log| {
+        Ok(Box::new(UniswapV2Mint::try_from(log)?))
+    });
+    decoder.register("Burn(address,uint256,uint256,address)", |log| {
+        Ok(Box::new(UniswapV2Burn::try_from(log)?))
+    });
+}
@@ -1,6 +1,7 @@
 use std::any::Any;
 
 use ethers::types::U256;
+use serde::{Deserialize, Serialize};
 
 use tycho_core::dto::ProtocolStateDelta;
 
@@ -9,20 +10,58 @@ use crate::{
     protocol::{
         errors::{SimulationError, TransitionError},
         events::{check_log_idx, EVMLogMeta, LogIndex},
-        models::GetAmountOutResult,
+        models::{GetAmountOutResult, QuoteResult},
         state::{ProtocolEvent, ProtocolSim},
         BytesConvertible,
     },
     safe_math::{safe_add_u256, safe_div_u256, safe_mul_u256, safe_sub_u256},
 };
 
-use super::{events::UniswapV2Sync, reserve_price::spot_price_from_reserves};
+use super::{
+    events::{UniswapV2Burn, UniswapV2Mint, UniswapV2Swap, UniswapV2Sync},
+    reserve_price::spot_price_from_reserves,
+};
+
+/// The pair stores its reserves as `uint112`
+const MAX_RESERVE: U256 = U256([u64::MAX, (1 << 48) - 1, 0, 0]);
+
+/// Liquidity permanently locked by the first mint of a pair
+const MINIMUM_LIQUIDITY: u64 = 1000;
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+/// Result of simulating adding liquidity to (mint) or removing it from (burn) a Uniswap V2 pair.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiquidityChangeResult {
+    /// The LP tokens minted to (mint) or burned by (burn) the liquidity provider
+    pub liquidity: U256,
+    /// The amount of token0 paid into (mint) or released by (burn) the pair
+    pub amount0: U256,
+    /// The amount of token1 paid into (mint) or released by (burn) the pair
+    pub amount1: U256,
+    /// The state of the pair after the liquidity change
+    pub new_state: UniswapV2State,
+}
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UniswapV2State {
     pub reserve0: U256,
     pub reserve1: U256,
+    /// Cumulative price of token 0 in token 1 as a UQ112x112, multiplied by the seconds elapsed
+    pub price0_cumulative_last: U256,
+    /// Cumulative price of token 1 in token 0 as a UQ112x112, multiplied by the seconds elapsed
+    pub price1_cumulative_last: U256,
+    /// Timestamp of the block in which the reserves were last updated, modulo 2**32
+    pub block_timestamp_last: u32,
+    /// Total supply of LP tokens
+    pub total_supply: U256,
+    /// Whether the protocol fee is switched on, i.e. the factory's `feeTo` is set
+    pub fee_on: bool,
+    /// The product of the reserves as of the last liquidity event, if the protocol fee is on
+    pub k_last: U256,
     pub log_index: LogIndex,
+    /// The log index of the last `Sync` event and the reserves before it, used to check the
+    /// `Swap`, `Mint` or `Burn` event emitted right after it
+    #[serde(default)]
+    last_sync: Option<(LogIndex, U256, U256)>,
 }
 
 impl UniswapV2State {
@@ -35,7 +74,385 @@ impl UniswapV2State {
     /// * `reserve0` - Reserve of token 0.
     /// * `reserve1` - Reserve of token 1.
     pub fn new(reserve0: U256, reserve1: U256) -> Self {
-        UniswapV2State { reserve0, reserve1, log_index: (0, 0) }
+        UniswapV2State {
+            reserve0,
+            reserve1,
+            price0_cumulative_last: U256::zero(),
+            price1_cumulative_last: U256::zero(),
+            block_timestamp_last: 0,
+            total_supply: U256::zero(),
+            fee_on: false,
+            k_last: U256::zero(),
+            log_index: (0, 0),
+            last_sync: None,
+        }
+    }
+
+    /// Sets the price accumulators, as stored by the pair contract.
+    pub fn with_cumulative_prices(
+        mut self,
+        price0_cumulative_last: U256,
+        price1_cumulative_last: U256,
+        block_timestamp_last: u32,
+    ) -> Self {
+        self.price0_cumulative_last = price0_cumulative_last;
+        self.price1_cumulative_last = price1_cumulative_last;
+        self.block_timestamp_last = block_timestamp_last;
+        self
+    }
+
+    /// Sets the total supply of LP tokens.
+    pub fn with_total_supply(mut self, total_supply: U256) -> Self {
+        self.total_supply = total_supply;
+        self
+    }
+
+    /// Sets the protocol fee parameters, as stored by the factory (`feeTo`) and the pair
+    /// (`kLast`).
+    pub fn with_protocol_fee(mut self, fee_on: bool, k_last: U256) -> Self {
+        self.fee_on = fee_on;
+        self.k_last = k_last;
+        self
+    }
+
+    // Solidity spec: function _mintFee(uint112 _reserve0, uint112 _reserve1) private returns
+    // (bool feeOn)
+    /// Returns the LP tokens that the next liquidity event mints to `feeTo`, i.e. 1/6th of the
+    /// growth in sqrt(k) since the last liquidity event.
+    pub fn pending_protocol_fee(&self) -> Result<U256, SimulationError> {
+        if !self.fee_on || self.k_last.is_zero() {
+            return Ok(U256::zero());
+        }
+        let root_k = safe_mul_u256(self.reserve0, self.reserve1)?.integer_sqrt();
+        let root_k_last = self.k_last.integer_sqrt();
+        if root_k <= root_k_last {
+            return Ok(U256::zero());
+        }
+        let numerator = safe_mul_u256(self.total_supply, safe_sub_u256(root_k, root_k_last)?)?;
+        let denominator = safe_add_u256(safe_mul_u256(root_k, U256::from(5))?, root_k_last)?;
+        safe_div_u256(numerator, denominator)
+    }
+
+    // Solidity spec: function _addLiquidity(address tokenA, address tokenB, uint
+    // amountADesired, uint amountBDesired, uint amountAMin, uint amountBMin) internal virtual
+    // returns (uint amountA, uint amountB)
+    /// Returns the amounts to deposit for at most the desired amounts, in the ratio of the
+    /// current reserves, as the router does.
+    pub fn optimal_liquidity_amounts(
+        &self,
+        amount0_desired: U256,
+        amount1_desired: U256,
+    ) -> Result<(U256, U256), SimulationError> {
+        if self.reserve0.is_zero() && self.reserve1.is_zero() {
+            return Ok((amount0_desired, amount1_desired));
+        }
+        if self.reserve0.is_zero() || self.reserve1.is_zero() {
+            return Err(SimulationError::NoLiquidity());
+        }
+        let amount1_optimal =
+            safe_div_u256(safe_mul_u256(amount0_desired, self.reserve1)?, self.reserve0)?;
+        if amount1_optimal <= amount1_desired {
+            return Ok((amount0_desired, amount1_optimal));
+        }
+        let amount0_optimal =
+            safe_div_u256(safe_mul_u256(amount1_desired, self.reserve0)?, self.reserve1)?;
+        Ok((amount0_optimal, amount1_desired))
+    }
+
+    /// Simulates adding liquidity through the router: deposits the optimal amounts for at most
+    /// the desired amounts and mints LP tokens for them.
+    pub fn add_liquidity(
+        &self,
+        amount0_desired: U256,
+        amount1_desired: U256,
+    ) -> Result<LiquidityChangeResult, SimulationError> {
+        let (amount0, amount1) =
+            self.optimal_liquidity_amounts(amount0_desired, amount1_desired)?;
+        self.mint(amount0, amount1)
+    }
+
+    // Solidity spec: function mint(address to) external lock returns (uint liquidity)
+    /// Simulates transferring the given amounts to the pair and minting LP tokens for them.
+    ///
+    /// Amounts in excess of the reserve ratio are donated to the existing liquidity providers.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationError::NotInitialized` if the pair has reserves but no total supply
+    /// was set (see `with_total_supply`), as the LP tokens can't be computed without it.
+    pub fn mint(
+        &self,
+        amount0: U256,
+        amount1: U256,
+    ) -> Result<LiquidityChangeResult, SimulationError> {
+        let mut new_state = self.clone();
+        new_state.total_supply = safe_add_u256(self.total_supply, self.pending_protocol_fee()?)?;
+
+        let is_empty = self.reserve0.is_zero() && self.reserve1.is_zero();
+        if new_state.total_supply.is_zero() && !is_empty {
+            return Err(SimulationError::NotInitialized(
+                "Total supply of LP tokens is unknown for a pair with reserves".to_string(),
+            ));
+        }
+        let liquidity = if new_state.total_supply.is_zero() {
+            let minimum_liquidity = U256::from(MINIMUM_LIQUIDITY);
+            let root = safe_mul_u256(amount0, amount1)?.integer_sqrt();
+            if root <= minimum_liquidity {
+                return Err(SimulationError::InsufficientAmount());
+            }
+            // the minimum liquidity is minted to the zero address, i.e. locked forever
+            new_state.total_supply = minimum_liquidity;
+            root - minimum_liquidity
+        } else {
+            if self.reserve0.is_zero() || self.reserve1.is_zero() {
+                return Err(SimulationError::NoLiquidity());
+            }
+            U256::min(
+                safe_div_u256(safe_mul_u256(amount0, new_state.total_supply)?, self.reserve0)?,
+                safe_div_u256(safe_mul_u256(amount1, new_state.total_supply)?, self.reserve1)?,
+            )
+        };
+        if liquidity.is_zero() {
+            return Err(SimulationError::InsufficientAmount());
+        }
+
+        new_state.total_supply = safe_add_u256(new_state.total_supply, liquidity)?;
+        new_state.reserve0 = safe_add_u256(self.reserve0, amount0)?;
+        new_state.reserve1 = safe_add_u256(self.reserve1, amount1)?;
+        new_state.update_k_last()?;
+        Ok(LiquidityChangeResult { liquidity, amount0, amount1, new_state })
+    }
+
+    // Solidity spec: function burn(address to) external lock returns (uint amount0, uint
+    // amount1)
+    /// Simulates burning `liquidity` LP tokens for a pro-rata share of the reserves.
+    pub fn burn(&self, liquidity: U256) -> Result<LiquidityChangeResult, SimulationError> {
+        let mut new_state = self.clone();
+        new_state.total_supply = safe_add_u256(self.total_supply, self.pending_protocol_fee()?)?;
+        if liquidity > new_state.total_supply {
+            return Err(SimulationError::InvalidInput(format!(
+                "Can't burn {} LP tokens, the total supply is {}",
+                liquidity, new_state.total_supply
+            )));
+        }
+
+        let amount0 =
+            safe_div_u256(safe_mul_u256(liquidity, self.reserve0)?, new_state.total_supply)?;
+        let amount1 =
+            safe_div_u256(safe_mul_u256(liquidity, self.reserve1)?, new_state.total_supply)?;
+        if amount0.is_zero() || amount1.is_zero() {
+            return Err(SimulationError::InsufficientAmount());
+        }
+
+        new_state.total_supply = safe_sub_u256(new_state.total_supply, liquidity)?;
+        new_state.reserve0 = safe_sub_u256(self.reserve0, amount0)?;
+        new_state.reserve1 = safe_sub_u256(self.reserve1, amount1)?;
+        new_state.update_k_last()?;
+        Ok(LiquidityChangeResult { liquidity, amount0, amount1, new_state })
+    }
+
+    // Solidity spec: if (feeOn) kLast = uint(reserve0).mul(reserve1); and in _mintFee:
+    // else if (_kLast != 0) kLast = 0;
+    fn update_k_last(&mut self) -> Result<(), SimulationError> {
+        self.k_last =
+            if self.fee_on { safe_mul_u256(self.reserve0, self.reserve1)? } else { U256::zero() };
+        Ok(())
+    }
+
+    /// Returns the price accumulators as of `block_timestamp`, including the time elapsed since
+    /// the last update, as the periphery's `UniswapV2OracleLibrary.currentCumulativePrices` does.
+    pub fn current_cumulative_prices(&self, block_timestamp: u32) -> (U256, U256) {
+        let mut state = self.clone();
+        state.update_cumulative_prices(block_timestamp);
+        (state.price0_cumulative_last, state.price1_cumulative_last)
+    }
+
+    /// Simulates selling `amount_in` of `token_in` in a block with the given timestamp, updating
+    /// the price accumulators with the reserves before the swap.
+    pub fn get_amount_out_at(
+        &self,
+        amount_in: U256,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+        block_timestamp: u32,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        let zero2one = token_in.address < token_out.address;
+        let (pool_amount_in, pool_amount_out, amount_out) =
+            self.swap_amounts(amount_in, token_in, token_out)?;
+        let mut new_state = self.clone();
+        new_state.update_cumulative_prices(block_timestamp);
+        new_state.apply_swap(pool_amount_in, pool_amount_out, zero2one)?;
+        Ok(GetAmountOutResult::new(amount_out, U256::from(120_000), Box::new(new_state)))
+    }
+
+    // Solidity spec: function _update(uint balance0, uint balance1, uint112 _reserve0, uint112
+    // _reserve1) private
+    /// Accumulates the prices of the current reserves over the time elapsed since the last
+    /// update. Overflows are desired, consumers compute differences of the accumulators.
+    fn update_cumulative_prices(&mut self, block_timestamp: u32) {
+        let time_elapsed = block_timestamp.wrapping_sub(self.block_timestamp_last);
+        if time_elapsed > 0 && !self.reserve0.is_zero() && !self.reserve1.is_zero() {
+            // Solidity spec: price0CumulativeLast += uint(UQ112x112.encode(_reserve1).uqdiv(
+            // _reserve0)) * timeElapsed
+            let price0 = (self.reserve1 << 112) / self.reserve0;
+            let price1 = (self.reserve0 << 112) / self.reserve1;
+            self.price0_cumulative_last = self
+                .price0_cumulative_last
+                .overflowing_add(
+                    price0
+                        .overflowing_mul(U256::from(time_elapsed))
+                        .0,
+                )
+                .0;
+            self.price1_cumulative_last = self
+                .price1_cumulative_last
+                .overflowing_add(
+                    price1
+                        .overflowing_mul(U256::from(time_elapsed))
+                        .0,
+                )
+                .0;
+        }
+        self.block_timestamp_last = block_timestamp;
+    }
+
+    fn apply_swap(
+        &mut self,
+        amount_in: U256,
+        amount_out: U256,
+        zero2one: bool,
+    ) -> Result<(), SimulationError> {
+        if zero2one {
+            self.reserve0 = safe_add_u256(self.reserve0, amount_in)?;
+            self.reserve1 = safe_sub_u256(self.reserve1, amount_out)?;
+        } else {
+            self.reserve0 = safe_sub_u256(self.reserve0, amount_out)?;
+            self.reserve1 = safe_add_u256(self.reserve1, amount_in)?;
+        }
+        Ok(())
+    }
+
+    /// Applies the reserve changes reported by a `Swap`, `Mint` or `Burn` event.
+    ///
+    /// The pair emits these events right after the `Sync` event of the same call. If that `Sync`
+    /// was applied, it already set the reserves; the changes are then only checked against the
+    /// reserves before it. Otherwise they are applied on top of the current reserves.
+    ///
+    /// Returns the reserves before the changes.
+    fn apply_reserve_changes(
+        &mut self,
+        (amount0_in, amount1_in): (U256, U256),
+        (amount0_out, amount1_out): (U256, U256),
+        log_meta: &EVMLogMeta,
+    ) -> Result<(U256, U256), TransitionError<LogIndex>> {
+        let (event_block, event_index) = log_meta.index();
+        let synced = self
+            .last_sync
+            .take()
+            .filter(|((sync_block, sync_index), _, _)| {
+                *sync_block == event_block && sync_index + 1 == event_index
+            });
+        let (reserve0, reserve1) = match synced {
+            Some((_, reserve0, reserve1)) => (reserve0, reserve1),
+            None => (self.reserve0, self.reserve1),
+        };
+        let overflow =
+            || TransitionError::InconsistentEvent("Reserve change overflows".to_string());
+        let expected0 = reserve0
+            .checked_add(amount0_in)
+            .and_then(|reserve| reserve.checked_sub(amount0_out))
+            .ok_or_else(overflow)?;
+        let expected1 = reserve1
+            .checked_add(amount1_in)
+            .and_then(|reserve| reserve.checked_sub(amount1_out))
+            .ok_or_else(overflow)?;
+
+        if synced.is_some() && (expected0, expected1) != (self.reserve0, self.reserve1) {
+            return Err(TransitionError::InconsistentEvent(format!(
+                "Event implies reserves ({}, {}), but the pair synced to ({}, {})",
+                expected0, expected1, self.reserve0, self.reserve1
+            )));
+        }
+        self.reserve0 = expected0;
+        self.reserve1 = expected1;
+        Ok((reserve0, reserve1))
+    }
+
+    /// Updates the total supply of LP tokens and `kLast` for the amounts of a `Mint` or `Burn`
+    /// event, given the reserves before it.
+    ///
+    /// The LP tokens of a mint follow exactly from the amounts. Those of a burn don't, as the
+    /// amounts are rounded down; the smallest number of LP tokens paying out the amounts is
+    /// assumed, so the total supply may be overestimated by the rounding. The total supply stays
+    /// unknown (zero) if it wasn't set for a pair with reserves.
+    fn apply_supply_change(
+        &mut self,
+        (amount0, amount1): (U256, U256),
+        (reserve0, reserve1): (U256, U256),
+        is_mint: bool,
+    ) -> Result<(), SimulationError> {
+        let before = UniswapV2State { reserve0, reserve1, ..self.clone() };
+        let total_supply = safe_add_u256(self.total_supply, before.pending_protocol_fee()?)?;
+        if is_mint && total_supply.is_zero() && reserve0.is_zero() && reserve1.is_zero() {
+            // includes the minimum liquidity locked by the first mint
+            self.total_supply = safe_mul_u256(amount0, amount1)?.integer_sqrt();
+        } else if !total_supply.is_zero() {
+            self.total_supply = if is_mint {
+                let liquidity = U256::min(
+                    safe_div_u256(safe_mul_u256(amount0, total_supply)?, reserve0)?,
+                    safe_div_u256(safe_mul_u256(amount1, total_supply)?, reserve1)?,
+                );
+                safe_add_u256(total_supply, liquidity)?
+            } else {
+                let liquidity = U256::max(
+                    ceil_div_u256(safe_mul_u256(amount0, total_supply)?, reserve0)?,
+                    ceil_div_u256(safe_mul_u256(amount1, total_supply)?, reserve1)?,
+                );
+                safe_sub_u256(total_supply, liquidity)?
+            };
+        }
+        self.update_k_last()
+    }
+
+    /// Computes the amounts of a swap, accounting for transfer taxes of the tokens.
+    ///
+    /// Returns the amount the pair receives, the amount it sends out and the amount the trader
+    /// receives.
+    fn swap_amounts(
+        &self,
+        amount_in: U256,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<(U256, U256, U256), SimulationError> {
+        let zero2one = token_in.address < token_out.address;
+        let pool_amount_in = token_in.amount_after_sell_tax(amount_in);
+        let pool_amount_out = self.compute_amount_out(pool_amount_in, zero2one)?;
+        Ok((pool_amount_in, pool_amount_out, token_out.amount_after_buy_tax(pool_amount_out)))
+    }
+
+    /// Computes the amount out of a swap using the constant product formula.
+    ///
+    /// # Arguments
+    ///
+    /// * `amount_in` - The amount of input for the trade.
+    /// * `zero2one` - Whether token 0 is sold for token 1.
+    fn compute_amount_out(&self, amount_in: U256, zero2one: bool) -> Result<U256, SimulationError> {
+        if amount_in == U256::zero() {
+            return Err(SimulationError::InsufficientAmount());
+        }
+        let reserve_sell = if zero2one { self.reserve0 } else { self.reserve1 };
+        let reserve_buy = if zero2one { self.reserve1 } else { self.reserve0 };
+
+        if reserve_sell == U256::zero() || reserve_buy == U256::zero() {
+            return Err(SimulationError::NoLiquidity());
+        }
+
+        let amount_in_with_fee = safe_mul_u256(amount_in, U256::from(997))?;
+        let numerator = safe_mul_u256(amount_in_with_fee, reserve_buy)?;
+        let denominator =
+            safe_add_u256(safe_mul_u256(reserve_sell, U256::from(1000))?, amount_in_with_fee)?;
+
+        safe_div_u256(numerator, denominator)
     }
 }
 
@@ -95,34 +512,57 @@ impl ProtocolSim for UniswapV2State {
         token_in: &ERC20Token,
         token_out: &ERC20Token,
     ) -> Result<GetAmountOutResult, SimulationError> {
-        if amount_in == U256::zero() {
-            return Err(SimulationError::InsufficientAmount());
-        }
         let zero2one = token_in.address < token_out.address;
-        let reserve_sell = if zero2one { self.reserve0 } else { self.reserve1 };
-        let reserve_buy = if zero2one { self.reserve1 } else { self.reserve0 };
-
-        if reserve_sell == U256::zero() || reserve_buy == U256::zero() {
-            return Err(SimulationError::NoLiquidity());
-        }
-
-        let amount_in_with_fee = safe_mul_u256(amount_in, U256::from(997))?;
-        let numerator = safe_mul_u256(amount_in_with_fee, reserve_buy)?;
-        let denominator =
-            safe_add_u256(safe_mul_u256(reserve_sell, U256::from(1000))?, amount_in_with_fee)?;
-
-        let amount_out = safe_div_u256(numerator, denominator)?;
+        let (pool_amount_in, pool_amount_out, amount_out) =
+            self.swap_amounts(amount_in, token_in, token_out)?;
         let mut new_state = self.clone();
-        if zero2one {
-            new_state.reserve0 = safe_add_u256(self.reserve0, amount_in)?;
-            new_state.reserve1 = safe_sub_u256(self.reserve1, amount_out)?;
-        } else {
-            new_state.reserve0 = safe_sub_u256(self.reserve0, amount_out)?;
-            new_state.reserve1 = safe_add_u256(self.reserve1, amount_in)?;
-        };
+        new_state.apply_swap(pool_amount_in, pool_amount_out, zero2one)?;
         Ok(GetAmountOutResult::new(amount_out, U256::from(120_000), Box::new(new_state)))
     }
 
+    fn quote_amount_out(
+        &self,
+        amount_in: U256,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<QuoteResult, SimulationError> {
+        let (_, _, amount_out) = self.swap_amounts(amount_in, token_in, token_out)?;
+        Ok(QuoteResult::new(amount_out, U256::from(120_000)))
+    }
+
+    /// Returns the limits of a trade, given that the reserves can't exceed the `uint112` range.
+    ///
+    /// # Returns
+    ///
+    /// * `(max_in, max_out)` - The amount of input that brings the input reserve to its maximum and
+    ///   the amount of output received for it, or zero for a pool without liquidity.
+    fn get_limits(
+        &self,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<(U256, U256), SimulationError> {
+        let zero2one = token_in.address < token_out.address;
+        let reserve_in = if zero2one { self.reserve0 } else { self.reserve1 };
+        if self.reserve0.is_zero() || self.reserve1.is_zero() {
+            return Ok((U256::zero(), U256::zero()));
+        }
+        // Selling x moves the price by a factor of (reserve_in / (reserve_in + x))^2, so selling
+        // (sqrt(10) - 1) * reserve_in, about 2.16 times the reserve, has a price impact of 90%.
+        // Larger trades are never sensible, even though the pair would execute them.
+        let pool_max_in = U256::min(
+            safe_div_u256(safe_mul_u256(reserve_in, U256::from(216))?, U256::from(100))?,
+            MAX_RESERVE.saturating_sub(reserve_in),
+        );
+        if pool_max_in.is_zero() {
+            return Ok((U256::zero(), U256::zero()));
+        }
+        let pool_max_out = self.compute_amount_out(pool_max_in, zero2one)?;
+        Ok((
+            token_in.max_amount_before_sell_tax(pool_max_in),
+            token_out.amount_after_buy_tax(pool_max_out),
+        ))
+    }
+
     fn delta_transition(
         &mut self,
         delta: ProtocolStateDelta,
@@ -148,18 +588,35 @@ impl ProtocolSim for UniswapV2State {
         protocol_event: Box<dyn ProtocolEvent>,
         log_meta: &EVMLogMeta,
     ) -> Result<(), TransitionError<LogIndex>> {
-        if let Some(sync_event) = protocol_event
-            .as_any()
-            .downcast_ref::<UniswapV2Sync>()
-        {
-            check_log_idx(self.log_index, log_meta)?;
+        let event = protocol_event.as_any();
+        check_log_idx(self.log_index, log_meta)?;
+        if let Some(sync_event) = event.downcast_ref::<UniswapV2Sync>() {
+            self.last_sync = Some((log_meta.index(), self.reserve0, self.reserve1));
             self.reserve0 = sync_event.reserve0;
             self.reserve1 = sync_event.reserve1;
-            self.log_index = log_meta.index();
-            Ok(())
+        } else if let Some(swap_event) = event.downcast_ref::<UniswapV2Swap>() {
+            self.apply_reserve_changes(
+                (swap_event.amount0_in, swap_event.amount1_in),
+                (swap_event.amount0_out, swap_event.amount1_out),
+                log_meta,
+            )?;
+        } else if let Some(mint_event) = event.downcast_ref::<UniswapV2Mint>() {
+            let amounts = (mint_event.amount0, mint_event.amount1);
+            let reserves =
+                self.apply_reserve_changes(amounts, (U256::zero(), U256::zero()), log_meta)?;
+            self.apply_supply_change(amounts, reserves, true)
+                .map_err(|err| TransitionError::InconsistentEvent(err.to_string()))?;
+        } else if let Some(burn_event) = event.downcast_ref::<UniswapV2Burn>() {
+            let amounts = (burn_event.amount0, burn_event.amount1);
+            let reserves =
+                self.apply_reserve_changes((U256::zero(), U256::zero()), amounts, log_meta)?;
+            self.apply_supply_change(amounts, reserves, false)
+                .map_err(|err| TransitionError::InconsistentEvent(err.to_string()))?;
         } else {
-            Err(TransitionError::InvalidEventType())
+            return Err(TransitionError::InvalidEventType());
         }
+        self.log_index = log_meta.index();
+        Ok(())
     }
 
     fn clone_box(&self) -> Box<dyn ProtocolSim> {
@@ -182,6 +639,16 @@ impl ProtocolSim for UniswapV2State {
     }
 }
 
+/// Divides `a` by `b`, rounding up.
+fn ceil_div_u256(a: U256, b: U256) -> Result<U256, SimulationError> {
+    let quotient = safe_div_u256(a, b)?;
+    if quotient * b == a {
+        Ok(quotient)
+    } else {
+        safe_add_u256(quotient, U256::one())
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -197,6 +664,8 @@ mod tests {
 
     use tycho_core::hex_bytes::Bytes;
 
+    use crate::models::TransferBehaviour;
+
     fn u256(s: &str) -> U256 {
         U256::from_dec_str(s).unwrap()
     }
@@ -257,6 +726,75 @@ mod tests {
         assert_eq!(state.reserve1, r1);
     }
 
+    #[test]
+    fn test_quote_amount_out() {
+        let t0 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            U256::from(10_000),
+        );
+        let t1 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            U256::from(10_000),
+        );
+        let state =
+            UniswapV2State::new(u256("6770398782322527849696614"), u256("5124813135806900540214"));
+        let amount_in = u256("10000000000000000000000");
+
+        let quote = state
+            .quote_amount_out(amount_in, &t0, &t1)
+            .unwrap();
+        let res = state
+            .get_amount_out(amount_in, &t0, &t1)
+            .unwrap();
+
+        assert_eq!(quote.amount, res.amount);
+        assert_eq!(quote.gas, res.gas);
+    }
+
+    #[test]
+    fn test_get_amount_out_with_transfer_taxes() {
+        let t0 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            U256::from(10_000),
+        )
+        .with_transfer_behaviour(TransferBehaviour { sell_tax_bps: 1000, ..Default::default() });
+        let t1 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            U256::from(10_000),
+        )
+        .with_transfer_behaviour(TransferBehaviour { buy_tax_bps: 500, ..Default::default() });
+        let state = UniswapV2State::new(u256("1000000"), u256("1000000"));
+
+        let res = state
+            .get_amount_out(u256("10000"), &t0, &t1)
+            .unwrap();
+
+        // the pair receives 9000 and sends out 8893, of which the trader receives 95%
+        assert_eq!(res.amount, u256("8449"));
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap();
+        assert_eq!(new_state.reserve0, u256("1009000"));
+        assert_eq!(new_state.reserve1, u256("991107"));
+        assert_eq!(
+            state
+                .quote_amount_out(u256("10000"), &t0, &t1)
+                .unwrap()
+                .amount,
+            res.amount
+        );
+    }
+
     #[test]
     fn test_get_amount_out_overflow() {
         let r0 = u256("33372357002392258830279");
@@ -344,6 +882,344 @@ mod tests {
         assert_eq!(state.log_index, log_meta.index());
     }
 
+    fn log_meta(log_index: u32) -> EVMLogMeta {
+        EVMLogMeta::new(
+            H160::from_str("0x95222290DD7278Aa3Ddd389Cc1E1d165CC4BAfe5").unwrap(),
+            1,
+            H256::from_str("0xe4ea49424508471a7f83633fe97dbbee641ddecb106e187896b27e09d0d05e1c")
+                .unwrap(),
+            1,
+            H256::from_str("0xe64a78e6e0fe611ecbf8e079ecb032985f5f08a5d9acba5910f27ec8be8095a9")
+                .unwrap(),
+            log_index,
+        )
+    }
+
+    #[test]
+    fn test_swap_event_after_sync() {
+        let mut state = UniswapV2State::new(u256("1000"), u256("1000"));
+
+        state
+            .event_transition(Box::new(UniswapV2Sync::new(u256("1100"), u256("910"))), &log_meta(1))
+            .unwrap();
+        state
+            .event_transition(
+                Box::new(UniswapV2Swap::new(u256("100"), U256::zero(), U256::zero(), u256("90"))),
+                &log_meta(2),
+            )
+            .unwrap();
+
+        assert_eq!(state.reserve0, u256("1100"));
+        assert_eq!(state.reserve1, u256("910"));
+        assert_eq!(state.log_index, (1, 2));
+    }
+
+    #[test]
+    fn test_swap_event_inconsistent_with_sync() {
+        let mut state = UniswapV2State::new(u256("1000"), u256("1000"));
+        state
+            .event_transition(Box::new(UniswapV2Sync::new(u256("1100"), u256("910"))), &log_meta(1))
+            .unwrap();
+
+        let res = state.event_transition(
+            Box::new(UniswapV2Swap::new(u256("100"), U256::zero(), U256::zero(), u256("95"))),
+            &log_meta(2),
+        );
+
+        assert!(matches!(res, Err(TransitionError::InconsistentEvent(_))));
+    }
+
+    #[test]
+    fn test_swap_event_after_sync_of_restored_state() {
+        let mut state = UniswapV2State::new(u256("1000"), u256("1000"));
+        state
+            .event_transition(Box::new(UniswapV2Sync::new(u256("1100"), u256("910"))), &log_meta(1))
+            .unwrap();
+
+        let mut restored: UniswapV2State =
+            serde_json::from_str(&serde_json::to_string(&state).unwrap()).unwrap();
+        restored
+            .event_transition(
+                Box::new(UniswapV2Swap::new(u256("100"), U256::zero(), U256::zero(), u256("90"))),
+                &log_meta(2),
+            )
+            .unwrap();
+
+        assert_eq!(restored.reserve0, u256("1100"));
+        assert_eq!(restored.reserve1, u256("910"));
+    }
+
+    #[test]
+    fn test_mint_and_burn_events_without_sync() {
+        let mut state = UniswapV2State::new(u256("1000"), u256("1000"));
+
+        state
+            .event_transition(Box::new(UniswapV2Mint::new(u256("500"), u256("300"))), &log_meta(1))
+            .unwrap();
+        state
+            .event_transition(Box::new(UniswapV2Burn::new(u256("100"), u256("200"))), &log_meta(5))
+            .unwrap();
+
+        assert_eq!(state.reserve0, u256("1400"));
+        assert_eq!(state.reserve1, u256("1100"));
+        // the total supply of the pair wasn't set, so it stays unknown
+        assert_eq!(state.total_supply, U256::zero());
+    }
+
+    #[test]
+    fn test_mint_and_burn_events_update_total_supply() {
+        let state = UniswapV2State::new(u256("1000000"), u256("2000000"))
+            .with_total_supply(u256("1000000"))
+            .with_protocol_fee(true, u256("1000000000000"));
+        let minted = state
+            .mint(u256("1000"), u256("2000"))
+            .unwrap();
+        let burned = minted
+            .new_state
+            .burn(u256("5000"))
+            .unwrap();
+
+        let mut tracked = state.clone();
+        let events: Vec<Box<dyn ProtocolEvent>> = vec![
+            Box::new(UniswapV2Sync::new(minted.new_state.reserve0, minted.new_state.reserve1)),
+            Box::new(UniswapV2Mint::new(minted.amount0, minted.amount1)),
+            Box::new(UniswapV2Sync::new(burned.new_state.reserve0, burned.new_state.reserve1)),
+            Box::new(UniswapV2Burn::new(burned.amount0, burned.amount1)),
+        ];
+        for (index, event) in events.into_iter().enumerate() {
+            tracked
+                .event_transition(event, &log_meta(index as u32 + 1))
+                .unwrap();
+        }
+
+        assert_eq!(tracked.total_supply, burned.new_state.total_supply);
+        assert_eq!(tracked.k_last, burned.new_state.k_last);
+    }
+
+    #[test]
+    fn test_first_mint_event_sets_total_supply() {
+        let mut state = UniswapV2State::new(U256::zero(), U256::zero());
+
+        state
+            .event_transition(
+                Box::new(UniswapV2Mint::new(u256("4000"), u256("1000"))),
+                &log_meta(1),
+            )
+            .unwrap();
+
+        assert_eq!(state.total_supply, u256("2000"));
+    }
+
+    #[test]
+    fn test_get_amount_out_at_accumulates_prices() {
+        let t0 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            U256::from(10_000),
+        );
+        let t1 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            U256::from(10_000),
+        );
+        let state = UniswapV2State::new(u256("1000000"), u256("2000000")).with_cumulative_prices(
+            U256::zero(),
+            U256::zero(),
+            1000,
+        );
+
+        let res = state
+            .get_amount_out_at(u256("1000"), &t0, &t1, 1010)
+            .unwrap();
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap();
+
+        // the prices before the swap (2 and 0.5) accumulate over 10 seconds
+        assert_eq!(new_state.price0_cumulative_last, U256::from(20) << 112);
+        assert_eq!(new_state.price1_cumulative_last, U256::from(5) << 111);
+        assert_eq!(new_state.block_timestamp_last, 1010);
+        assert_eq!(new_state.reserve0, u256("1001000"));
+        // a second swap in the same block doesn't accumulate again
+        assert_eq!(
+            new_state.current_cumulative_prices(1010),
+            (new_state.price0_cumulative_last, new_state.price1_cumulative_last)
+        );
+        assert_eq!(
+            state.current_cumulative_prices(1010),
+            (U256::from(20) << 112, U256::from(5) << 111)
+        );
+    }
+
+    #[test]
+    fn test_get_limits() {
+        let t0 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            U256::from(10_000),
+        );
+        let t1 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            U256::from(10_000),
+        );
+        let state = UniswapV2State::new(u256("1000"), u256("2000"));
+
+        let (max_in, max_out) = state.get_limits(&t0, &t1).unwrap();
+
+        assert_eq!(max_in, u256("2160"));
+        assert_eq!(max_out, u256("1365"));
+        let res = state
+            .get_amount_out(max_in, &t0, &t1)
+            .unwrap();
+        assert_eq!(res.amount, max_out);
+        // the price impact of selling the limit is about 90%
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV2State>()
+            .unwrap();
+        assert_ulps_eq!(
+            new_state.spot_price(&t0, &t1).unwrap(),
+            state.spot_price(&t0, &t1).unwrap() * 0.1,
+            epsilon = 0.002
+        );
+        assert_eq!(
+            UniswapV2State::new(U256::zero(), U256::zero())
+                .get_limits(&t0, &t1)
+                .unwrap(),
+            (U256::zero(), U256::zero())
+        );
+    }
+
+    #[test]
+    fn test_get_limits_with_transfer_taxes() {
+        let t0 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000000",
+            18,
+            "T0",
+            U256::from(10_000),
+        )
+        .with_transfer_behaviour(TransferBehaviour { sell_tax_bps: 1000, ..Default::default() });
+        let t1 = ERC20Token::new(
+            "0x0000000000000000000000000000000000000001",
+            18,
+            "T1",
+            U256::from(10_000),
+        )
+        .with_transfer_behaviour(TransferBehaviour { buy_tax_bps: 500, ..Default::default() });
+        let state = UniswapV2State::new(u256("1000"), u256("2000"));
+
+        let (max_in, max_out) = state.get_limits(&t0, &t1).unwrap();
+
+        assert_eq!(max_in, u256("2400"));
+        assert_eq!(t0.amount_after_sell_tax(max_in), u256("2160"));
+        assert_eq!(max_out, u256("1297"));
+        let res = state
+            .get_amount_out(max_in, &t0, &t1)
+            .unwrap();
+        assert_eq!(res.amount, max_out);
+    }
+
+    #[test]
+    fn test_first_mint_locks_minimum_liquidity() {
+        let state = UniswapV2State::new(U256::zero(), U256::zero());
+
+        let res = state
+            .add_liquidity(u256("4000"), u256("9000"))
+            .unwrap();
+
+        assert_eq!(res.liquidity, u256("5000"));
+        assert_eq!(res.new_state.total_supply, u256("6000"));
+        assert_eq!(res.new_state.reserve0, u256("4000"));
+        assert_eq!(res.new_state.reserve1, u256("9000"));
+    }
+
+    #[test]
+    fn test_mint_without_total_supply() {
+        let state = UniswapV2State::new(u256("1000000"), u256("2000000"));
+
+        let res = state.mint(u256("1000"), u256("2000"));
+
+        assert!(matches!(res, Err(SimulationError::NotInitialized(_))));
+    }
+
+    #[rstest]
+    #[case::excess_token1(u256("1000"), u256("3000"), u256("1000"), u256("2000"))]
+    #[case::excess_token0(u256("5000"), u256("2000"), u256("1000"), u256("2000"))]
+    fn test_add_liquidity(
+        #[case] amount0_desired: U256,
+        #[case] amount1_desired: U256,
+        #[case] exp_amount0: U256,
+        #[case] exp_amount1: U256,
+    ) {
+        let state = UniswapV2State::new(u256("1000000"), u256("2000000"))
+            .with_total_supply(u256("1000000"));
+
+        let res = state
+            .add_liquidity(amount0_desired, amount1_desired)
+            .unwrap();
+
+        assert_eq!((res.amount0, res.amount1), (exp_amount0, exp_amount1));
+        assert_eq!(res.liquidity, u256("1000"));
+        assert_eq!(res.new_state.total_supply, u256("1001000"));
+        assert_eq!(res.new_state.reserve0, u256("1001000"));
+        assert_eq!(res.new_state.reserve1, u256("2002000"));
+    }
+
+    #[test]
+    fn test_burn() {
+        let state = UniswapV2State::new(u256("1000000"), u256("2000000"))
+            .with_total_supply(u256("1000000"));
+
+        let res = state.burn(u256("100000")).unwrap();
+
+        assert_eq!((res.amount0, res.amount1), (u256("100000"), u256("200000")));
+        assert_eq!(res.new_state.total_supply, u256("900000"));
+        assert_eq!(res.new_state.reserve0, u256("900000"));
+        assert_eq!(res.new_state.reserve1, u256("1800000"));
+        assert!(matches!(state.burn(u256("1000001")), Err(SimulationError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_protocol_fee() {
+        // sqrt(k) grew from 1_000_000 to 1_100_000 since the last liquidity event
+        let state = UniswapV2State::new(u256("1100000"), u256("1100000"))
+            .with_total_supply(u256("1000000"))
+            .with_protocol_fee(true, u256("1000000000000"));
+
+        assert_eq!(state.pending_protocol_fee().unwrap(), u256("15384"));
+
+        let res = state.burn(u256("100000")).unwrap();
+
+        assert_eq!((res.amount0, res.amount1), (u256("108333"), u256("108333")));
+        assert_eq!(res.new_state.total_supply, u256("915384"));
+        assert_eq!(res.new_state.k_last, u256("991667") * u256("991667"));
+        assert_eq!(
+            res.new_state
+                .pending_protocol_fee()
+                .unwrap(),
+            U256::zero()
+        );
+    }
+
+    #[test]
+    fn test_k_last_is_reset_when_fee_is_off() {
+        let state = UniswapV2State::new(u256("1000000"), u256("1000000"))
+            .with_total_supply(u256("1000000"))
+            .with_protocol_fee(false, u256("1000000000000"));
+
+        let res = state.burn(u256("100000")).unwrap();
+
+        assert_eq!(res.new_state.k_last, U256::zero());
+    }
+
     #[test]
     fn test_delta_transition() {
         let mut state = UniswapV2State::new(u256("1000"), u256("1000"));
@@ -1,8 +1,15 @@
 use std::any::Any;
 
-use ethers::types::U256;
+use ethers::{
+    abi::ParamType,
+    types::{Log, U256},
+};
 
-use crate::protocol::state::ProtocolEvent;
+use crate::protocol::{
+    errors::LogDecodingError,
+    log_decoder::{decode_data, token_to_i32, token_to_u256, topic_to_i32, LogDecoder},
+    state::ProtocolEvent,
+};
 
 /// Underlying data structure for mint and burns
 ///
@@ -50,11 +57,81 @@ impl SwapEvent {
     }
 }
 
+/// Emitted when a position's owed tokens are collected. Positions are not tracked, so this event
+/// doesn't change the state.
+#[derive(Debug, Clone)]
+pub struct CollectEvent {
+    pub tick_lower: i32,
+    pub tick_upper: i32,
+    pub amount0: u128,
+    pub amount1: u128,
+}
+
+impl CollectEvent {
+    pub fn new(tick_lower: i32, tick_upper: i32, amount0: u128, amount1: u128) -> Self {
+        CollectEvent { tick_lower, tick_upper, amount0, amount1 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct FlashEvent {
+    pub amount0: U256,
+    pub amount1: U256,
+    pub paid0: U256,
+    pub paid1: U256,
+}
+
+impl FlashEvent {
+    pub fn new(amount0: U256, amount1: U256, paid0: U256, paid1: U256) -> Self {
+        FlashEvent { amount0, amount1, paid0, paid1 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct SetFeeProtocolEvent {
+    pub fee_protocol0_new: u8,
+    pub fee_protocol1_new: u8,
+}
+
+impl SetFeeProtocolEvent {
+    pub fn new(fee_protocol0_new: u8, fee_protocol1_new: u8) -> Self {
+        SetFeeProtocolEvent { fee_protocol0_new, fee_protocol1_new }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct CollectProtocolEvent {
+    pub amount0: u128,
+    pub amount1: u128,
+}
+
+impl CollectProtocolEvent {
+    pub fn new(amount0: u128, amount1: u128) -> Self {
+        CollectProtocolEvent { amount0, amount1 }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct IncreaseObservationCardinalityNextEvent {
+    pub observation_cardinality_next_new: u16,
+}
+
+impl IncreaseObservationCardinalityNextEvent {
+    pub fn new(observation_cardinality_next_new: u16) -> Self {
+        IncreaseObservationCardinalityNextEvent { observation_cardinality_next_new }
+    }
+}
+
 #[derive(Debug, Clone)]
 pub enum UniswapV3Event {
     Mint(LiquidityChangeData),
     Burn(LiquidityChangeData),
     Swap(SwapEvent),
+    Collect(CollectEvent),
+    Flash(FlashEvent),
+    SetFeeProtocol(SetFeeProtocolEvent),
+    CollectProtocol(CollectProtocolEvent),
+    IncreaseObservationCardinalityNext(IncreaseObservationCardinalityNextEvent),
 }
 
 impl ProtocolEvent for UniswapV3Event {
@@ -83,3 +160,187 @@ impl From<SwapEvent> for UniswapV3Event {
         UniswapV3Event::Swap(value)
     }
 }
+
+impl From<CollectEvent> for UniswapV3Event {
+    fn from(value: CollectEvent) -> Self {
+        UniswapV3Event::Collect(value)
+    }
+}
+
+impl From<FlashEvent> for UniswapV3Event {
+    fn from(value: FlashEvent) -> Self {
+        UniswapV3Event::Flash(value)
+    }
+}
+
+impl From<SetFeeProtocolEvent> for UniswapV3Event {
+    fn from(value: SetFeeProtocolEvent) -> Self {
+        UniswapV3Event::SetFeeProtocol(value)
+    }
+}
+
+impl From<CollectProtocolEvent> for UniswapV3Event {
+    fn from(value: CollectProtocolEvent) -> Self {
+        UniswapV3Event::CollectProtocol(value)
+    }
+}
+
+impl From<IncreaseObservationCardinalityNextEvent> for UniswapV3Event {
+    fn from(value: IncreaseObservationCardinalityNextEvent) -> Self {
+        UniswapV3Event::IncreaseObservationCardinalityNext(value)
+    }
+}
+
+fn token_to_u128(token: &ethers::abi::Token) -> Result<u128, LogDecodingError> {
+    let value = token_to_u256(token)?;
+    if value > U256::from(u128::MAX) {
+        return Err(LogDecodingError::InvalidData(format!("{} overflows uint128", value)));
+    }
+    Ok(value.as_u128())
+}
+
+fn token_to_u16(token: &ethers::abi::Token) -> Result<u16, LogDecodingError> {
+    let value = token_to_u256(token)?;
+    if value > U256::from(u16::MAX) {
+        return Err(LogDecodingError::InvalidData(format!("{} overflows uint16", value)));
+    }
+    Ok(value.low_u32() as u16)
+}
+
+fn token_to_u8(token: &ethers::abi::Token) -> Result<u8, LogDecodingError> {
+    let value = token_to_u256(token)?;
+    if value > U256::from(u8::MAX) {
+        return Err(LogDecodingError::InvalidData(format!("{} overflows uint8", value)));
+    }
+    Ok(value.low_u32() as u8)
+}
+
+impl UniswapV3Event {
+    // Solidity spec: event Mint(address sender, address indexed owner, int24 indexed tickLower,
+    // int24 indexed tickUpper, uint128 amount, uint256 amount0, uint256 amount1)
+    fn decode_mint(log: &Log) -> Result<Self, LogDecodingError> {
+        let data = decode_data(
+            log,
+            &[ParamType::Address, ParamType::Uint(128), ParamType::Uint(256), ParamType::Uint(256)],
+        )?;
+        Ok(MintEvent::new(topic_to_i32(log, 2)?, topic_to_i32(log, 3)?, token_to_u128(&data[1])?)
+            .into())
+    }
+
+    // Solidity spec: event Burn(address indexed owner, int24 indexed tickLower, int24 indexed
+    // tickUpper, uint128 amount, uint256 amount0, uint256 amount1)
+    fn decode_burn(log: &Log) -> Result<Self, LogDecodingError> {
+        let data =
+            decode_data(log, &[ParamType::Uint(128), ParamType::Uint(256), ParamType::Uint(256)])?;
+        Ok(BurnEvent::new(topic_to_i32(log, 2)?, topic_to_i32(log, 3)?, token_to_u128(&data[0])?)
+            .into())
+    }
+
+    // Solidity spec: event Swap(address indexed sender, address indexed recipient, int256 amount0,
+    // int256 amount1, uint160 sqrtPriceX96, uint128 liquidity, int24 tick)
+    fn decode_swap(log: &Log) -> Result<Self, LogDecodingError> {
+        let data = decode_data(
+            log,
+            &[
+                ParamType::Int(256),
+                ParamType::Int(256),
+                ParamType::Uint(160),
+                ParamType::Uint(128),
+                ParamType::Int(24),
+            ],
+        )?;
+        Ok(SwapEvent::new(
+            token_to_u256(&data[2])?,
+            token_to_u128(&data[3])?,
+            token_to_i32(&data[4])?,
+        )
+        .into())
+    }
+
+    // Solidity spec: event Collect(address indexed owner, address recipient, int24 indexed
+    // tickLower, int24 indexed tickUpper, uint128 amount0, uint128 amount1)
+    fn decode_collect(log: &Log) -> Result<Self, LogDecodingError> {
+        let data =
+            decode_data(log, &[ParamType::Address, ParamType::Uint(128), ParamType::Uint(128)])?;
+        Ok(CollectEvent::new(
+            topic_to_i32(log, 2)?,
+            topic_to_i32(log, 3)?,
+            token_to_u128(&data[1])?,
+            token_to_u128(&data[2])?,
+        )
+        .into())
+    }
+
+    // Solidity spec: event Flash(address indexed sender, address indexed recipient, uint256
+    // amount0, uint256 amount1, uint256 paid0, uint256 paid1)
+    fn decode_flash(log: &Log) -> Result<Self, LogDecodingError> {
+        let data = decode_data(
+            log,
+            &[
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+                ParamType::Uint(256),
+            ],
+        )?;
+        Ok(FlashEvent::new(
+            token_to_u256(&data[0])?,
+            token_to_u256(&data[1])?,
+            token_to_u256(&data[2])?,
+            token_to_u256(&data[3])?,
+        )
+        .into())
+    }
+
+    // Solidity spec: event SetFeeProtocol(uint8 feeProtocol0Old, uint8 feeProtocol1Old, uint8
+    // feeProtocol0New, uint8 feeProtocol1New)
+    fn decode_set_fee_protocol(log: &Log) -> Result<Self, LogDecodingError> {
+        let data = decode_data(
+            log,
+            &[ParamType::Uint(8), ParamType::Uint(8), ParamType::Uint(8), ParamType::Uint(8)],
+        )?;
+        Ok(SetFeeProtocolEvent::new(token_to_u8(&data[2])?, token_to_u8(&data[3])?).into())
+    }
+
+    // Solidity spec: event CollectProtocol(address indexed sender, address indexed recipient,
+    // uint128 amount0, uint128 amount1)
+    fn decode_collect_protocol(log: &Log) -> Result<Self, LogDecodingError> {
+        let data = decode_data(log, &[ParamType::Uint(128), ParamType::Uint(128)])?;
+        Ok(CollectProtocolEvent::new(token_to_u128(&data[0])?, token_to_u128(&data[1])?).into())
+    }
+
+    // Solidity spec: event IncreaseObservationCardinalityNext(uint16
+    // observationCardinalityNextOld, uint16 observationCardinalityNextNew)
+    fn decode_increase_observation_cardinality_next(log: &Log) -> Result<Self, LogDecodingError> {
+        let data = decode_data(log, &[ParamType::Uint(16), ParamType::Uint(16)])?;
+        Ok(IncreaseObservationCardinalityNextEvent::new(token_to_u16(&data[1])?).into())
+    }
+}
+
+/// Registers the decoders for all Uniswap V3 pool events that affect the pool's state.
+pub fn register_log_decoders(decoder: &mut LogDecoder) {
+    decoder.register("Mint(address,address,int24,int24,uint128,uint256,uint256)", |log| {
+        Ok(Box::new(UniswapV3Event::decode_mint(log)?))
+    });
+    decoder.register("Burn(address,int24,int24,uint128,uint256,uint256)", |log| {
+        Ok(Box::new(UniswapV3Event::decode_burn(log)?))
+    });
+    decoder.register("Swap(address,address,int256,int256,uint160,uint128,int24)", |log| {
+        Ok(Box::new(UniswapV3Event::decode_swap(log)?))
+    });
+    decoder.register("Collect(address,address,int24,int24,uint128,uint128)", |log| {
+        Ok(Box::new(UniswapV3Event::decode_collect(log)?))
+    });
+    decoder.register("Flash(address,address,uint256,uint256,uint256,uint256)", |log| {
+        Ok(Box::new(UniswapV3Event::decode_flash(log)?))
+    });
+    decoder.register("SetFeeProtocol(uint8,uint8,uint8,uint8)", |log| {
+        Ok(Box::new(UniswapV3Event::decode_set_fee_protocol(log)?))
+    });
+    decoder.register("CollectProtocol(address,address,uint128,uint128)", |log| {
+        Ok(Box::new(UniswapV3Event::decode_collect_protocol(log)?))
+    });
+    decoder.register("IncreaseObservationCardinalityNext(uint16,uint16)", |log| {
+        Ok(Box::new(UniswapV3Event::decode_increase_observation_cardinality_next(log)?))
+    });
+}
@@ -1,3 +1,9 @@
+use ethers::types::U256;
+
+use crate::{protocol::errors::SimulationError, safe_math::safe_sub_u256};
+
+use super::{solidity_math::mul_div, sqrt_price_math::Q96};
+
 // Solidity spec: function addDelta(uint128 x, int128 y) internal pure returns (uint128 z) {
 pub fn add_liquidity_delta(x: u128, y: i128) -> u128 {
     if y < 0 {
@@ -7,9 +13,52 @@ pub fn add_liquidity_delta(x: u128, y: i128) -> u128 {
     }
 }
 
+// Solidity spec: function getLiquidityForAmount0(uint160 sqrtRatioAX96, uint160 sqrtRatioBX96,
+// uint256 amount0) internal pure returns (uint128 liquidity)
+fn get_liquidity_for_amount0(a: U256, b: U256, amount0: U256) -> Result<U256, SimulationError> {
+    let (sqrt_ratio_a, sqrt_ratio_b) = if a > b { (b, a) } else { (a, b) };
+    let intermediate = mul_div(sqrt_ratio_a, sqrt_ratio_b, Q96)?;
+    mul_div(amount0, intermediate, safe_sub_u256(sqrt_ratio_b, sqrt_ratio_a)?)
+}
+
+// Solidity spec: function getLiquidityForAmount1(uint160 sqrtRatioAX96, uint160 sqrtRatioBX96,
+// uint256 amount1) internal pure returns (uint128 liquidity)
+fn get_liquidity_for_amount1(a: U256, b: U256, amount1: U256) -> Result<U256, SimulationError> {
+    let (sqrt_ratio_a, sqrt_ratio_b) = if a > b { (b, a) } else { (a, b) };
+    mul_div(amount1, Q96, safe_sub_u256(sqrt_ratio_b, sqrt_ratio_a)?)
+}
+
+// Solidity spec: function getLiquidityForAmounts(uint160 sqrtRatioX96, uint160 sqrtRatioAX96,
+// uint160 sqrtRatioBX96, uint256 amount0, uint256 amount1) internal pure returns (uint128
+// liquidity)
+pub fn get_liquidity_for_amounts(
+    sqrt_price: U256,
+    a: U256,
+    b: U256,
+    amount0: U256,
+    amount1: U256,
+) -> Result<u128, SimulationError> {
+    let (sqrt_ratio_a, sqrt_ratio_b) = if a > b { (b, a) } else { (a, b) };
+
+    let liquidity = if sqrt_price <= sqrt_ratio_a {
+        get_liquidity_for_amount0(sqrt_ratio_a, sqrt_ratio_b, amount0)?
+    } else if sqrt_price < sqrt_ratio_b {
+        let liquidity0 = get_liquidity_for_amount0(sqrt_price, sqrt_ratio_b, amount0)?;
+        let liquidity1 = get_liquidity_for_amount1(sqrt_ratio_a, sqrt_price, amount1)?;
+        liquidity0.min(liquidity1)
+    } else {
+        get_liquidity_for_amount1(sqrt_ratio_a, sqrt_ratio_b, amount1)?
+    };
+
+    if liquidity > U256::from(u128::MAX) {
+        return Err(SimulationError::ArithmeticOverflow());
+    }
+    Ok(liquidity.as_u128())
+}
+
 #[cfg(test)]
 mod tests {
-    use super::*;
+    use super::{super::tick_math::get_sqrt_ratio_at_tick, *};
 
     #[test]
     fn test_add_liquidity_delta_y_neg() {
@@ -30,4 +79,50 @@ mod tests {
 
         assert_eq!(res, 11000);
     }
+
+    #[test]
+    fn test_get_liquidity_for_amounts_in_range() {
+        let sqrt_lower = get_sqrt_ratio_at_tick(-60).unwrap();
+        let sqrt_price = get_sqrt_ratio_at_tick(0).unwrap();
+        let sqrt_upper = get_sqrt_ratio_at_tick(60).unwrap();
+
+        let res = get_liquidity_for_amounts(
+            sqrt_price,
+            sqrt_lower,
+            sqrt_upper,
+            U256::exp10(18),
+            U256::exp10(18),
+        )
+        .unwrap();
+
+        let expected0 = get_liquidity_for_amount0(sqrt_price, sqrt_upper, U256::exp10(18)).unwrap();
+        let expected1 = get_liquidity_for_amount1(sqrt_lower, sqrt_price, U256::exp10(18)).unwrap();
+        assert_eq!(U256::from(res), expected0.min(expected1));
+    }
+
+    #[test]
+    fn test_get_liquidity_for_amounts_out_of_range() {
+        let sqrt_lower = get_sqrt_ratio_at_tick(-60).unwrap();
+        let sqrt_upper = get_sqrt_ratio_at_tick(60).unwrap();
+
+        let below = get_liquidity_for_amounts(
+            sqrt_lower - 1,
+            sqrt_lower,
+            sqrt_upper,
+            U256::exp10(18),
+            U256::zero(),
+        )
+        .unwrap();
+        let above = get_liquidity_for_amounts(
+            sqrt_upper,
+            sqrt_lower,
+            sqrt_upper,
+            U256::zero(),
+            U256::exp10(18),
+        )
+        .unwrap();
+
+        assert!(below > 0);
+        assert!(above > 0);
+    }
 }
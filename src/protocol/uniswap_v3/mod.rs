@@ -2,10 +2,12 @@
 pub mod enums;
 pub mod events;
 mod liquidity_math;
+pub mod oracle;
 mod solidity_math;
 mod sqrt_price_math;
 pub mod state;
 mod swap_math;
 pub mod tick_list;
 mod tick_math;
+pub mod tick_provider;
 pub mod tycho_decoder;
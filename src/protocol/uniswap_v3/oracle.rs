@@ -0,0 +1,450 @@
+//! Uniswap V3 price and liquidity oracle
+//!
+//! Port of the pool's `Oracle` library: a ring buffer of observations of the cumulative tick and
+//! seconds per liquidity, written at most once per block, which allows to compute time weighted
+//! averages over past periods.
+use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::protocol::errors::SimulationError;
+
+/// Truncates a value to the `uint160` the seconds per liquidity accumulator is stored as.
+fn to_uint160(value: U256) -> U256 {
+    value & ((U256::one() << 160) - 1)
+}
+
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Observation {
+    /// The block timestamp of the observation
+    pub block_timestamp: u32,
+    /// The tick accumulator, i.e. tick * time elapsed since the pool was first initialized
+    pub tick_cumulative: i64,
+    /// The seconds per liquidity, i.e. seconds elapsed / max(1, liquidity) since the pool was
+    /// first initialized. Wraps around at 2^160 as the on-chain `uint160`.
+    pub seconds_per_liquidity_cumulative_x128: U256,
+    /// Whether the observation is initialized
+    pub initialized: bool,
+}
+
+impl Observation {
+    // Solidity spec: function transform(Observation memory last, uint32 blockTimestamp, int24
+    // tick, uint128 liquidity) private pure returns (Observation memory)
+    fn transform(&self, block_timestamp: u32, tick: i32, liquidity: u128) -> Observation {
+        let delta = block_timestamp.wrapping_sub(self.block_timestamp);
+        Observation {
+            block_timestamp,
+            tick_cumulative: self.tick_cumulative + tick as i64 * delta as i64,
+            seconds_per_liquidity_cumulative_x128: to_uint160(
+                self.seconds_per_liquidity_cumulative_x128 +
+                    (U256::from(delta) << 128) / U256::from(liquidity.max(1)),
+            ),
+            initialized: true,
+        }
+    }
+}
+
+/// The observations of a pool together with the position of the most recent one.
+#[derive(Clone, Debug, Default, PartialEq, Eq, Serialize, Deserialize)]
+pub struct Oracle {
+    observations: Vec<Observation>,
+    /// Index of the most recently written observation
+    index: u16,
+    /// Number of populated observations
+    cardinality: u16,
+    /// Number of observations the buffer will grow to on the next write
+    cardinality_next: u16,
+}
+
+impl Oracle {
+    /// Creates an oracle from the observations stored on chain. An oracle of a pool that is not
+    /// initialized has a cardinality, index and next cardinality of zero.
+    ///
+    /// # Errors
+    ///
+    /// Returns `SimulationError::InvalidInput` if `index` is not below `cardinality`,
+    /// `cardinality_next` is below `cardinality` or the observation at `index` is missing or not
+    /// initialized.
+    pub fn new(
+        mut observations: Vec<Observation>,
+        index: u16,
+        cardinality: u16,
+        cardinality_next: u16,
+    ) -> Result<Self, SimulationError> {
+        if cardinality == 0 {
+            if index != 0 || cardinality_next != 0 {
+                return Err(SimulationError::InvalidInput(
+                    "Oracle without observations has an index or next cardinality".to_string(),
+                ));
+            }
+            return Ok(Oracle::default());
+        }
+        if index >= cardinality {
+            return Err(SimulationError::InvalidInput(format!(
+                "Observation index {} is out of the cardinality {}",
+                index, cardinality
+            )));
+        }
+        if cardinality_next < cardinality {
+            return Err(SimulationError::InvalidInput(format!(
+                "Next cardinality {} is below the cardinality {}",
+                cardinality_next, cardinality
+            )));
+        }
+        if !observations
+            .get(index as usize)
+            .is_some_and(|observation| observation.initialized)
+        {
+            return Err(SimulationError::InvalidInput(format!(
+                "Observation {} is not initialized",
+                index
+            )));
+        }
+        observations.resize(
+            observations
+                .len()
+                .max(cardinality_next as usize),
+            Observation::default(),
+        );
+        Ok(Oracle { observations, index, cardinality, cardinality_next })
+    }
+
+    /// Creates an oracle with a single observation at `block_timestamp`, as the pool does when
+    /// it is initialized.
+    pub fn initialize(block_timestamp: u32) -> Self {
+        Oracle {
+            observations: vec![Observation {
+                block_timestamp,
+                tick_cumulative: 0,
+                seconds_per_liquidity_cumulative_x128: U256::zero(),
+                initialized: true,
+            }],
+            index: 0,
+            cardinality: 1,
+            cardinality_next: 1,
+        }
+    }
+
+    pub fn is_initialized(&self) -> bool {
+        self.cardinality > 0
+    }
+
+    pub fn index(&self) -> u16 {
+        self.index
+    }
+
+    pub fn cardinality(&self) -> u16 {
+        self.cardinality
+    }
+
+    pub fn cardinality_next(&self) -> u16 {
+        self.cardinality_next
+    }
+
+    /// Returns the most recently written observation.
+    pub fn last_observation(&self) -> Option<&Observation> {
+        if self.is_initialized() {
+            self.observations
+                .get(self.index as usize)
+        } else {
+            None
+        }
+    }
+
+    // Solidity spec: function write(Observation[65535] storage self, uint16 index, uint32
+    // blockTimestamp, int24 tick, uint128 liquidity, uint16 cardinality, uint16 cardinalityNext)
+    /// Writes an observation, at most once per block. `tick` and `liquidity` are the values
+    /// before the change that triggered the write.
+    pub fn write(
+        &mut self,
+        block_timestamp: u32,
+        tick: i32,
+        liquidity: u128,
+    ) -> Result<(), SimulationError> {
+        if !self.is_initialized() {
+            return Err(SimulationError::NotInitialized("Oracle".to_string()));
+        }
+        let last = self.observations[self.index as usize];
+        if last.block_timestamp == block_timestamp {
+            return Ok(());
+        }
+
+        if self.cardinality_next > self.cardinality && self.index == self.cardinality - 1 {
+            self.cardinality = self.cardinality_next;
+        }
+        self.index = (self.index + 1) % self.cardinality;
+        self.observations[self.index as usize] = last.transform(block_timestamp, tick, liquidity);
+        Ok(())
+    }
+
+    // Solidity spec: function grow(Observation[65535] storage self, uint16 current, uint16 next)
+    /// Prepares the buffer to store up to `next` observations.
+    pub fn grow(&mut self, next: u16) -> Result<(), SimulationError> {
+        if !self.is_initialized() {
+            return Err(SimulationError::NotInitialized("Oracle".to_string()));
+        }
+        if next <= self.cardinality_next {
+            return Ok(());
+        }
+        // Uninitialized slots are written with a timestamp of 1 on chain to pre-pay the storage,
+        // which is irrelevant here.
+        self.observations
+            .resize(next as usize, Observation { block_timestamp: 1, ..Default::default() });
+        self.cardinality_next = next;
+        Ok(())
+    }
+
+    // Solidity spec: function observe(Observation[65535] storage self, uint32 time, uint32[]
+    // memory secondsAgos, int24 tick, uint16 index, uint128 liquidity, uint16 cardinality)
+    /// Returns the tick and seconds per liquidity accumulators `seconds_ago` seconds before
+    /// `time`, for each of the `seconds_agos`.
+    ///
+    /// `tick` and `liquidity` are the current values of the pool.
+    pub fn observe(
+        &self,
+        time: u32,
+        seconds_agos: &[u32],
+        tick: i32,
+        liquidity: u128,
+    ) -> Result<Vec<(i64, U256)>, SimulationError> {
+        if !self.is_initialized() {
+            return Err(SimulationError::NotInitialized("Oracle".to_string()));
+        }
+        seconds_agos
+            .iter()
+            .map(|seconds_ago| self.observe_single(time, *seconds_ago, tick, liquidity))
+            .collect()
+    }
+
+    fn observe_single(
+        &self,
+        time: u32,
+        seconds_ago: u32,
+        tick: i32,
+        liquidity: u128,
+    ) -> Result<(i64, U256), SimulationError> {
+        if seconds_ago == 0 {
+            let mut last = self.observations[self.index as usize];
+            if last.block_timestamp != time {
+                last = last.transform(time, tick, liquidity);
+            }
+            return Ok((last.tick_cumulative, last.seconds_per_liquidity_cumulative_x128));
+        }
+
+        let target = time.wrapping_sub(seconds_ago);
+        let (before_or_at, at_or_after) =
+            self.get_surrounding_observations(time, target, tick, liquidity)?;
+
+        if target == before_or_at.block_timestamp {
+            Ok((before_or_at.tick_cumulative, before_or_at.seconds_per_liquidity_cumulative_x128))
+        } else if target == at_or_after.block_timestamp {
+            Ok((at_or_after.tick_cumulative, at_or_after.seconds_per_liquidity_cumulative_x128))
+        } else {
+            // interpolate between the two surrounding observations
+            let observation_time_delta = at_or_after
+                .block_timestamp
+                .wrapping_sub(before_or_at.block_timestamp);
+            let target_delta = target.wrapping_sub(before_or_at.block_timestamp);
+            let tick_cumulative = before_or_at.tick_cumulative +
+                ((at_or_after.tick_cumulative - before_or_at.tick_cumulative) /
+                    observation_time_delta as i64) *
+                    target_delta as i64;
+            let seconds_per_liquidity_delta = to_uint160(
+                at_or_after
+                    .seconds_per_liquidity_cumulative_x128
+                    .overflowing_sub(before_or_at.seconds_per_liquidity_cumulative_x128)
+                    .0,
+            );
+            let seconds_per_liquidity = to_uint160(
+                before_or_at.seconds_per_liquidity_cumulative_x128 +
+                    seconds_per_liquidity_delta * U256::from(target_delta) /
+                        U256::from(observation_time_delta),
+            );
+            Ok((tick_cumulative, seconds_per_liquidity))
+        }
+    }
+
+    fn get_surrounding_observations(
+        &self,
+        time: u32,
+        target: u32,
+        tick: i32,
+        liquidity: u128,
+    ) -> Result<(Observation, Observation), SimulationError> {
+        // optimistically set before to the newest observation
+        let before_or_at = self.observations[self.index as usize];
+        if lte(time, before_or_at.block_timestamp, target) {
+            if before_or_at.block_timestamp == target {
+                return Ok((before_or_at, Observation::default()));
+            }
+            return Ok((before_or_at, before_or_at.transform(target, tick, liquidity)));
+        }
+
+        // now, set before to the oldest observation
+        let mut oldest = self.observations[((self.index + 1) % self.cardinality) as usize];
+        if !oldest.initialized {
+            oldest = self.observations[0];
+        }
+        if !lte(time, oldest.block_timestamp, target) {
+            return Err(SimulationError::InvalidInput(format!(
+                "Target timestamp {} is older than the oldest observation {}",
+                target, oldest.block_timestamp
+            )));
+        }
+
+        Ok(self.binary_search(time, target))
+    }
+
+    fn binary_search(&self, time: u32, target: u32) -> (Observation, Observation) {
+        let cardinality = self.cardinality as usize;
+        let mut l = (self.index as usize + 1) % cardinality;
+        let mut r = l + cardinality - 1;
+        loop {
+            let i = (l + r) / 2;
+            let before_or_at = self.observations[i % cardinality];
+            if !before_or_at.initialized {
+                l = i + 1;
+                continue;
+            }
+            let at_or_after = self.observations[(i + 1) % cardinality];
+
+            let target_at_or_after = lte(time, before_or_at.block_timestamp, target);
+            if target_at_or_after && lte(time, target, at_or_after.block_timestamp) {
+                return (before_or_at, at_or_after);
+            }
+            if !target_at_or_after {
+                r = i - 1;
+            } else {
+                l = i + 1;
+            }
+        }
+    }
+}
+
+// Solidity spec: function lte(uint32 time, uint32 a, uint32 b) private pure returns (bool)
+/// Comparator for 32-bit timestamps that accounts for overflow, `time` must be chronologically
+/// after both `a` and `b`.
+fn lte(time: u32, a: u32, b: u32) -> bool {
+    if a <= time && b <= time {
+        return a <= b;
+    }
+    let a_adjusted = if a > time { a as u64 } else { a as u64 + (1 << 32) };
+    let b_adjusted = if b > time { b as u64 } else { b as u64 + (1 << 32) };
+    a_adjusted <= b_adjusted
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    #[test]
+    fn test_write_and_observe() {
+        let mut oracle = Oracle::initialize(1000);
+        oracle.grow(3).unwrap();
+
+        oracle.write(1010, 100, 5).unwrap();
+        oracle.write(1010, 200, 5).unwrap();
+        oracle.write(1020, -50, 5).unwrap();
+
+        assert_eq!(oracle.cardinality(), 3);
+        assert_eq!(oracle.index(), 2);
+        let res = oracle
+            .observe(1030, &[0, 10, 15, 20, 25, 30], 10, 5)
+            .unwrap();
+        let tick_cumulatives: Vec<i64> = res
+            .iter()
+            .map(|(tick_cumulative, _)| *tick_cumulative)
+            .collect();
+        assert_eq!(tick_cumulatives, vec![600, 500, 750, 1000, 500, 0]);
+    }
+
+    #[test]
+    fn test_write_wraps_around() {
+        let mut oracle = Oracle::initialize(0);
+        oracle.grow(2).unwrap();
+
+        oracle.write(10, 1, 1).unwrap();
+        oracle.write(20, 2, 1).unwrap();
+
+        assert_eq!(oracle.index(), 0);
+        assert_eq!(
+            oracle
+                .last_observation()
+                .unwrap()
+                .tick_cumulative,
+            30
+        );
+        assert!(matches!(oracle.observe(20, &[15], 2, 1), Err(SimulationError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_seconds_per_liquidity_wraps_at_uint160() {
+        let max_uint160 = (U256::one() << 160) - 1;
+        let observations = vec![Observation {
+            block_timestamp: 10,
+            seconds_per_liquidity_cumulative_x128: max_uint160,
+            initialized: true,
+            ..Default::default()
+        }];
+        let mut oracle = Oracle::new(observations, 0, 1, 2).unwrap();
+
+        oracle.write(12, 0, 1).unwrap();
+
+        let expected = (U256::from(2) << 128) - 1;
+        assert_eq!(
+            oracle
+                .last_observation()
+                .unwrap()
+                .seconds_per_liquidity_cumulative_x128,
+            expected
+        );
+        // interpolating between the observations on both sides of the wrap
+        let res = oracle.observe(12, &[1], 0, 1).unwrap();
+        assert_eq!(res[0].1, (U256::one() << 128) - 1);
+    }
+
+    #[test]
+    fn test_write_uninitialized() {
+        let mut oracle = Oracle::default();
+
+        let res = oracle.write(10, 1, 1);
+
+        assert!(matches!(res, Err(SimulationError::NotInitialized(_))));
+    }
+
+    #[rstest]
+    #[case::index_out_of_cardinality(2, 2, 2)]
+    #[case::next_cardinality_below_cardinality(0, 2, 1)]
+    #[case::uninitialized_observation(1, 2, 2)]
+    #[case::missing_observation(2, 3, 3)]
+    #[case::index_without_cardinality(1, 0, 0)]
+    fn test_new_invalid(#[case] index: u16, #[case] cardinality: u16, #[case] next: u16) {
+        let observations = vec![
+            Observation { block_timestamp: 10, initialized: true, ..Default::default() },
+            Observation::default(),
+        ];
+
+        let res = Oracle::new(observations, index, cardinality, next);
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_new() {
+        let observations =
+            vec![Observation { block_timestamp: 10, initialized: true, ..Default::default() }];
+
+        let oracle = Oracle::new(observations, 0, 1, 3).unwrap();
+
+        assert_eq!(oracle.cardinality_next(), 3);
+        assert_eq!(Oracle::new(Vec::new(), 0, 0, 0).unwrap(), Oracle::default());
+    }
+
+    #[test]
+    fn test_lte_overflow() {
+        assert!(lte(5, u32::MAX - 5, 3));
+        assert!(!lte(5, 3, u32::MAX - 5));
+        assert!(lte(100, 10, 20));
+    }
+}
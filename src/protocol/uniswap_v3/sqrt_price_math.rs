@@ -8,7 +8,7 @@ use crate::{
 
 use super::solidity_math::{mul_div, mul_div_rounding_up};
 
-const Q96: U256 = U256([0, 4294967296, 0, 0]);
+pub const Q96: U256 = U256([0, 4294967296, 0, 0]);
 const RESOLUTION: U256 = U256([96, 0, 0, 0]);
 const U160_MAX: U256 = U256([u64::MAX, u64::MAX, 4294967295, 0]);
 
@@ -1,6 +1,7 @@
-use std::any::Any;
+use std::{any::Any, borrow::Cow, sync::Arc};
 
 use ethers::types::{Sign, I256, U256};
+use serde::{Deserialize, Serialize};
 use tracing::trace;
 
 use tycho_core::{dto::ProtocolStateDelta, Bytes};
@@ -10,32 +11,59 @@ use crate::{
     protocol::{
         errors::{SimulationError, TransitionError},
         events::{check_log_idx, EVMLogMeta, LogIndex},
-        models::GetAmountOutResult,
+        models::{GetAmountOutResult, QuoteResult},
         state::{ProtocolEvent, ProtocolSim},
         BytesConvertible,
     },
     safe_math::{safe_add_u256, safe_sub_u256},
+    serde_helpers::decimal_string,
 };
 
 use super::{
     enums::FeeAmount,
     events::UniswapV3Event,
     liquidity_math,
-    sqrt_price_math::sqrt_price_q96_to_f64,
+    oracle::Oracle,
+    solidity_math::mul_div,
+    sqrt_price_math::{get_amount0_delta, get_amount1_delta, sqrt_price_q96_to_f64},
     swap_math,
     tick_list::{TickInfo, TickList},
     tick_math,
+    tick_provider::{TickProvider, TickProviderHandle},
     tycho_decoder::i24_le_bytes_to_i32,
 };
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+const Q128: U256 = U256([0, 0, 1, 0]);
+/// Number of tick bitmap words requested from the tick provider at once
+const TICK_FETCH_WORDS: i32 = 16;
+
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct UniswapV3State {
+    #[serde(with = "decimal_string")]
     liquidity: u128,
     sqrt_price: U256,
     fee: FeeAmount,
     tick: i32,
-    ticks: TickList,
+    /// Shared between clones, so that states produced by swaps don't copy the tick list. It is
+    /// only copied once a state modifies its ticks (see `Arc::make_mut`).
+    ticks: Arc<TickList>,
+    /// Fee growth per unit of liquidity for token0 and token1. Snapshots don't contain the
+    /// on-chain values, so fees are accounted from the moment the state was created on.
+    fee_growth_global_0_x128: U256,
+    fee_growth_global_1_x128: U256,
     log_index: LogIndex,
+    /// Source of additional ticks for swaps that walk past the known ticks. Not part of
+    /// snapshots, it has to be set again after restoring one.
+    #[serde(skip)]
+    tick_provider: Option<TickProviderHandle>,
+    /// Price and liquidity observations, only updated by swaps simulated at a block timestamp
+    oracle: Oracle,
+    /// The denominators of the protocol's share of the swap fees, packed as on chain: token0 in
+    /// the lower and token1 in the upper 4 bits. 0 means the protocol fee is off.
+    fee_protocol: u8,
+    /// Accumulated protocol fees in token0 and token1
+    protocol_fees_token0: U256,
+    protocol_fees_token1: U256,
 }
 
 #[derive(Debug)]
@@ -45,6 +73,8 @@ struct SwapState {
     sqrt_price: U256,
     tick: i32,
     liquidity: u128,
+    fee_growth_global_x128: U256,
+    protocol_fee: U256,
 }
 
 #[derive(Debug)]
@@ -61,11 +91,63 @@ struct StepComputation {
 // TODO: these attributes allow updating the state after a swap
 #[derive(Debug)]
 struct SwapResults {
+    /// The part of the specified amount that the swap didn't consume
+    amount_remaining: I256,
     amount_calculated: I256,
     sqrt_price: U256,
     liquidity: u128,
     tick: i32,
     gas_used: U256,
+    /// Fee growth of the input token after the swap
+    fee_growth_global_x128: U256,
+    /// Protocol fees accrued by the swap in the input token
+    protocol_fee: U256,
+    /// Initialized ticks crossed by the swap, together with the input token's fee growth at the
+    /// time they were crossed
+    crossed_ticks: Vec<(i32, U256)>,
+    /// The steps of the swap, only recorded if requested
+    steps: Vec<SwapStep>,
+}
+
+/// Result of simulating a change to a liquidity position (mint or burn) on a Uniswap V3 pool.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct LiquidityChangeResult {
+    /// The liquidity added to (mint) or removed from (burn) the position
+    pub liquidity: u128,
+    /// The amount of token0 paid into (mint) or released by (burn) the pool
+    pub amount0: U256,
+    /// The amount of token1 paid into (mint) or released by (burn) the pool
+    pub amount1: U256,
+    /// The state of the pool after the liquidity change
+    pub new_state: UniswapV3State,
+}
+
+/// A single step of a Uniswap V3 swap, i.e. the part of the swap executed within one tick range.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapStep {
+    /// The tick bounding the step's tick range in the direction of the swap
+    pub tick_next: i32,
+    /// Whether the step crossed `tick_next` and it was initialized, i.e. changed the liquidity
+    pub crossed_initialized_tick: bool,
+    pub sqrt_price_start: U256,
+    pub sqrt_price_end: U256,
+    /// The liquidity in range during the step
+    pub liquidity: u128,
+    /// The amount of the input token swapped in this step, excluding fees
+    pub amount_in: U256,
+    pub amount_out: U256,
+    /// The swap fee charged in this step, including the protocol's share
+    pub fee_amount: U256,
+    /// The protocol's share of `fee_amount`
+    pub protocol_fee_amount: U256,
+}
+
+/// Detailed result of a Uniswap V3 swap, listing each step the swap was executed in.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct SwapBreakdown {
+    pub amount_out: U256,
+    pub gas: U256,
+    pub steps: Vec<SwapStep>,
 }
 
 impl UniswapV3State {
@@ -78,7 +160,166 @@ impl UniswapV3State {
     ) -> Self {
         let spacing = UniswapV3State::get_spacing(fee);
         let tick_list = TickList::from(spacing, ticks);
-        UniswapV3State { liquidity, sqrt_price, fee, tick, ticks: tick_list, log_index: (0, 0) }
+        UniswapV3State {
+            liquidity,
+            sqrt_price,
+            fee,
+            tick,
+            ticks: Arc::new(tick_list),
+            fee_growth_global_0_x128: U256::zero(),
+            fee_growth_global_1_x128: U256::zero(),
+            log_index: (0, 0),
+            tick_provider: None,
+            oracle: Oracle::default(),
+            fee_protocol: 0,
+            protocol_fees_token0: U256::zero(),
+            protocol_fees_token1: U256::zero(),
+        }
+    }
+
+    /// Sets the provider used to fetch more ticks if a swap walks past the known ticks.
+    ///
+    /// Without a provider, such swaps fail with `SimulationError::InsufficientData`.
+    pub fn with_tick_provider(mut self, provider: Arc<dyn TickProvider>) -> Self {
+        self.tick_provider = Some(TickProviderHandle::new(provider));
+        self
+    }
+
+    /// Sets the protocol fee, given as the denominators of the protocol's share of the swap fees,
+    /// as `setFeeProtocol` does. Each must be 0 (off) or between 4 and 10.
+    pub fn with_fee_protocol(
+        mut self,
+        fee_protocol0: u8,
+        fee_protocol1: u8,
+    ) -> Result<Self, SimulationError> {
+        self.fee_protocol = pack_fee_protocol(fee_protocol0, fee_protocol1)?;
+        Ok(self)
+    }
+
+    /// Returns the protocol fees accumulated in token0 and token1.
+    pub fn protocol_fees(&self) -> (U256, U256) {
+        (self.protocol_fees_token0, self.protocol_fees_token1)
+    }
+
+    /// Sets the oracle observations of the pool, e.g. as read from chain.
+    pub fn with_oracle(mut self, oracle: Oracle) -> Self {
+        self.oracle = oracle;
+        self
+    }
+
+    pub fn oracle(&self) -> &Oracle {
+        &self.oracle
+    }
+
+    /// Increases the number of observations the oracle stores, as
+    /// `increaseObservationCardinalityNext` does.
+    pub fn increase_observation_cardinality_next(
+        &mut self,
+        cardinality_next: u16,
+    ) -> Result<(), SimulationError> {
+        self.oracle.grow(cardinality_next)
+    }
+
+    /// Simulates selling `amount_in` of `token_in` in a block with the given timestamp, writing
+    /// an oracle observation if the swap moves the tick.
+    ///
+    /// If the oracle has no observations yet, it is initialized at `block_timestamp`.
+    pub fn get_amount_out_at(
+        &self,
+        amount_in: U256,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+        block_timestamp: u32,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        let (amount_out, gas, mut new_state) =
+            self.swap_to_new_state(amount_in, token_in, token_out)?;
+        new_state.write_observation(block_timestamp, new_state.tick != self.tick, self)?;
+        Ok(GetAmountOutResult::new(amount_out, gas, Box::new(new_state)))
+    }
+
+    /// Simulates minting like `mint` in a block with the given timestamp, writing an oracle
+    /// observation if the range contains the current tick, as `_modifyPosition` does.
+    ///
+    /// If the oracle has no observations yet, it is initialized at `block_timestamp`.
+    pub fn mint_at(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        block_timestamp: u32,
+    ) -> Result<LiquidityChangeResult, SimulationError> {
+        let mut res = self.mint(tick_lower, tick_upper, liquidity)?;
+        let in_range = tick_lower <= self.tick && self.tick < tick_upper;
+        res.new_state
+            .write_observation(block_timestamp, in_range, self)?;
+        Ok(res)
+    }
+
+    /// Simulates burning like `burn` in a block with the given timestamp, writing an oracle
+    /// observation if the range contains the current tick, as `_modifyPosition` does.
+    ///
+    /// If the oracle has no observations yet, it is initialized at `block_timestamp`.
+    pub fn burn_at(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        block_timestamp: u32,
+    ) -> Result<LiquidityChangeResult, SimulationError> {
+        let mut res = self.burn(tick_lower, tick_upper, liquidity)?;
+        let in_range = tick_lower <= self.tick && self.tick < tick_upper;
+        res.new_state
+            .write_observation(block_timestamp, in_range, self)?;
+        Ok(res)
+    }
+
+    /// Initializes the oracle at `block_timestamp` if needed and, if `write` is set, records an
+    /// observation of the tick and liquidity of `before`, the state before the change.
+    fn write_observation(
+        &mut self,
+        block_timestamp: u32,
+        write: bool,
+        before: &UniswapV3State,
+    ) -> Result<(), SimulationError> {
+        if !self.oracle.is_initialized() {
+            self.oracle = Oracle::initialize(block_timestamp);
+        }
+        if write {
+            self.oracle
+                .write(block_timestamp, before.tick, before.liquidity)?;
+        }
+        Ok(())
+    }
+
+    /// Returns the tick and seconds per liquidity accumulators as of each `seconds_agos` before
+    /// `block_timestamp`, as the pool's `observe` does.
+    pub fn observe(
+        &self,
+        block_timestamp: u32,
+        seconds_agos: &[u32],
+    ) -> Result<Vec<(i64, U256)>, SimulationError> {
+        self.oracle
+            .observe(block_timestamp, seconds_agos, self.tick, self.liquidity)
+    }
+
+    /// Returns the time weighted average tick over the `seconds_ago` seconds before
+    /// `block_timestamp`, rounded towards negative infinity as the periphery's `OracleLibrary`
+    /// does.
+    pub fn twap_tick(
+        &self,
+        block_timestamp: u32,
+        seconds_ago: u32,
+    ) -> Result<i32, SimulationError> {
+        if seconds_ago == 0 {
+            return Err(SimulationError::InvalidInput("TWAP period must be positive".to_string()));
+        }
+        let cumulatives = self.observe(block_timestamp, &[seconds_ago, 0])?;
+        let tick_cumulative_delta = cumulatives[1].0 - cumulatives[0].0;
+        let mut mean_tick = tick_cumulative_delta / seconds_ago as i64;
+        if tick_cumulative_delta < 0 && tick_cumulative_delta % seconds_ago as i64 != 0 {
+            mean_tick -= 1;
+        }
+        Ok(mean_tick as i32)
     }
 
     fn get_spacing(fee: FeeAmount) -> u16 {
@@ -100,9 +341,422 @@ impl UniswapV3State {
                     self.liquidity += amount as u128;
                 }
             }
-            self.ticks
-                .apply_liquidity_change(lower, upper, amount);
+            let ticks = Arc::make_mut(&mut self.ticks);
+            let lower_is_new = ticks.get_tick(lower).is_err();
+            let upper_is_new = ticks.get_tick(upper).is_err();
+            ticks.apply_liquidity_change(lower, upper, amount);
+
+            // By convention, all fee growth is assumed to have happened below a newly initialized
+            // tick
+            for (index, is_new) in [(lower, lower_is_new), (upper, upper_is_new)] {
+                if is_new && index <= self.tick {
+                    if let Ok(tick) = ticks.get_tick_mut(index) {
+                        tick.fee_growth_outside_0_x128 = self.fee_growth_global_0_x128;
+                        tick.fee_growth_outside_1_x128 = self.fee_growth_global_1_x128;
+                    }
+                }
+            }
+        }
+    }
+
+    /// Returns the fee growth per unit of liquidity inside the range `[tick_lower, tick_upper)`
+    /// for token0 and token1.
+    pub fn fee_growth_inside(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+    ) -> Result<(U256, U256), SimulationError> {
+        self.check_ticks(tick_lower, tick_upper)?;
+        let (lower_0, lower_1) = self.fee_growth_outside(tick_lower);
+        let (upper_0, upper_1) = self.fee_growth_outside(tick_upper);
+        let global_0 = self.fee_growth_global_0_x128;
+        let global_1 = self.fee_growth_global_1_x128;
+
+        let (below_0, below_1) = if self.tick >= tick_lower {
+            (lower_0, lower_1)
+        } else {
+            (global_0.overflowing_sub(lower_0).0, global_1.overflowing_sub(lower_1).0)
+        };
+        let (above_0, above_1) = if self.tick < tick_upper {
+            (upper_0, upper_1)
+        } else {
+            (global_0.overflowing_sub(upper_0).0, global_1.overflowing_sub(upper_1).0)
+        };
+
+        Ok((
+            global_0
+                .overflowing_sub(below_0)
+                .0
+                .overflowing_sub(above_0)
+                .0,
+            global_1
+                .overflowing_sub(below_1)
+                .0
+                .overflowing_sub(above_1)
+                .0,
+        ))
+    }
+
+    /// Returns the fees in token0 and token1 owed to a position with `liquidity` on the range
+    /// `[tick_lower, tick_upper)`.
+    ///
+    /// `fee_growth_inside_last` is the value of `fee_growth_inside` for the range when the
+    /// position was last touched, e.g. right after minting it. Applying swaps to the state and
+    /// calling this method on the resulting state projects the earnings of the position.
+    pub fn fees_owed(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        fee_growth_inside_last: (U256, U256),
+    ) -> Result<(U256, U256), SimulationError> {
+        let (inside_0, inside_1) = self.fee_growth_inside(tick_lower, tick_upper)?;
+        let fees_0 = mul_div(
+            inside_0
+                .overflowing_sub(fee_growth_inside_last.0)
+                .0,
+            U256::from(liquidity),
+            Q128,
+        )?;
+        let fees_1 = mul_div(
+            inside_1
+                .overflowing_sub(fee_growth_inside_last.1)
+                .0,
+            U256::from(liquidity),
+            Q128,
+        )?;
+        Ok((fees_0, fees_1))
+    }
+
+    /// Distributes the fees paid for a flash loan between the protocol and the liquidity
+    /// providers in range, as `flash` does.
+    fn handle_flash_fees(&mut self, paid0: U256, paid1: U256) -> Result<(), SimulationError> {
+        // flash loans require liquidity, so there is always someone to distribute the fees to
+        if self.liquidity == 0 {
+            return Err(SimulationError::NoLiquidity());
+        }
+        let liquidity = U256::from(self.liquidity);
+        let fee_protocol0 = self.fee_protocol % 16;
+        let fee_protocol1 = self.fee_protocol >> 4;
+        if paid0 > U256::zero() {
+            let protocol_fee =
+                if fee_protocol0 == 0 { U256::zero() } else { paid0 / U256::from(fee_protocol0) };
+            self.protocol_fees_token0 = safe_add_u256(self.protocol_fees_token0, protocol_fee)?;
+            self.fee_growth_global_0_x128 = self
+                .fee_growth_global_0_x128
+                .overflowing_add(mul_div(safe_sub_u256(paid0, protocol_fee)?, Q128, liquidity)?)
+                .0;
+        }
+        if paid1 > U256::zero() {
+            let protocol_fee =
+                if fee_protocol1 == 0 { U256::zero() } else { paid1 / U256::from(fee_protocol1) };
+            self.protocol_fees_token1 = safe_add_u256(self.protocol_fees_token1, protocol_fee)?;
+            self.fee_growth_global_1_x128 = self
+                .fee_growth_global_1_x128
+                .overflowing_add(mul_div(safe_sub_u256(paid1, protocol_fee)?, Q128, liquidity)?)
+                .0;
+        }
+        Ok(())
+    }
+
+    fn fee_growth_outside(&self, index: i32) -> (U256, U256) {
+        self.ticks
+            .get_tick(index)
+            .map(|tick| (tick.fee_growth_outside_0_x128, tick.fee_growth_outside_1_x128))
+            .unwrap_or_default()
+    }
+
+    /// Applies the fee growth and protocol fees accrued during a swap, flipping the fee growth
+    /// outside of every crossed tick.
+    fn apply_fee_growth(
+        &mut self,
+        zero_for_one: bool,
+        fee_growth_global_x128: U256,
+        protocol_fee: U256,
+        crossed_ticks: &[(i32, U256)],
+    ) {
+        if zero_for_one {
+            self.fee_growth_global_0_x128 = fee_growth_global_x128;
+            self.protocol_fees_token0 = self
+                .protocol_fees_token0
+                .saturating_add(protocol_fee);
+        } else {
+            self.fee_growth_global_1_x128 = fee_growth_global_x128;
+            self.protocol_fees_token1 = self
+                .protocol_fees_token1
+                .saturating_add(protocol_fee);
+        }
+        if crossed_ticks.is_empty() {
+            return;
+        }
+        let ticks = Arc::make_mut(&mut self.ticks);
+        for (index, fee_growth_in) in crossed_ticks {
+            let (global_0, global_1) = if zero_for_one {
+                (*fee_growth_in, self.fee_growth_global_1_x128)
+            } else {
+                (self.fee_growth_global_0_x128, *fee_growth_in)
+            };
+            if let Ok(tick) = ticks.get_tick_mut(*index) {
+                tick.fee_growth_outside_0_x128 = global_0
+                    .overflowing_sub(tick.fee_growth_outside_0_x128)
+                    .0;
+                tick.fee_growth_outside_1_x128 = global_1
+                    .overflowing_sub(tick.fee_growth_outside_1_x128)
+                    .0;
+            }
+        }
+    }
+
+    /// Simulates minting `liquidity` on the range `[tick_lower, tick_upper)`.
+    ///
+    /// The returned amounts are the tokens that need to be paid into the pool, rounded up as the
+    /// pool contract does.
+    pub fn mint(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+    ) -> Result<LiquidityChangeResult, SimulationError> {
+        self.check_ticks(tick_lower, tick_upper)?;
+        if liquidity == 0 {
+            return Err(SimulationError::InsufficientAmount());
+        }
+        if tick_lower <= self.tick && self.tick < tick_upper {
+            self.liquidity
+                .checked_add(liquidity)
+                .ok_or(SimulationError::ArithmeticOverflow())?;
+        }
+        let liquidity_delta =
+            i128::try_from(liquidity).map_err(|_| SimulationError::ArithmeticOverflow())?;
+
+        let (amount0, amount1) =
+            self.get_amounts_for_liquidity(tick_lower, tick_upper, liquidity, true)?;
+        let mut new_state = self.clone();
+        new_state.handle_liquidity_change(tick_lower, tick_upper, liquidity_delta);
+
+        Ok(LiquidityChangeResult { liquidity, amount0, amount1, new_state })
+    }
+
+    /// Simulates minting the maximum liquidity on the range `[tick_lower, tick_upper)` that the
+    /// desired token amounts allow for, as the periphery's `LiquidityAmounts` library does.
+    ///
+    /// The amounts consumed never exceed the desired amounts.
+    pub fn mint_for_amounts(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        amount0_desired: U256,
+        amount1_desired: U256,
+    ) -> Result<LiquidityChangeResult, SimulationError> {
+        self.check_ticks(tick_lower, tick_upper)?;
+        let liquidity = liquidity_math::get_liquidity_for_amounts(
+            self.sqrt_price,
+            tick_math::get_sqrt_ratio_at_tick(tick_lower)?,
+            tick_math::get_sqrt_ratio_at_tick(tick_upper)?,
+            amount0_desired,
+            amount1_desired,
+        )?;
+        self.mint(tick_lower, tick_upper, liquidity)
+    }
+
+    /// Simulates burning `liquidity` from the range `[tick_lower, tick_upper)`.
+    ///
+    /// The returned amounts are the tokens released by the pool, rounded down as the pool contract
+    /// does. Individual positions are not tracked, so burning liquidity that was never minted can
+    /// only be detected if the range contains the current tick.
+    pub fn burn(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+    ) -> Result<LiquidityChangeResult, SimulationError> {
+        self.check_ticks(tick_lower, tick_upper)?;
+        if liquidity == 0 {
+            return Err(SimulationError::InsufficientAmount());
+        }
+        if tick_lower <= self.tick && self.tick < tick_upper && liquidity > self.liquidity {
+            return Err(SimulationError::NoLiquidity());
+        }
+        let liquidity_delta =
+            i128::try_from(liquidity).map_err(|_| SimulationError::ArithmeticOverflow())?;
+
+        let (amount0, amount1) =
+            self.get_amounts_for_liquidity(tick_lower, tick_upper, liquidity, false)?;
+        let mut new_state = self.clone();
+        new_state.handle_liquidity_change(tick_lower, tick_upper, -liquidity_delta);
+
+        Ok(LiquidityChangeResult { liquidity, amount0, amount1, new_state })
+    }
+
+    fn check_ticks(&self, tick_lower: i32, tick_upper: i32) -> Result<(), SimulationError> {
+        if tick_lower >= tick_upper {
+            return Err(SimulationError::InvalidInput(format!(
+                "Lower tick {} must be below upper tick {}",
+                tick_lower, tick_upper
+            )));
+        }
+        if tick_lower < tick_math::MIN_TICK || tick_upper > tick_math::MAX_TICK {
+            return Err(SimulationError::InvalidInput(format!(
+                "Tick range [{}, {}) is out of bounds",
+                tick_lower, tick_upper
+            )));
+        }
+        let spacing = UniswapV3State::get_spacing(self.fee) as i32;
+        if tick_lower % spacing != 0 || tick_upper % spacing != 0 {
+            return Err(SimulationError::InvalidInput(format!(
+                "Ticks {} and {} must be multiples of the tick spacing {}",
+                tick_lower, tick_upper, spacing
+            )));
+        }
+        Ok(())
+    }
+
+    // Solidity spec: Pool._modifyPosition
+    fn get_amounts_for_liquidity(
+        &self,
+        tick_lower: i32,
+        tick_upper: i32,
+        liquidity: u128,
+        round_up: bool,
+    ) -> Result<(U256, U256), SimulationError> {
+        let sqrt_price_lower = tick_math::get_sqrt_ratio_at_tick(tick_lower)?;
+        let sqrt_price_upper = tick_math::get_sqrt_ratio_at_tick(tick_upper)?;
+
+        if self.tick < tick_lower {
+            // current tick is below the range, the position is entirely in token0
+            let amount0 =
+                get_amount0_delta(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?;
+            Ok((amount0, U256::zero()))
+        } else if self.tick < tick_upper {
+            let amount0 =
+                get_amount0_delta(self.sqrt_price, sqrt_price_upper, liquidity, round_up)?;
+            let amount1 =
+                get_amount1_delta(sqrt_price_lower, self.sqrt_price, liquidity, round_up)?;
+            Ok((amount0, amount1))
+        } else {
+            // current tick is above the range, the position is entirely in token1
+            let amount1 =
+                get_amount1_delta(sqrt_price_lower, sqrt_price_upper, liquidity, round_up)?;
+            Ok((U256::zero(), amount1))
+        }
+    }
+
+    /// Simulates selling `amount_in` of `token_in` and returns every step the swap was executed
+    /// in, together with the resulting amount out and gas estimate.
+    ///
+    /// This is meant for debugging and tooling; use `get_amount_out` for regular quotes.
+    pub fn get_amount_out_detailed(
+        &self,
+        amount_in: U256,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<SwapBreakdown, SimulationError> {
+        let zero_for_one = token_in < token_out;
+        let amount_specified = Self::pool_amount_specified(amount_in, token_in);
+
+        let (result, _) = self.swap_fetching_ticks(zero_for_one, amount_specified, None, true)?;
+
+        Ok(SwapBreakdown {
+            amount_out: token_out.amount_after_buy_tax(
+                result
+                    .amount_calculated
+                    .abs()
+                    .into_raw(),
+            ),
+            gas: result.gas_used,
+            steps: result.steps,
+        })
+    }
+
+    /// Runs `swap`, fetching missing ticks from the tick provider whenever the swap walks past
+    /// the known ticks and resuming it on the extended tick list.
+    ///
+    /// Ticks already fetched through the provider, e.g. by earlier quotes, are added before the
+    /// swap runs, so the provider is only asked for ranges that weren't searched yet.
+    ///
+    /// Returns the swap results and, if ticks were fetched, the extended tick list.
+    fn swap_fetching_ticks(
+        &self,
+        zero_for_one: bool,
+        amount_specified: I256,
+        sqrt_price_limit: Option<U256>,
+        record_steps: bool,
+    ) -> Result<(SwapResults, Option<Arc<TickList>>), SimulationError> {
+        let provider = match &self.tick_provider {
+            Some(provider) => provider,
+            None => {
+                return self
+                    .swap(zero_for_one, amount_specified, sqrt_price_limit, record_steps)
+                    .map(|res| (res, None))
+            }
+        };
+
+        let mut state = Cow::Borrowed(self);
+        // Start from the ticks other swaps on this or related states already fetched
+        let (fetched, mut searched_until) = provider.fetched_ticks(zero_for_one);
+        if fetched
+            .iter()
+            .any(|tick| self.ticks.get_tick(tick.index).is_err())
+        {
+            state.to_mut().insert_ticks(fetched)?;
+        }
+        loop {
+            let partial =
+                match state.swap(zero_for_one, amount_specified, sqrt_price_limit, record_steps) {
+                    Ok(res) => {
+                        let ticks = match state {
+                            Cow::Borrowed(_) => None,
+                            Cow::Owned(extended) => Some(extended.ticks),
+                        };
+                        return Ok((res, ticks));
+                    }
+                    Err(SimulationError::InsufficientData(partial)) => partial,
+                    Err(err) => return Err(err),
+                };
+
+            let spacing = UniswapV3State::get_spacing(self.fee) as i32;
+            let (smallest, largest) = state
+                .ticks
+                .bounds()
+                .unwrap_or((state.tick, state.tick));
+            let range = TICK_FETCH_WORDS * 256 * spacing;
+            let (lower, upper) = if zero_for_one {
+                let upper = searched_until.unwrap_or(smallest) - 1;
+                ((upper - range).max(tick_math::MIN_TICK), upper)
+            } else {
+                let lower = searched_until.unwrap_or(largest) + 1;
+                (lower, (lower + range).min(tick_math::MAX_TICK))
+            };
+            if lower > upper {
+                // There are no more ticks to fetch in the direction of the swap
+                return Err(SimulationError::InsufficientData(partial));
+            }
+            searched_until = Some(if zero_for_one { lower } else { upper });
+
+            let ticks = provider.get_ticks(lower, upper, zero_for_one)?;
+            if ticks.is_empty() {
+                continue;
+            }
+            state.to_mut().insert_ticks(ticks)?;
+        }
+    }
+
+    /// Adds ticks fetched from a tick provider, initializing their fee growth outside by the same
+    /// convention as newly initialized ticks.
+    fn insert_ticks(&mut self, ticks: Vec<TickInfo>) -> Result<(), SimulationError> {
+        let ticks_list = Arc::make_mut(&mut self.ticks);
+        let inserted = ticks_list
+            .insert_ticks(ticks)
+            .map_err(SimulationError::InvalidInput)?;
+        for index in inserted {
+            if index <= self.tick {
+                if let Ok(tick) = ticks_list.get_tick_mut(index) {
+                    tick.fee_growth_outside_0_x128 = self.fee_growth_global_0_x128;
+                    tick.fee_growth_outside_1_x128 = self.fee_growth_global_1_x128;
+                }
+            }
         }
+        Ok(())
     }
 
     fn swap(
@@ -110,6 +764,7 @@ impl UniswapV3State {
         zero_for_one: bool,
         amount_specified: I256,
         sqrt_price_limit: Option<U256>,
+        record_steps: bool,
     ) -> Result<SwapResults, SimulationError> {
         if self.liquidity == 0 {
             return Err(SimulationError::NoLiquidity());
@@ -138,7 +793,17 @@ impl UniswapV3State {
             sqrt_price: self.sqrt_price,
             tick: self.tick,
             liquidity: self.liquidity,
+            fee_growth_global_x128: if zero_for_one {
+                self.fee_growth_global_0_x128
+            } else {
+                self.fee_growth_global_1_x128
+            },
+            protocol_fee: U256::zero(),
         };
+        let fee_protocol =
+            if zero_for_one { self.fee_protocol % 16 } else { self.fee_protocol >> 4 };
+        let mut crossed_ticks = Vec::new();
+        let mut steps = Vec::new();
         let mut gas_used = U256::from(130_000);
 
         while state.amount_remaining != I256::zero() && state.sqrt_price != price_limit {
@@ -153,6 +818,12 @@ impl UniswapV3State {
                         new_state.liquidity = state.liquidity;
                         new_state.tick = state.tick;
                         new_state.sqrt_price = state.sqrt_price;
+                        new_state.apply_fee_growth(
+                            zero_for_one,
+                            state.fee_growth_global_x128,
+                            state.protocol_fee,
+                            &crossed_ticks,
+                        );
                         return Err(SimulationError::InsufficientData(GetAmountOutResult::new(
                             state.amount_calculated.abs().into_raw(),
                             gas_used,
@@ -173,7 +844,6 @@ impl UniswapV3State {
                 state.amount_remaining,
                 self.fee as u32,
             )?;
-            state.sqrt_price = sqrt_price;
 
             let step = StepComputation {
                 sqrt_price_start: state.sqrt_price,
@@ -184,6 +854,8 @@ impl UniswapV3State {
                 amount_out,
                 fee_amount,
             };
+            state.sqrt_price = sqrt_price;
+            let liquidity_in_range = state.liquidity;
             if exact_input {
                 state.amount_remaining -= I256::checked_from_sign_and_abs(
                     Sign::Positive,
@@ -201,8 +873,23 @@ impl UniswapV3State {
                 )
                 .unwrap();
             }
+            // if the protocol fee is on, calculate how much is owed and decrement the LP fee
+            let mut lp_fee = step.fee_amount;
+            let mut protocol_fee = U256::zero();
+            if fee_protocol > 0 {
+                protocol_fee = step.fee_amount / U256::from(fee_protocol);
+                lp_fee = safe_sub_u256(lp_fee, protocol_fee)?;
+                state.protocol_fee = safe_add_u256(state.protocol_fee, protocol_fee)?;
+            }
+            if state.liquidity > 0 {
+                state.fee_growth_global_x128 = state
+                    .fee_growth_global_x128
+                    .overflowing_add(mul_div(lp_fee, Q128, U256::from(state.liquidity))?)
+                    .0;
+            }
             if state.sqrt_price == step.sqrt_price_next {
                 if step.initialized {
+                    crossed_ticks.push((step.tick_next, state.fee_growth_global_x128));
                     let liquidity_raw = self
                         .ticks
                         .get_tick(step.tick_next)
@@ -216,17 +903,84 @@ impl UniswapV3State {
             } else if state.sqrt_price != step.sqrt_price_start {
                 state.tick = tick_math::get_tick_at_sqrt_ratio(state.sqrt_price)?;
             }
+            if record_steps {
+                steps.push(SwapStep {
+                    tick_next: step.tick_next,
+                    crossed_initialized_tick: step.initialized &&
+                        state.sqrt_price == step.sqrt_price_next,
+                    sqrt_price_start: step.sqrt_price_start,
+                    sqrt_price_end: state.sqrt_price,
+                    liquidity: liquidity_in_range,
+                    amount_in: step.amount_in,
+                    amount_out: step.amount_out,
+                    fee_amount: step.fee_amount,
+                    protocol_fee_amount: protocol_fee,
+                });
+            }
             gas_used = safe_add_u256(gas_used, U256::from(2000))?;
         }
         Ok(SwapResults {
+            amount_remaining: state.amount_remaining,
             amount_calculated: state.amount_calculated,
             sqrt_price: state.sqrt_price,
             liquidity: state.liquidity,
             tick: state.tick,
             gas_used,
+            fee_growth_global_x128: state.fee_growth_global_x128,
+            protocol_fee: state.protocol_fee,
+            crossed_ticks,
+            steps,
         })
     }
 
+    /// Swaps `amount_in` of `token_a` for `token_b` and returns the amount out, the gas used and
+    /// the state of the pool after the swap.
+    fn swap_to_new_state(
+        &self,
+        amount_in: U256,
+        token_a: &ERC20Token,
+        token_b: &ERC20Token,
+    ) -> Result<(U256, U256, UniswapV3State), SimulationError> {
+        let zero_for_one = token_a < token_b;
+        let amount_specified = Self::pool_amount_specified(amount_in, token_a);
+
+        let (result, fetched_ticks) =
+            self.swap_fetching_ticks(zero_for_one, amount_specified, None, false)?;
+
+        trace!(?amount_in, ?token_a, ?token_b, ?zero_for_one, ?result, "V3 SWAP");
+        let mut new_state = self.clone();
+        if let Some(ticks) = fetched_ticks {
+            new_state.ticks = ticks;
+        }
+        new_state.liquidity = result.liquidity;
+        new_state.tick = result.tick;
+        new_state.sqrt_price = result.sqrt_price;
+        new_state.apply_fee_growth(
+            zero_for_one,
+            result.fee_growth_global_x128,
+            result.protocol_fee,
+            &result.crossed_ticks,
+        );
+
+        Ok((
+            token_b.amount_after_buy_tax(
+                result
+                    .amount_calculated
+                    .abs()
+                    .into_raw(),
+            ),
+            result.gas_used,
+            new_state,
+        ))
+    }
+
+    /// Returns the exact input the pool receives when `amount_in` of `token_in` is sold, i.e.
+    /// after the token's sell tax.
+    fn pool_amount_specified(amount_in: U256, token_in: &ERC20Token) -> I256 {
+        I256::checked_from_sign_and_abs(Sign::Positive, token_in.amount_after_sell_tax(amount_in))
+            .unwrap()
+    }
+
     fn get_sqrt_ratio_target(
         sqrt_price_next: U256,
         sqrt_price_limit: U256,
@@ -246,6 +1000,20 @@ impl UniswapV3State {
     }
 }
 
+/// Packs the protocol fee denominators of token0 and token1 as the pool stores them. Each must be
+/// 0 (off) or between 4 and 10, as `setFeeProtocol` requires.
+fn pack_fee_protocol(fee_protocol0: u8, fee_protocol1: u8) -> Result<u8, SimulationError> {
+    for fee_protocol in [fee_protocol0, fee_protocol1] {
+        if fee_protocol != 0 && !(4..=10).contains(&fee_protocol) {
+            return Err(SimulationError::InvalidInput(format!(
+                "Invalid protocol fee {}",
+                fee_protocol
+            )));
+        }
+    }
+    Ok(fee_protocol0 + (fee_protocol1 << 4))
+}
+
 impl ProtocolSim for UniswapV3State {
     fn fee(&self) -> f64 {
         (self.fee as u32) as f64 / 1_000_000.0
@@ -266,24 +1034,175 @@ impl ProtocolSim for UniswapV3State {
         token_a: &ERC20Token,
         token_b: &ERC20Token,
     ) -> Result<GetAmountOutResult, SimulationError> {
-        let zero_for_one = token_a < token_b;
-        let amount_specified = I256::checked_from_sign_and_abs(Sign::Positive, amount_in).unwrap();
+        let (amount_out, gas, new_state) = self.swap_to_new_state(amount_in, token_a, token_b)?;
+        Ok(GetAmountOutResult::new(amount_out, gas, Box::new(new_state)))
+    }
 
-        let result = self.swap(zero_for_one, amount_specified, None)?;
+    fn quote_amount_out(
+        &self,
+        amount_in: U256,
+        token_a: &ERC20Token,
+        token_b: &ERC20Token,
+    ) -> Result<QuoteResult, SimulationError> {
+        let zero_for_one = token_a < token_b;
+        let amount_specified = Self::pool_amount_specified(amount_in, token_a);
 
-        trace!(?amount_in, ?token_a, ?token_b, ?zero_for_one, ?result, "V3 SWAP");
-        let mut new_state = self.clone();
-        new_state.liquidity = result.liquidity;
-        new_state.tick = result.tick;
-        new_state.sqrt_price = result.sqrt_price;
+        let (result, _) = self.swap_fetching_ticks(zero_for_one, amount_specified, None, false)?;
 
-        Ok(GetAmountOutResult::new(
-            result
-                .amount_calculated
-                .abs()
-                .into_raw(),
+        Ok(QuoteResult::new(
+            token_b.amount_after_buy_tax(
+                result
+                    .amount_calculated
+                    .abs()
+                    .into_raw(),
+            ),
             result.gas_used,
-            Box::new(new_state),
+        ))
+    }
+
+    /// Quotes all amounts with a single swap of the largest amount, if they are sorted in
+    /// ascending order.
+    ///
+    /// Every smaller amount runs through the same steps as the largest one until it is used up,
+    /// so its amount out is the output of the steps it fully covers plus one partial step. If the
+    /// largest amount can't be quoted, every amount is quoted on its own.
+    fn get_amounts_out(
+        &self,
+        amounts_in: &[U256],
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<Vec<Result<QuoteResult, SimulationError>>, SimulationError> {
+        let quote_each = || {
+            Ok(amounts_in
+                .iter()
+                .map(|amount_in| self.quote_amount_out(*amount_in, token_in, token_out))
+                .collect())
+        };
+        let max_amount = match amounts_in.last() {
+            Some(max_amount)
+                if amounts_in
+                    .windows(2)
+                    .all(|w| w[0] <= w[1]) =>
+            {
+                *max_amount
+            }
+            _ => return quote_each(),
+        };
+
+        let zero_for_one = token_in < token_out;
+        let amount_specified = Self::pool_amount_specified(max_amount, token_in);
+        let result = match self.swap_fetching_ticks(zero_for_one, amount_specified, None, true) {
+            Ok((result, _)) => result,
+            // the largest amount can't be quoted, the smaller ones might still be
+            Err(_) => return quote_each(),
+        };
+        let price_limit = if zero_for_one {
+            safe_add_u256(tick_math::MIN_SQRT_RATIO, U256::one())?
+        } else {
+            safe_sub_u256(tick_math::MAX_SQRT_RATIO, U256::one())?
+        };
+
+        let mut quotes = Vec::with_capacity(amounts_in.len());
+        // index of the first step not fully covered by the current amount, together with the
+        // input consumed and output produced by the steps before it
+        let (mut step_idx, mut consumed, mut amount_out) = (0, U256::zero(), U256::zero());
+        for amount_in in amounts_in {
+            // the sell tax is monotonic, so the taxed amounts are still sorted
+            let pool_amount_in = token_in.amount_after_sell_tax(*amount_in);
+            while let Some(step) = result.steps.get(step_idx) {
+                let step_consumed = step.amount_in + step.fee_amount;
+                if consumed + step_consumed > pool_amount_in {
+                    break;
+                }
+                consumed += step_consumed;
+                amount_out += step.amount_out;
+                step_idx += 1;
+            }
+
+            let remaining = pool_amount_in - consumed;
+            let step = match result.steps.get(step_idx) {
+                Some(step) if !remaining.is_zero() => step,
+                // the amount is used up exactly by the covered steps or, if all steps are
+                // covered, the swap stops at the price limit
+                _ => {
+                    quotes.push(Ok(QuoteResult::new(
+                        token_out.amount_after_buy_tax(amount_out),
+                        U256::from(130_000 + 2000 * step_idx as u64),
+                    )));
+                    continue;
+                }
+            };
+            let sqrt_price_target = UniswapV3State::get_sqrt_ratio_target(
+                tick_math::get_sqrt_ratio_at_tick(step.tick_next)?,
+                price_limit,
+                zero_for_one,
+            );
+            let (sqrt_price, _, step_amount_out, _) = swap_math::compute_swap_step(
+                step.sqrt_price_start,
+                sqrt_price_target,
+                step.liquidity,
+                I256::checked_from_sign_and_abs(Sign::Positive, remaining).unwrap(),
+                self.fee as u32,
+            )?;
+            if sqrt_price == sqrt_price_target {
+                // rounding lets the remaining amount reach the end of the step, in which case the
+                // swap continues differently than the largest one
+                quotes.push(self.quote_amount_out(*amount_in, token_in, token_out));
+            } else {
+                quotes.push(Ok(QuoteResult::new(
+                    token_out.amount_after_buy_tax(amount_out + step_amount_out),
+                    U256::from(130_000 + 2000 * (step_idx as u64 + 1)),
+                )));
+            }
+        }
+        Ok(quotes)
+    }
+
+    /// Returns the limits of a trade that moves the price up to the furthest known tick.
+    ///
+    /// Only the ticks already in the tick list are considered, ticks that a tick provider could
+    /// still fetch are not.
+    ///
+    /// # Returns
+    ///
+    /// * `(max_in, max_out)` - The amount of input, including fees, needed to move the price to the
+    ///   last known tick in the direction of the trade and the amount of output received for it, or
+    ///   zero if there is no liquidity in that direction.
+    fn get_limits(
+        &self,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<(U256, U256), SimulationError> {
+        let zero_for_one = token_in < token_out;
+        let (smallest, largest) = match self.ticks.bounds() {
+            Some(bounds) => bounds,
+            None => return Ok((U256::zero(), U256::zero())),
+        };
+        let price_limit = if zero_for_one {
+            tick_math::get_sqrt_ratio_at_tick(smallest.max(tick_math::MIN_TICK))?
+                .max(safe_add_u256(tick_math::MIN_SQRT_RATIO, U256::one())?)
+        } else {
+            tick_math::get_sqrt_ratio_at_tick(largest.min(tick_math::MAX_TICK))?
+                .min(safe_sub_u256(tick_math::MAX_SQRT_RATIO, U256::one())?)
+        };
+        let reachable = if zero_for_one {
+            price_limit < self.sqrt_price
+        } else {
+            price_limit > self.sqrt_price
+        };
+        if self.liquidity == 0 || !reachable {
+            return Ok((U256::zero(), U256::zero()));
+        }
+
+        let result = self.swap(zero_for_one, I256::MAX, Some(price_limit), false)?;
+        let pool_max_in = (I256::MAX - result.amount_remaining).into_raw();
+        let pool_max_out = result
+            .amount_calculated
+            .abs()
+            .into_raw();
+        Ok((
+            token_in.max_amount_before_sell_tax(pool_max_in),
+            token_out.amount_after_buy_tax(pool_max_out),
         ))
     }
 
@@ -346,7 +1265,7 @@ impl ProtocolSim for UniswapV3State {
             // tick liquidity keys are in the format "tick/{tick_index}/net_liquidity"
             if key.starts_with("ticks/") {
                 let parts: Vec<&str> = key.split('/').collect();
-                self.ticks.set_tick_liquidity(
+                Arc::make_mut(&mut self.ticks).set_tick_liquidity(
                     parts[1]
                         .parse::<i32>()
                         .map_err(|err| TransitionError::DecodeError(err.to_string()))?,
@@ -359,7 +1278,7 @@ impl ProtocolSim for UniswapV3State {
             // tick liquidity keys are in the format "tick/{tick_index}/net_liquidity"
             if key.starts_with("tick/") {
                 let parts: Vec<&str> = key.split('/').collect();
-                self.ticks.set_tick_liquidity(
+                Arc::make_mut(&mut self.ticks).set_tick_liquidity(
                     parts[1]
                         .parse::<i32>()
                         .map_err(|err| TransitionError::DecodeError(err.to_string()))?,
@@ -393,6 +1312,33 @@ impl ProtocolSim for UniswapV3State {
                     self.tick = data.tick;
                     self.sqrt_price = data.sqrt_price;
                 }
+                UniswapV3Event::Collect(_) => {
+                    // positions are not tracked, collecting doesn't affect the pool's state
+                }
+                UniswapV3Event::Flash(data) => {
+                    self.handle_flash_fees(data.paid0, data.paid1)
+                        .map_err(|err| TransitionError::InconsistentEvent(err.to_string()))?;
+                }
+                UniswapV3Event::SetFeeProtocol(data) => {
+                    self.fee_protocol =
+                        pack_fee_protocol(data.fee_protocol0_new, data.fee_protocol1_new)
+                            .map_err(|err| TransitionError::InconsistentEvent(err.to_string()))?;
+                }
+                UniswapV3Event::CollectProtocol(data) => {
+                    self.protocol_fees_token0 = self
+                        .protocol_fees_token0
+                        .saturating_sub(U256::from(data.amount0));
+                    self.protocol_fees_token1 = self
+                        .protocol_fees_token1
+                        .saturating_sub(U256::from(data.amount1));
+                }
+                UniswapV3Event::IncreaseObservationCardinalityNext(data) => {
+                    if self.oracle.is_initialized() {
+                        self.oracle
+                            .grow(data.observation_cardinality_next_new)
+                            .map_err(|err| TransitionError::InconsistentEvent(err.to_string()))?;
+                    }
+                }
             }
             Ok(())
         } else {
@@ -416,7 +1362,12 @@ impl ProtocolSim for UniswapV3State {
                 self.sqrt_price == other_state.sqrt_price &&
                 self.fee == other_state.fee &&
                 self.tick == other_state.tick &&
-                self.ticks == other_state.ticks
+                self.ticks == other_state.ticks &&
+                self.fee_growth_global_0_x128 == other_state.fee_growth_global_0_x128 &&
+                self.fee_growth_global_1_x128 == other_state.fee_growth_global_1_x128 &&
+                self.fee_protocol == other_state.fee_protocol &&
+                self.protocol_fees_token0 == other_state.protocol_fees_token0 &&
+                self.protocol_fees_token1 == other_state.protocol_fees_token1
         } else {
             false
         }
@@ -434,17 +1385,46 @@ mod tests {
     use rstest::rstest;
     use tycho_core::hex_bytes::Bytes;
 
-    use crate::protocol::uniswap_v3::events::{BurnEvent, MintEvent, SwapEvent};
+    use crate::{
+        models::TransferBehaviour,
+        protocol::uniswap_v3::{
+            events::{
+                BurnEvent, CollectEvent, CollectProtocolEvent, FlashEvent, MintEvent,
+                SetFeeProtocolEvent, SwapEvent,
+            },
+            sqrt_price_math::Q96,
+        },
+    };
 
     use super::*;
 
     #[test]
     fn test_get_amount_out_full_range_liquidity() {
-        let token_x = ERC20Token::new(
-            "0x6b175474e89094c44da98b954eedeac495271d0f",
-            18,
-            "X",
-            U256::from(10_000),
+        let (token_x, token_y) = tokens();
+
+        let pool = UniswapV3State::new(
+            8330443394424070888454257,
+            U256::from_dec_str("188562464004052255423565206602").unwrap(),
+            FeeAmount::Medium,
+            17342,
+            vec![TickInfo::new(0, 0), TickInfo::new(46080, 0)],
+        );
+        let sell_amount = U256::from(11000) * U256::exp10(18);
+        let expected = U256::from_dec_str("61927070842678722935941").unwrap();
+
+        let res = pool
+            .get_amount_out(sell_amount, &token_x, &token_y)
+            .unwrap();
+
+        assert_eq!(res.amount, expected);
+    }
+
+    fn tokens() -> (ERC20Token, ERC20Token) {
+        let token_x = ERC20Token::new(
+            "0x6b175474e89094c44da98b954eedeac495271d0f",
+            18,
+            "X",
+            U256::from(10_000),
         );
         let token_y = ERC20Token::new(
             "0xf1ca9cb74685755965c7458528a36934df52a3ef",
@@ -452,7 +1432,706 @@ mod tests {
             "Y",
             U256::from(10_000),
         );
+        (token_x, token_y)
+    }
+
+    fn lp_pool() -> UniswapV3State {
+        UniswapV3State::new(
+            8330443394424070888454257,
+            U256::from_dec_str("188562464004052255423565206602").unwrap(),
+            FeeAmount::Medium,
+            17342,
+            vec![
+                TickInfo::new(0, 8330443394424070888454257),
+                TickInfo::new(46080, -8330443394424070888454257),
+            ],
+        )
+    }
+
+    #[test]
+    fn test_mint_in_range() {
+        let pool = lp_pool();
+        let liquidity = 10u128.pow(18);
+
+        let res = pool
+            .mint(17280, 17400, liquidity)
+            .unwrap();
+
+        assert!(res.amount0 > U256::zero());
+        assert!(res.amount1 > U256::zero());
+        assert_eq!(res.new_state.liquidity, pool.liquidity + liquidity);
+        assert_eq!(
+            res.new_state
+                .ticks
+                .get_tick(17280)
+                .unwrap()
+                .net_liquidity,
+            liquidity as i128
+        );
+        assert_eq!(
+            res.new_state
+                .ticks
+                .get_tick(17400)
+                .unwrap()
+                .net_liquidity,
+            -(liquidity as i128)
+        );
+        // the original state is left untouched
+        assert!(pool.ticks.get_tick(17280).is_err());
+    }
+
+    #[rstest]
+    #[case::below_range(17400, 17520, true, false)]
+    #[case::above_range(17160, 17280, false, true)]
+    fn test_mint_out_of_range(
+        #[case] lower: i32,
+        #[case] upper: i32,
+        #[case] expect_amount0: bool,
+        #[case] expect_amount1: bool,
+    ) {
+        let pool = lp_pool();
+
+        let res = pool
+            .mint(lower, upper, 10u128.pow(18))
+            .unwrap();
+
+        assert_eq!(res.amount0 > U256::zero(), expect_amount0);
+        assert_eq!(res.amount1 > U256::zero(), expect_amount1);
+        assert_eq!(res.new_state.liquidity, pool.liquidity);
+    }
+
+    #[test]
+    fn test_mint_then_burn() {
+        let pool = lp_pool();
+        let liquidity = 10u128.pow(18);
+
+        let minted = pool
+            .mint(17280, 17400, liquidity)
+            .unwrap();
+        let burned = minted
+            .new_state
+            .burn(17280, 17400, liquidity)
+            .unwrap();
+
+        assert_eq!(burned.new_state, pool);
+        // minting rounds up and burning rounds down
+        assert!(burned.amount0 <= minted.amount0);
+        assert!(burned.amount1 <= minted.amount1);
+        assert!(minted.amount0 - burned.amount0 <= U256::one());
+        assert!(minted.amount1 - burned.amount1 <= U256::one());
+    }
+
+    #[test]
+    fn test_mint_for_amounts() {
+        let pool = lp_pool();
+        let amount0_desired = U256::exp10(18);
+        let amount1_desired = U256::exp10(18);
+
+        let res = pool
+            .mint_for_amounts(17280, 17400, amount0_desired, amount1_desired)
+            .unwrap();
+
+        assert!(res.liquidity > 0);
+        assert!(res.amount0 <= amount0_desired);
+        assert!(res.amount1 <= amount1_desired);
+        // one of the tokens bounds the liquidity and is (almost) entirely consumed
+        assert!(
+            amount0_desired - res.amount0 <= U256::one() ||
+                amount1_desired - res.amount1 <= U256::one()
+        );
+    }
+
+    #[rstest]
+    #[case::inverted(17400, 17280)]
+    #[case::not_spaced(17281, 17400)]
+    #[case::out_of_bounds(-887280, 17400)]
+    fn test_mint_invalid_ticks(#[case] lower: i32, #[case] upper: i32) {
+        let pool = lp_pool();
+
+        let res = pool.mint(lower, upper, 10u128.pow(18));
+
+        assert!(matches!(res, Err(SimulationError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_burn_more_than_available() {
+        let pool = lp_pool();
+
+        let res = pool.burn(17280, 17400, pool.liquidity + 1);
+
+        assert!(matches!(res, Err(SimulationError::NoLiquidity())));
+    }
+
+    #[rstest]
+    #[case::sorted(vec![0, 1, 5_000, 30_000, 60_000, 100_000, 100_000])]
+    #[case::unsorted(vec![60_000, 1, 30_000])]
+    fn test_get_amounts_out(#[case] amounts: Vec<u64>) {
+        let (token_x, token_y) = tokens();
+        let pool = lp_pool()
+            .mint(17400, 17520, 10u128.pow(18))
+            .unwrap()
+            .new_state;
+        let amounts_in: Vec<U256> = amounts
+            .into_iter()
+            .map(|amount| U256::from(amount) * U256::exp10(18))
+            .collect();
+
+        let quotes: Vec<QuoteResult> = pool
+            .get_amounts_out(&amounts_in, &token_y, &token_x)
+            .unwrap()
+            .into_iter()
+            .map(|quote| quote.unwrap())
+            .collect();
 
+        let expected: Vec<QuoteResult> = amounts_in
+            .iter()
+            .map(|amount_in| {
+                pool.quote_amount_out(*amount_in, &token_y, &token_x)
+                    .unwrap()
+            })
+            .collect();
+        assert_eq!(quotes, expected);
+    }
+
+    #[test]
+    fn test_get_amount_out_with_transfer_taxes() {
+        let (token_x, token_y) = tokens();
+        let taxed_x = token_x
+            .clone()
+            .with_transfer_behaviour(TransferBehaviour { buy_tax_bps: 300, ..Default::default() });
+        let taxed_y = token_y
+            .clone()
+            .with_transfer_behaviour(TransferBehaviour { sell_tax_bps: 500, ..Default::default() });
+        let pool = lp_pool();
+        let amounts_in: Vec<U256> = [1_000u64, 5_000, 20_000]
+            .iter()
+            .map(|amount| U256::from(*amount) * U256::exp10(18))
+            .collect();
+
+        let quotes = pool
+            .get_amounts_out(&amounts_in, &taxed_y, &taxed_x)
+            .unwrap();
+
+        for (amount_in, quote) in amounts_in.iter().zip(quotes) {
+            let untaxed = pool
+                .get_amount_out(taxed_y.amount_after_sell_tax(*amount_in), &token_y, &token_x)
+                .unwrap();
+            let taxed = pool
+                .get_amount_out(*amount_in, &taxed_y, &taxed_x)
+                .unwrap();
+            assert_eq!(taxed.amount, taxed_x.amount_after_buy_tax(untaxed.amount));
+            assert!(taxed
+                .new_state
+                .eq(untaxed.new_state.as_ref()));
+            assert_eq!(quote.unwrap().amount, taxed.amount);
+        }
+    }
+
+    #[test]
+    fn test_get_limits() {
+        let pool = lp_pool();
+        let (token0, token1) = (
+            ERC20Token::new("0x0000000000000000000000000000000000000001", 18, "T0", U256::zero()),
+            ERC20Token::new("0x0000000000000000000000000000000000000002", 18, "T1", U256::zero()),
+        );
+
+        let (max_in, max_out) = pool
+            .get_limits(&token0, &token1)
+            .unwrap();
+
+        // all liquidity is in range down to tick 0, i.e. a sqrt price of Q96
+        let expected_out = U256::from(pool.liquidity) * (pool.sqrt_price - Q96) / Q96;
+        assert!(expected_out - max_out <= U256::one());
+        let quote = pool
+            .quote_amount_out(max_in, &token0, &token1)
+            .unwrap();
+        assert_eq!(quote.amount, max_out);
+        let res = pool.quote_amount_out(max_in + 1, &token0, &token1);
+        assert!(matches!(res, Err(SimulationError::InsufficientData(_))));
+    }
+
+    #[test]
+    fn test_get_amounts_out_beyond_liquidity() {
+        let pool = lp_pool();
+        let (token0, token1) = (
+            ERC20Token::new("0x0000000000000000000000000000000000000001", 18, "T0", U256::zero()),
+            ERC20Token::new("0x0000000000000000000000000000000000000002", 18, "T1", U256::zero()),
+        );
+        let (max_in, _) = pool
+            .get_limits(&token0, &token1)
+            .unwrap();
+
+        let quotes = pool
+            .get_amounts_out(&[max_in / 2, max_in + 1], &token0, &token1)
+            .unwrap();
+
+        assert_eq!(
+            quotes[0].as_ref().unwrap(),
+            &pool
+                .quote_amount_out(max_in / 2, &token0, &token1)
+                .unwrap()
+        );
+        assert!(matches!(quotes[1], Err(SimulationError::InsufficientData(_))));
+    }
+
+    #[test]
+    fn test_get_limits_with_transfer_taxes() {
+        let pool = lp_pool();
+        let (token0, token1) = (
+            ERC20Token::new("0x0000000000000000000000000000000000000001", 18, "T0", U256::zero()),
+            ERC20Token::new("0x0000000000000000000000000000000000000002", 18, "T1", U256::zero()),
+        );
+        let taxed0 = token0
+            .clone()
+            .with_transfer_behaviour(TransferBehaviour { sell_tax_bps: 500, ..Default::default() });
+        let taxed1 = token1
+            .clone()
+            .with_transfer_behaviour(TransferBehaviour { buy_tax_bps: 300, ..Default::default() });
+
+        let (pool_max_in, pool_max_out) = pool
+            .get_limits(&token0, &token1)
+            .unwrap();
+        let (max_in, max_out) = pool
+            .get_limits(&taxed0, &taxed1)
+            .unwrap();
+
+        assert_eq!(taxed0.amount_after_sell_tax(max_in), pool_max_in);
+        assert_eq!(max_out, taxed1.amount_after_buy_tax(pool_max_out));
+        let quote = pool
+            .quote_amount_out(max_in, &taxed0, &taxed1)
+            .unwrap();
+        assert_eq!(quote.amount, max_out);
+    }
+
+    #[test]
+    fn test_swap_updates_fee_growth() {
+        let pool = lp_pool();
+        let (token_x, token_y) = tokens();
+
+        let res = pool
+            .get_amount_out(U256::exp10(18), &token_x, &token_y)
+            .unwrap();
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+
+        let fee = U256::from(3) * U256::exp10(15);
+        let expected = fee * Q128 / U256::from(pool.liquidity);
+        assert_eq!(new_state.fee_growth_global_0_x128, expected);
+        assert_eq!(new_state.fee_growth_global_1_x128, U256::zero());
+    }
+
+    #[rstest]
+    // crosses the uninitialized tick 17340 and ends within the next range
+    #[case::zero_for_one(true, U256::exp10(22))]
+    // ends within the starting range
+    #[case::one_for_zero(false, U256::from(2) * U256::exp10(22))]
+    fn test_swap_ending_within_range_updates_tick(
+        #[case] zero_for_one: bool,
+        #[case] amount_in: U256,
+    ) {
+        let pool = lp_pool();
+        let (token_x, token_y) = tokens();
+        let (token_in, token_out) =
+            if zero_for_one { (&token_x, &token_y) } else { (&token_y, &token_x) };
+
+        let res = pool
+            .get_amount_out(amount_in, token_in, token_out)
+            .unwrap();
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+
+        assert_ne!(new_state.tick, pool.tick);
+        assert_eq!(
+            new_state.tick,
+            tick_math::get_tick_at_sqrt_ratio(new_state.sqrt_price).unwrap()
+        );
+    }
+
+    #[test]
+    fn test_fees_owed_in_range() {
+        let (token_x, token_y) = tokens();
+        let liquidity = 10u128.pow(18);
+        let minted = lp_pool()
+            .mint(17280, 17400, liquidity)
+            .unwrap()
+            .new_state;
+        let fee_growth_inside_last = minted
+            .fee_growth_inside(17280, 17400)
+            .unwrap();
+
+        let res = minted
+            .get_amount_out(U256::exp10(18), &token_x, &token_y)
+            .unwrap();
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        let (fees_0, fees_1) = new_state
+            .fees_owed(17280, 17400, liquidity, fee_growth_inside_last)
+            .unwrap();
+
+        // the position earns its pro-rata share of the swap fee
+        let fee = U256::from(3) * U256::exp10(15);
+        let expected = fee * U256::from(liquidity) / U256::from(minted.liquidity);
+        assert!(expected - fees_0 <= U256::one());
+        assert_eq!(fees_1, U256::zero());
+    }
+
+    #[test]
+    fn test_fees_owed_after_crossing_into_range() {
+        let (token_x, token_y) = tokens();
+        let liquidity = 10u128.pow(18);
+        let minted = lp_pool()
+            .mint(17400, 17520, liquidity)
+            .unwrap()
+            .new_state;
+        let fee_growth_inside_last = minted
+            .fee_growth_inside(17400, 17520)
+            .unwrap();
+
+        let res = minted
+            .get_amount_out(U256::from(100_000) * U256::exp10(18), &token_y, &token_x)
+            .unwrap();
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert!(new_state.tick >= 17400 && new_state.tick < 17520);
+
+        let (fees_0, fees_1) = new_state
+            .fees_owed(17400, 17520, liquidity, fee_growth_inside_last)
+            .unwrap();
+        // only the fees accrued after the price entered the range are owed
+        let fees_all_swap = U256::from(300) * U256::exp10(18) * U256::from(liquidity) /
+            U256::from(new_state.liquidity);
+        assert_eq!(fees_0, U256::zero());
+        assert!(fees_1 > U256::zero());
+        assert!(fees_1 < fees_all_swap);
+        // a range the price never reached earns nothing
+        assert_eq!(
+            new_state
+                .fee_growth_inside(17520, 17640)
+                .unwrap(),
+            (U256::zero(), U256::zero())
+        );
+    }
+
+    #[test]
+    fn test_fee_growth_inside_adjacent_positions() {
+        let (token_x, token_y) = tokens();
+        let liquidity = 10u128.pow(18);
+        // accrue fees first, so that the fee growth outside of the new ticks is non-zero
+        let pool = lp_pool()
+            .get_amount_out(U256::exp10(18), &token_x, &token_y)
+            .unwrap()
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap()
+            .clone();
+        let lower = pool
+            .mint(17280, 17340, liquidity)
+            .unwrap()
+            .new_state;
+
+        let both = lower
+            .mint(17340, 17400, liquidity)
+            .unwrap()
+            .new_state;
+
+        // the shared tick has no net liquidity but keeps its fee growth outside
+        let shared = both.ticks.get_tick(17340).unwrap();
+        assert_eq!(shared.net_liquidity, 0);
+        assert_eq!(shared.liquidity_gross, 2 * liquidity);
+        assert_ne!(shared.fee_growth_outside_0_x128, U256::zero());
+        assert_eq!(
+            both.fee_growth_inside(17280, 17340)
+                .unwrap(),
+            lower
+                .fee_growth_inside(17280, 17340)
+                .unwrap()
+        );
+    }
+
+    #[test]
+    fn test_get_amount_out_detailed() {
+        let (token_x, token_y) = tokens();
+        let liquidity = 10u128.pow(18);
+        let pool = lp_pool()
+            .mint(17400, 17520, liquidity)
+            .unwrap()
+            .new_state;
+        let amount_in = U256::from(100_000) * U256::exp10(18);
+
+        let res = pool
+            .get_amount_out_detailed(amount_in, &token_y, &token_x)
+            .unwrap();
+        let expected = pool
+            .get_amount_out(amount_in, &token_y, &token_x)
+            .unwrap();
+
+        assert_eq!(res.amount_out, expected.amount);
+        assert_eq!(res.gas, expected.gas);
+        assert_eq!(res.gas, U256::from(130_000 + 2000 * res.steps.len()));
+        assert_eq!(res.steps.len(), 2);
+
+        let first = &res.steps[0];
+        assert_eq!(first.tick_next, 17400);
+        assert!(first.crossed_initialized_tick);
+        assert_eq!(first.sqrt_price_start, pool.sqrt_price);
+        assert_eq!(first.liquidity, pool.liquidity);
+
+        let last = &res.steps[1];
+        assert!(!last.crossed_initialized_tick);
+        assert_eq!(last.sqrt_price_start, first.sqrt_price_end);
+        assert_eq!(last.liquidity, pool.liquidity + liquidity);
+
+        let total_in = res
+            .steps
+            .iter()
+            .fold(U256::zero(), |acc, step| acc + step.amount_in + step.fee_amount);
+        let total_out = res
+            .steps
+            .iter()
+            .fold(U256::zero(), |acc, step| acc + step.amount_out);
+        assert_eq!(total_in, amount_in);
+        assert_eq!(total_out, res.amount_out);
+    }
+
+    #[derive(Debug, Default)]
+    struct StubTickProvider {
+        ticks: Vec<TickInfo>,
+        requests: std::sync::Mutex<Vec<(i32, i32)>>,
+    }
+
+    impl TickProvider for StubTickProvider {
+        fn get_ticks(&self, lower: i32, upper: i32) -> Result<Vec<TickInfo>, SimulationError> {
+            self.requests
+                .lock()
+                .unwrap()
+                .push((lower, upper));
+            Ok(self
+                .ticks
+                .iter()
+                .filter(|tick| lower <= tick.index && tick.index <= upper)
+                .cloned()
+                .collect())
+        }
+    }
+
+    #[test]
+    fn test_get_amount_out_fetches_missing_ticks() {
+        let (token_x, token_y) = tokens();
+        let liquidity_outer: i128 = 8330443394424070888454257;
+        let liquidity_inner: i128 = 10i128.pow(24);
+        let all_ticks = vec![
+            TickInfo::new(15960, liquidity_outer),
+            TickInfo::new(17280, liquidity_inner),
+            TickInfo::new(17400, -liquidity_inner),
+            TickInfo::new(18000, -liquidity_outer),
+        ];
+        let sqrt_price = U256::from_dec_str("188562464004052255423565206602").unwrap();
+        let total_liquidity = (liquidity_outer + liquidity_inner) as u128;
+        let full_pool = UniswapV3State::new(
+            total_liquidity,
+            sqrt_price,
+            FeeAmount::Medium,
+            17342,
+            all_ticks.clone(),
+        );
+        let sparse_pool = UniswapV3State::new(
+            total_liquidity,
+            sqrt_price,
+            FeeAmount::Medium,
+            17342,
+            all_ticks[1..3].to_vec(),
+        );
+        let provider = Arc::new(StubTickProvider { ticks: all_ticks, ..Default::default() });
+        let sell_amount = U256::from(60_000) * U256::exp10(18);
+
+        let err = sparse_pool
+            .get_amount_out(sell_amount, &token_x, &token_y)
+            .unwrap_err();
+        assert!(matches!(err, SimulationError::InsufficientData(_)));
+
+        let res = sparse_pool
+            .with_tick_provider(provider.clone())
+            .get_amount_out(sell_amount, &token_x, &token_y)
+            .unwrap();
+        let expected = full_pool
+            .get_amount_out(sell_amount, &token_x, &token_y)
+            .unwrap();
+
+        assert_eq!(res.amount, expected.amount);
+        assert_eq!(res.gas, expected.gas);
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        let expected_state = expected
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert_eq!(new_state.sqrt_price, expected_state.sqrt_price);
+        assert_eq!(new_state.tick, expected_state.tick);
+        assert_eq!(new_state.liquidity, expected_state.liquidity);
+        assert!(new_state.ticks.get_tick(15960).is_ok());
+        assert_eq!(provider.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_quote_amount_out_reuses_fetched_ticks() {
+        let (token_x, token_y) = tokens();
+        let liquidity_outer: i128 = 8330443394424070888454257;
+        let provider = Arc::new(StubTickProvider {
+            ticks: vec![TickInfo::new(15960, liquidity_outer)],
+            ..Default::default()
+        });
+        let pool = UniswapV3State::new(
+            liquidity_outer as u128,
+            U256::from_dec_str("188562464004052255423565206602").unwrap(),
+            FeeAmount::Medium,
+            17342,
+            vec![TickInfo::new(17280, 0), TickInfo::new(18000, -liquidity_outer)],
+        )
+        .with_tick_provider(provider.clone());
+        let sell_amount = U256::from(60_000) * U256::exp10(18);
+
+        let first = pool
+            .quote_amount_out(sell_amount, &token_x, &token_y)
+            .unwrap();
+        let second = pool
+            .quote_amount_out(sell_amount, &token_x, &token_y)
+            .unwrap();
+
+        assert_eq!(first, second);
+        assert_eq!(provider.requests.lock().unwrap().len(), 1);
+    }
+
+    #[test]
+    fn test_eq_compares_tick_providers() {
+        let provider: Arc<dyn TickProvider> = Arc::new(StubTickProvider::default());
+        let pool = lp_pool().with_tick_provider(provider.clone());
+
+        assert_eq!(pool, pool.clone());
+        assert_eq!(pool, lp_pool().with_tick_provider(provider));
+        assert_ne!(pool, lp_pool().with_tick_provider(Arc::new(StubTickProvider::default())));
+        assert_ne!(pool, lp_pool());
+    }
+
+    #[test]
+    fn test_get_amount_out_tick_provider_exhausted() {
+        let (token_x, token_y) = tokens();
+        let pool = UniswapV3State::new(
+            10u128.pow(24),
+            U256::from_dec_str("188562464004052255423565206602").unwrap(),
+            FeeAmount::Medium,
+            17342,
+            vec![TickInfo::new(17280, 10i128.pow(24)), TickInfo::new(17400, -10i128.pow(24))],
+        )
+        .with_tick_provider(Arc::new(StubTickProvider::default()));
+
+        let err = pool
+            .get_amount_out(U256::from(60_000) * U256::exp10(18), &token_x, &token_y)
+            .unwrap_err();
+
+        assert!(matches!(err, SimulationError::InsufficientData(_)));
+    }
+
+    #[test]
+    fn test_twap_after_swaps() {
+        let (token_x, token_y) = tokens();
+        let mut pool = lp_pool().with_oracle(Oracle::initialize(1000));
+        pool.increase_observation_cardinality_next(10)
+            .unwrap();
+        let sell_amount = U256::from(10_000) * U256::exp10(18);
+
+        let first = pool
+            .get_amount_out_at(sell_amount, &token_x, &token_y, 1012)
+            .unwrap()
+            .new_state;
+        let first = first
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        let second = first
+            .get_amount_out_at(sell_amount, &token_x, &token_y, 1024)
+            .unwrap()
+            .new_state;
+        let second = second
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+
+        assert_eq!(second.oracle().cardinality(), 10);
+        assert_eq!(second.oracle().index(), 2);
+        let sum_ticks = (pool.tick + first.tick + second.tick) as i64;
+        let res = second.observe(1036, &[36, 0]).unwrap();
+        assert_eq!(res[1].0 - res[0].0, sum_ticks * 12);
+        assert_eq!(second.twap_tick(1036, 36).unwrap(), (sum_ticks / 3) as i32);
+        // the oracle only covers the period since it was initialized
+        assert!(matches!(second.twap_tick(1036, 37), Err(SimulationError::InvalidInput(_))));
+    }
+
+    #[rstest]
+    #[case::in_range(17280, 17400, 1)]
+    #[case::out_of_range(17400, 17520, 0)]
+    fn test_mint_and_burn_at_write_observations(
+        #[case] lower: i32,
+        #[case] upper: i32,
+        #[case] expected_index: u16,
+    ) {
+        let mut pool = lp_pool().with_oracle(Oracle::initialize(1000));
+        pool.increase_observation_cardinality_next(10)
+            .unwrap();
+        let liquidity = 10u128.pow(18);
+
+        let minted = pool
+            .mint_at(lower, upper, liquidity, 1012)
+            .unwrap()
+            .new_state;
+        let burned = minted
+            .burn_at(lower, upper, liquidity, 1024)
+            .unwrap()
+            .new_state;
+
+        assert_eq!(minted.oracle().index(), expected_index);
+        assert_eq!(burned.oracle().index(), 2 * expected_index);
+        if expected_index > 0 {
+            let observation = burned
+                .oracle()
+                .last_observation()
+                .unwrap();
+            assert_eq!(observation.tick_cumulative, pool.tick as i64 * 24);
+            // the observation is written with the liquidity before the burn
+            let expected = (U256::from(12) << 128) / U256::from(pool.liquidity) +
+                (U256::from(12) << 128) / U256::from(minted.liquidity);
+            assert_eq!(observation.seconds_per_liquidity_cumulative_x128, expected);
+        }
+    }
+
+    #[test]
+    fn test_observe_without_oracle() {
+        let pool = lp_pool();
+
+        let res = pool.observe(1000, &[0]);
+
+        assert!(matches!(res, Err(SimulationError::NotInitialized(_))));
+    }
+
+    #[test]
+    fn test_quote_amount_out_shares_ticks() {
+        let (token_x, token_y) = tokens();
         let pool = UniswapV3State::new(
             8330443394424070888454257,
             U256::from_dec_str("188562464004052255423565206602").unwrap(),
@@ -461,13 +2140,22 @@ mod tests {
             vec![TickInfo::new(0, 0), TickInfo::new(46080, 0)],
         );
         let sell_amount = U256::from(11000) * U256::exp10(18);
-        let expected = U256::from_dec_str("61927070842678722935941").unwrap();
 
+        let quote = pool
+            .quote_amount_out(sell_amount, &token_x, &token_y)
+            .unwrap();
         let res = pool
             .get_amount_out(sell_amount, &token_x, &token_y)
             .unwrap();
 
-        assert_eq!(res.amount, expected);
+        assert_eq!(quote.amount, res.amount);
+        assert_eq!(quote.gas, res.gas);
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+        assert!(Arc::ptr_eq(&new_state.ticks, &pool.ticks));
     }
 
     struct SwapTestCase {
@@ -734,6 +2422,151 @@ mod tests {
         assert_eq!(pool.liquidity, exp_pool_liq)
     }
 
+    #[test]
+    fn test_swap_with_protocol_fee() {
+        let (token_x, token_y) = tokens();
+        let pool = lp_pool()
+            .with_fee_protocol(4, 0)
+            .unwrap();
+
+        let res = pool
+            .get_amount_out(U256::exp10(18), &token_x, &token_y)
+            .unwrap();
+        let new_state = res
+            .new_state
+            .as_any()
+            .downcast_ref::<UniswapV3State>()
+            .unwrap();
+
+        // the protocol takes a quarter of the 0.3% fee, the amount out is unaffected
+        let fee = U256::from(3) * U256::exp10(15);
+        let protocol_fee = fee / 4;
+        assert_eq!(new_state.protocol_fees(), (protocol_fee, U256::zero()));
+        assert_eq!(
+            new_state.fee_growth_global_0_x128,
+            (fee - protocol_fee) * Q128 / U256::from(pool.liquidity)
+        );
+        assert_eq!(
+            res.amount,
+            lp_pool()
+                .get_amount_out(U256::exp10(18), &token_x, &token_y)
+                .unwrap()
+                .amount
+        );
+    }
+
+    #[test]
+    fn test_with_fee_protocol_invalid() {
+        assert!(matches!(lp_pool().with_fee_protocol(3, 0), Err(SimulationError::InvalidInput(_))));
+    }
+
+    #[test]
+    fn test_event_transition_fee_protocol_and_flash() {
+        let mut pool = lp_pool();
+        let liquidity = U256::from(pool.liquidity);
+
+        pool.event_transition(
+            Box::new(UniswapV3Event::from(SetFeeProtocolEvent::new(5, 10))),
+            &logmeta(),
+        )
+        .unwrap();
+        pool.event_transition(
+            Box::new(UniswapV3Event::from(FlashEvent::new(
+                U256::exp10(20),
+                U256::exp10(20),
+                U256::from(1000),
+                U256::from(3000),
+            ))),
+            &logmeta(),
+        )
+        .unwrap();
+
+        assert_eq!(pool.fee_protocol, 5 + (10 << 4));
+        assert_eq!(pool.protocol_fees(), (U256::from(200), U256::from(300)));
+        assert_eq!(pool.fee_growth_global_0_x128, U256::from(800) * Q128 / liquidity);
+        assert_eq!(pool.fee_growth_global_1_x128, U256::from(2700) * Q128 / liquidity);
+
+        pool.event_transition(
+            Box::new(UniswapV3Event::from(CollectProtocolEvent::new(150, 300))),
+            &logmeta(),
+        )
+        .unwrap();
+        pool.event_transition(
+            Box::new(UniswapV3Event::from(CollectEvent::new(17280, 17400, 10, 10))),
+            &logmeta(),
+        )
+        .unwrap();
+
+        assert_eq!(pool.protocol_fees(), (U256::from(50), U256::zero()));
+    }
+
+    #[rstest]
+    #[case::below_minimum(3, 0)]
+    #[case::above_maximum(0, 11)]
+    #[case::overflowing_packing(4, 20)]
+    fn test_event_transition_fee_protocol_invalid(
+        #[case] fee_protocol0: u8,
+        #[case] fee_protocol1: u8,
+    ) {
+        let mut pool = lp_pool();
+
+        let res = pool.event_transition(
+            Box::new(UniswapV3Event::from(SetFeeProtocolEvent::new(fee_protocol0, fee_protocol1))),
+            &logmeta(),
+        );
+
+        assert!(matches!(res, Err(TransitionError::InconsistentEvent(_))));
+        assert_eq!(pool.fee_protocol, 0);
+    }
+
+    #[test]
+    fn test_event_transition_flash_without_liquidity() {
+        let mut pool = UniswapV3State::new(
+            0,
+            U256::from_dec_str("188562464004052255423565206602").unwrap(),
+            FeeAmount::Medium,
+            17342,
+            vec![],
+        );
+
+        let res = pool.event_transition(
+            Box::new(UniswapV3Event::from(FlashEvent::new(
+                U256::exp10(20),
+                U256::zero(),
+                U256::from(1000),
+                U256::zero(),
+            ))),
+            &logmeta(),
+        );
+
+        assert!(matches!(res, Err(TransitionError::InconsistentEvent(_))));
+    }
+
+    #[test]
+    fn test_eq_compares_fees() {
+        let pool = lp_pool();
+        let mut flashed = lp_pool();
+        flashed
+            .event_transition(
+                Box::new(UniswapV3Event::from(FlashEvent::new(
+                    U256::exp10(20),
+                    U256::exp10(20),
+                    U256::from(1000),
+                    U256::from(3000),
+                ))),
+                &logmeta(),
+            )
+            .unwrap();
+
+        let with_fee_protocol = lp_pool()
+            .with_fee_protocol(4, 0)
+            .unwrap();
+
+        assert!(ProtocolSim::eq(&pool, &lp_pool()));
+        assert!(!ProtocolSim::eq(&pool, &with_fee_protocol));
+        assert!(!ProtocolSim::eq(&pool, &flashed));
+    }
+
     #[test]
     fn test_event_transition_swap() {
         let mut pool = UniswapV3State::new(
@@ -1,22 +1,43 @@
 use std::cmp;
 
 use ethers::types::U256;
+use serde::{Deserialize, Serialize};
+
+use crate::serde_helpers::decimal_string;
 
 use super::tick_math;
 
-#[derive(Copy, Clone, Debug, PartialEq, Eq)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TickInfo {
     pub index: i32,
+    #[serde(with = "decimal_string")]
     pub net_liquidity: i128,
+    /// Total liquidity of the positions using this tick as a boundary. A tick stays initialized
+    /// while this is non-zero, even if the net liquidity is 0.
+    #[serde(with = "decimal_string", default)]
+    pub liquidity_gross: u128,
     pub sqrt_price: U256,
+    /// Fee growth per unit of liquidity on the other side of this tick (relative to the current
+    /// tick), for token0 and token1 respectively.
+    pub fee_growth_outside_0_x128: U256,
+    pub fee_growth_outside_1_x128: U256,
 }
 
 impl TickInfo {
+    /// Creates an initialized tick. The gross liquidity is unknown and assumed to be the absolute
+    /// net liquidity, i.e. all positions referencing the tick are on the same side of it.
     pub fn new(index: i32, net_liquidity: i128) -> Self {
         // Note: using this method here returns slightly different values
         //  compared to the Python implementation, likely more correct
         let sqrt_price = tick_math::get_sqrt_ratio_at_tick(index).unwrap();
-        TickInfo { index, net_liquidity, sqrt_price }
+        TickInfo {
+            index,
+            net_liquidity,
+            liquidity_gross: net_liquidity.unsigned_abs(),
+            sqrt_price,
+            fee_growth_outside_0_x128: U256::zero(),
+            fee_growth_outside_1_x128: U256::zero(),
+        }
     }
 }
 
@@ -39,7 +60,7 @@ pub enum TickListErrorKind {
     TicksExeeded,
 }
 
-#[derive(Clone, Debug, PartialEq, Eq)]
+#[derive(Clone, Debug, PartialEq, Eq, Serialize, Deserialize)]
 pub struct TickList {
     tick_spacing: u16,
     ticks: Vec<TickInfo>,
@@ -87,30 +108,79 @@ impl TickList {
         Ok(true)
     }
 
+    /// Returns the smallest and largest known tick index, if there are any ticks.
+    pub fn bounds(&self) -> Option<(i32, i32)> {
+        Some((self.ticks.first()?.index, self.ticks.last()?.index))
+    }
+
+    /// Inserts ticks that are not part of the list yet, keeping it ordered.
+    ///
+    /// Returns the indexes of the inserted ticks. Ticks already present in the list are skipped.
+    /// If any tick is invalid, none of them are inserted.
+    pub fn insert_ticks(&mut self, ticks: Vec<TickInfo>) -> Result<Vec<i32>, String> {
+        if let Some(tick) = ticks
+            .iter()
+            .find(|tick| tick.index % self.tick_spacing as i32 != 0)
+        {
+            return Err(format!(
+                "Tick index {} not aligned with tick spacing {}",
+                tick.index, self.tick_spacing,
+            ));
+        }
+        let mut inserted = Vec::new();
+        for tick in ticks {
+            if let Err(insert_idx) = self
+                .ticks
+                .binary_search_by(|t| t.index.cmp(&tick.index))
+            {
+                self.ticks.insert(insert_idx, tick);
+                inserted.push(tick.index);
+            }
+        }
+        Ok(inserted)
+    }
+
+    /// Applies a change of `delta` to the liquidity of a position on the range `[lower, upper)`.
+    ///
+    /// As `Tick.update` and `Tick.clear` do on-chain, a tick is only removed once no position uses
+    /// it as a boundary anymore, so ticks between adjacent positions keep their fee growth.
     pub fn apply_liquidity_change(&mut self, lower: i32, upper: i32, delta: i128) {
-        self.upsert_tick(lower, delta);
-        self.upsert_tick(upper, -delta);
+        self.upsert_tick(lower, delta, delta);
+        self.upsert_tick(upper, -delta, delta);
     }
 
-    fn upsert_tick(&mut self, tick: i32, delta: i128) {
+    fn upsert_tick(&mut self, tick: i32, net_delta: i128, gross_delta: i128) {
         match self
             .ticks
             .binary_search_by(|t| t.index.cmp(&tick))
         {
             Ok(existing_idx) => {
                 let tick = &mut self.ticks[existing_idx];
-                tick.net_liquidity += delta;
-                if tick.net_liquidity == 0 {
+                tick.net_liquidity += net_delta;
+                // the gross liquidity can't be below the net liquidity, even if it was unknown
+                tick.liquidity_gross = tick
+                    .liquidity_gross
+                    .saturating_add_signed(gross_delta)
+                    .max(tick.net_liquidity.unsigned_abs());
+                if tick.liquidity_gross == 0 {
                     self.ticks.remove(existing_idx);
                 }
             }
             Err(insert_idx) => {
-                self.ticks
-                    .insert(insert_idx, TickInfo::new(tick, delta));
+                let mut new_tick = TickInfo::new(tick, net_delta);
+                new_tick.liquidity_gross = new_tick
+                    .liquidity_gross
+                    .max(gross_delta.unsigned_abs());
+                self.ticks.insert(insert_idx, new_tick);
             }
         }
     }
 
+    /// Sets the net liquidity of a tick, e.g. as reported by an indexer.
+    ///
+    /// The gross liquidity changes by the same absolute amount, keeping liquidity added on the
+    /// other side of the tick by simulated positions. The tick is removed once its gross liquidity
+    /// is 0.
     pub fn set_tick_liquidity(&mut self, tick: i32, liquidity: i128) {
         match self
             .ticks
@@ -118,8 +188,12 @@ impl TickList {
         {
             Ok(existing_idx) => {
                 let tick = &mut self.ticks[existing_idx];
+                tick.liquidity_gross = tick
+                    .liquidity_gross
+                    .saturating_sub(tick.net_liquidity.unsigned_abs())
+                    .saturating_add(liquidity.unsigned_abs());
                 tick.net_liquidity = liquidity;
-                if tick.net_liquidity == 0 {
+                if tick.liquidity_gross == 0 {
                     self.ticks.remove(existing_idx);
                 }
             }
@@ -160,6 +234,16 @@ impl TickList {
         }
     }
 
+    pub fn get_tick_mut(&mut self, index: i32) -> Result<&mut TickInfo, TickListError> {
+        match self
+            .ticks
+            .binary_search_by(|el| el.index.cmp(&index))
+        {
+            Ok(idx) => Ok(&mut self.ticks[idx]),
+            Err(_) => Err(TickListError { kind: TickListErrorKind::NotFound }),
+        }
+    }
+
     pub fn next_initialized_tick(&self, index: i32, lte: bool) -> Result<&TickInfo, TickListError> {
         if lte {
             if self.is_below_smallest(index) {
@@ -269,7 +353,14 @@ mod tests {
     }
 
     fn create_tick_info(idx: i32, liq: i128) -> TickInfo {
-        TickInfo { index: idx, net_liquidity: liq, sqrt_price: U256::zero() }
+        TickInfo {
+            index: idx,
+            net_liquidity: liq,
+            liquidity_gross: liq.unsigned_abs(),
+            sqrt_price: U256::zero(),
+            fee_growth_outside_0_x128: U256::zero(),
+            fee_growth_outside_1_x128: U256::zero(),
+        }
     }
 
     #[test]
@@ -645,4 +736,86 @@ mod tests {
         assert!(tick_list.get_tick(-10).is_err());
         assert!(tick_list.get_tick(10).is_err());
     }
+
+    #[test]
+    fn test_apply_liquidity_change_adjacent_positions() {
+        let mut tick_list = TickList::from(10, vec![]);
+
+        tick_list.apply_liquidity_change(-10, 0, 100);
+        tick_list.apply_liquidity_change(0, 10, 100);
+
+        // the shared boundary has no net liquidity but is still used by both positions
+        let shared = tick_list.get_tick(0).unwrap();
+        assert_eq!(shared.net_liquidity, 0);
+        assert_eq!(shared.liquidity_gross, 200);
+
+        tick_list.apply_liquidity_change(-10, 0, -100);
+        let shared = tick_list.get_tick(0).unwrap();
+        assert_eq!(shared.net_liquidity, 100);
+        assert_eq!(shared.liquidity_gross, 100);
+        assert!(tick_list.get_tick(-10).is_err());
+
+        tick_list.apply_liquidity_change(0, 10, -100);
+        assert_eq!(tick_list.bounds(), None);
+    }
+
+    #[rstest]
+    #[case::removed(10, 0, None)]
+    #[case::reduced(10, 4, Some(4))]
+    #[case::flipped(10, -3, Some(3))]
+    fn test_set_tick_liquidity(
+        #[case] initial: i128,
+        #[case] liquidity: i128,
+        #[case] expected_gross: Option<u128>,
+    ) {
+        let mut tick_list = TickList::from(10, vec![create_tick_info(0, initial)]);
+
+        tick_list.set_tick_liquidity(0, liquidity);
+
+        assert_eq!(
+            tick_list
+                .get_tick(0)
+                .ok()
+                .map(|tick| tick.liquidity_gross),
+            expected_gross
+        );
+    }
+
+    #[test]
+    fn test_insert_ticks() {
+        let tick_infos = vec![create_tick_info(0, 10), create_tick_info(100, -10)];
+        let mut tick_list = TickList::from(10, tick_infos);
+
+        let inserted = tick_list
+            .insert_ticks(vec![
+                create_tick_info(-100, 5),
+                create_tick_info(0, 20),
+                create_tick_info(200, -5),
+            ])
+            .unwrap();
+
+        assert_eq!(inserted, vec![-100, 200]);
+        assert_eq!(tick_list.bounds(), Some((-100, 200)));
+        // existing ticks are left untouched
+        assert_eq!(
+            tick_list
+                .get_tick(0)
+                .unwrap()
+                .net_liquidity,
+            10
+        );
+        assert!(tick_list
+            .insert_ticks(vec![create_tick_info(-105, 5)])
+            .is_err());
+    }
+
+    #[test]
+    fn test_insert_ticks_with_invalid_tick() {
+        let mut tick_list = TickList::from(10, vec![create_tick_info(0, 10)]);
+
+        let res = tick_list.insert_ticks(vec![create_tick_info(-100, 5), create_tick_info(55, -5)]);
+
+        assert!(res.is_err());
+        assert_eq!(tick_list.bounds(), Some((0, 0)));
+    }
 }
@@ -0,0 +1,95 @@
+//! Tick data providers
+//!
+//! Snapshots of Uniswap V3 pools may only contain the ticks around the current price. A
+//! `TickProvider` lets a `UniswapV3State` fetch additional ticks when a swap walks past the ticks
+//! it knows about, e.g. from a local cache or an RPC client.
+use std::{
+    fmt::Debug,
+    sync::{Arc, RwLock},
+};
+
+use crate::protocol::errors::SimulationError;
+
+use super::tick_list::TickInfo;
+
+pub trait TickProvider: Debug + Send + Sync {
+    /// Returns the initialized ticks with an index within `[lower, upper]`.
+    ///
+    /// Returning an empty vector signals that there are no initialized ticks in the range.
+    fn get_ticks(&self, lower: i32, upper: i32) -> Result<Vec<TickInfo>, SimulationError>;
+}
+
+/// A shared handle to a `TickProvider`, caching the ticks fetched through it.
+///
+/// Clones share the cache, so all states derived from the state the provider was set on fetch
+/// each range only once. The cached ticks are assumed to stay valid while the handle is used;
+/// setting the provider again starts with an empty cache.
+#[derive(Clone, Debug)]
+pub struct TickProviderHandle {
+    provider: Arc<dyn TickProvider>,
+    fetched: Arc<RwLock<FetchedTicks>>,
+}
+
+/// Ticks fetched through a `TickProviderHandle`
+#[derive(Debug, Default)]
+struct FetchedTicks {
+    /// The lowest tick index searched by swaps walking down
+    searched_lower: Option<i32>,
+    /// The highest tick index searched by swaps walking up
+    searched_upper: Option<i32>,
+    ticks: Vec<TickInfo>,
+}
+
+impl TickProviderHandle {
+    pub fn new(provider: Arc<dyn TickProvider>) -> Self {
+        TickProviderHandle { provider, fetched: Arc::default() }
+    }
+
+    /// Fetches the initialized ticks within `[lower, upper]` from the provider and caches them.
+    ///
+    /// `downwards` tells whether the range extends the searched ticks downwards or upwards.
+    pub fn get_ticks(
+        &self,
+        lower: i32,
+        upper: i32,
+        downwards: bool,
+    ) -> Result<Vec<TickInfo>, SimulationError> {
+        let ticks = self.provider.get_ticks(lower, upper)?;
+        let mut fetched = self.fetched.write().unwrap();
+        if downwards {
+            fetched.searched_lower = Some(
+                fetched
+                    .searched_lower
+                    .map_or(lower, |l| l.min(lower)),
+            );
+        } else {
+            fetched.searched_upper = Some(
+                fetched
+                    .searched_upper
+                    .map_or(upper, |u| u.max(upper)),
+            );
+        }
+        fetched
+            .ticks
+            .extend(ticks.iter().cloned());
+        Ok(ticks)
+    }
+
+    /// Returns the ticks fetched so far and the furthest tick index searched in the given
+    /// direction.
+    pub fn fetched_ticks(&self, downwards: bool) -> (Vec<TickInfo>, Option<i32>) {
+        let fetched = self.fetched.read().unwrap();
+        let searched_until =
+            if downwards { fetched.searched_lower } else { fetched.searched_upper };
+        (fetched.ticks.clone(), searched_until)
+    }
+}
+
+/// Handles are equal if they share the same provider.
+impl PartialEq for TickProviderHandle {
+    fn eq(&self, other: &Self) -> bool {
+        Arc::ptr_eq(&self.provider, &other.provider)
+    }
+}
+
+impl Eq for TickProviderHandle {}
@@ -33,6 +33,7 @@ pub struct Trade {
 ///
 /// # Methods
 /// - `price`: Calculates price information for a token pair within the adapter.
+/// - `price_fractions`: Like `price`, but returns the prices as exact fractions.
 /// - `swap`: Simulates a token swap operation, returning details about the trade and state updates.
 /// - `get_limits`: Retrieves the trade limits for a given token pair.
 /// - `get_capabilities`: Checks the capabilities of the adapter for a specific token pair.
@@ -50,6 +51,24 @@ where
         block: u64,
         overwrites: Option<HashMap<rAddress, Overwrites>>,
     ) -> Result<Vec<f64>, SimulationError> {
+        Ok(self
+            .price_fractions(pair_id, sell_token, buy_token, amounts, block, overwrites)?
+            .into_iter()
+            .map(|(numerator, denominator)| fraction_to_f64(numerator, denominator))
+            .collect())
+    }
+
+    /// Returns the prices the adapter reports for the amounts as `(numerator, denominator)`, all
+    /// from a single `price` call.
+    pub fn price_fractions(
+        &self,
+        pair_id: String,
+        sell_token: Address,
+        buy_token: Address,
+        amounts: Vec<U256>,
+        block: u64,
+        overwrites: Option<HashMap<rAddress, Overwrites>>,
+    ) -> Result<Vec<(U256, U256)>, SimulationError> {
         let args = vec![
             self.hexstring_to_bytes(&pair_id)?,
             Token::Address(sell_token),
@@ -65,8 +84,7 @@ where
         let res = self
             .call("price", args, block, None, overwrites, None, U256::zero())?
             .return_value;
-        let price = self.calculate_price(res[0].clone())?;
-        Ok(price)
+        decode_fractions(res[0].clone())
     }
 
     #[allow(clippy::too_many_arguments)]
@@ -196,32 +214,43 @@ where
     }
 
     fn calculate_price(&self, value: Token) -> Result<Vec<f64>, SimulationError> {
-        if let Token::Array(fractions) = value {
-            // Map over each `Token::Tuple` in the array
-            fractions
-                .into_iter()
-                .map(|fraction_token| {
-                    if let Token::Tuple(ref components) = fraction_token {
-                        let numerator = components[0]
-                            .clone()
-                            .into_uint()
-                            .unwrap();
-                        let denominator = components[1]
-                            .clone()
-                            .into_uint()
-                            .unwrap();
-                        if denominator.is_zero() {
-                            Err(SimulationError::DecodingError("Denominator is zero".to_string()))
-                        } else {
-                            Ok((numerator.as_u128() as f64) / (denominator.as_u128() as f64))
-                        }
+        Ok(decode_fractions(value)?
+            .into_iter()
+            .map(|(numerator, denominator)| fraction_to_f64(numerator, denominator))
+            .collect())
+    }
+}
+
+fn decode_fractions(value: Token) -> Result<Vec<(U256, U256)>, SimulationError> {
+    if let Token::Array(fractions) = value {
+        // Map over each `Token::Tuple` in the array
+        fractions
+            .into_iter()
+            .map(|fraction_token| {
+                if let Token::Tuple(ref components) = fraction_token {
+                    let numerator = components[0]
+                        .clone()
+                        .into_uint()
+                        .unwrap();
+                    let denominator = components[1]
+                        .clone()
+                        .into_uint()
+                        .unwrap();
+                    if denominator.is_zero() {
+                        Err(SimulationError::DecodingError("Denominator is zero".to_string()))
                     } else {
-                        Err(SimulationError::DecodingError("Invalid fraction tuple".to_string()))
+                        Ok((numerator, denominator))
                     }
-                })
-                .collect()
-        } else {
-            Err(SimulationError::DecodingError("Price is not a Token::Array".to_string()))
-        }
+                } else {
+                    Err(SimulationError::DecodingError("Invalid fraction tuple".to_string()))
+                }
+            })
+            .collect()
+    } else {
+        Err(SimulationError::DecodingError("Price is not a Token::Array".to_string()))
     }
 }
+
+fn fraction_to_f64(numerator: U256, denominator: U256) -> f64 {
+    (numerator.as_u128() as f64) / (denominator.as_u128() as f64)
+}
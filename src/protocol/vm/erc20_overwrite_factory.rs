@@ -3,21 +3,48 @@
 use std::{collections::HashMap, path::Path};
 
 use ethers::{abi::Address, types::U256};
-use revm::primitives::Address as rAddress;
-
-use crate::protocol::vm::{
-    errors::FileError,
-    utils::{get_contract_bytecode, get_storage_slot_index_at_key, SlotId},
+use revm::primitives::{Address as rAddress, U256 as rU256};
+
+use crate::{
+    evm::simulation_db::{AccountOverride, StorageOverride},
+    protocol::vm::{
+        errors::FileError,
+        utils::{get_contract_bytecode, ContractCompiler, SlotId},
+    },
 };
 
-pub struct GethOverwrite {
-    /// the formatted overwrites
-    pub state_diff: HashMap<String, String>,
-    /// the bytecode as a string
-    pub code: String,
+pub type Overwrites = HashMap<SlotId, U256>;
+
+/// The order of the keys of a nested allowances mapping
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq)]
+pub enum AllowanceKeyOrder {
+    /// `allowances[owner][spender]`, as in most tokens
+    #[default]
+    OwnerSpender,
+    /// `allowances[spender][owner]`
+    SpenderOwner,
 }
 
-pub type Overwrites = HashMap<SlotId, U256>;
+/// Where a value is stored within its slot, for tokens packing it with other data
+#[derive(Clone, Copy, Debug, PartialEq, Eq)]
+pub struct PackedValue {
+    /// Position of the value's lowest bit within the slot
+    pub offset: usize,
+    /// Number of bits of the value
+    pub bits: usize,
+    /// The rest of the slot, e.g. flags stored next to a balance. Bits overlapping the value
+    /// are ignored.
+    pub other_bits: U256,
+}
+
+impl PackedValue {
+    /// Returns the slot content storing `value`, capped at the largest value that fits.
+    fn pack(&self, value: U256) -> U256 {
+        let max_value = if self.bits >= 256 { U256::MAX } else { (U256::one() << self.bits) - 1 };
+        let mask = max_value << self.offset;
+        (self.other_bits & !mask) | (value.min(max_value) << self.offset)
+    }
+}
 
 pub struct ERC20OverwriteFactory {
     token_address: rAddress,
@@ -25,6 +52,9 @@ pub struct ERC20OverwriteFactory {
     balance_slot: SlotId,
     allowance_slot: SlotId,
     total_supply_slot: SlotId,
+    compiler: ContractCompiler,
+    allowance_key_order: AllowanceKeyOrder,
+    packed_balance: Option<PackedValue>,
 }
 
 impl ERC20OverwriteFactory {
@@ -35,18 +65,60 @@ impl ERC20OverwriteFactory {
             balance_slot: token_slots.0,
             allowance_slot: token_slots.1,
             total_supply_slot: SlotId::from(2),
+            compiler: ContractCompiler::Solidity,
+            allowance_key_order: AllowanceKeyOrder::OwnerSpender,
+            packed_balance: None,
         }
     }
 
+    /// Sets the compiler of the token contract, which determines how mapping slots are derived.
+    pub fn with_compiler(mut self, compiler: ContractCompiler) -> Self {
+        self.compiler = compiler;
+        self
+    }
+
+    /// Sets the order of the keys of the allowances mapping.
+    pub fn with_allowance_key_order(mut self, order: AllowanceKeyOrder) -> Self {
+        self.allowance_key_order = order;
+        self
+    }
+
+    /// Sets the slot of the total supply. Defaults to slot 2.
+    pub fn with_total_supply_slot(mut self, slot: SlotId) -> Self {
+        self.total_supply_slot = slot;
+        self
+    }
+
+    /// Sets where balances are stored within their slots, for tokens packing them with other
+    /// data. Balances too large for the packed value are capped.
+    pub fn with_packed_balance(mut self, packing: PackedValue) -> Self {
+        self.packed_balance = Some(packing);
+        self
+    }
+
     pub fn set_balance(&mut self, balance: U256, owner: Address) {
-        let storage_index = get_storage_slot_index_at_key(owner, self.balance_slot);
+        let storage_index = self
+            .compiler
+            .compute_map_slot(owner, self.balance_slot);
+        let value = match &self.packed_balance {
+            Some(packing) => packing.pack(balance),
+            None => balance,
+        };
         self.overwrites
-            .insert(storage_index, balance);
+            .insert(storage_index, value);
     }
 
     pub fn set_allowance(&mut self, allowance: U256, spender: Address, owner: Address) {
-        let owner_slot = get_storage_slot_index_at_key(owner, self.allowance_slot);
-        let storage_index = get_storage_slot_index_at_key(spender, owner_slot);
+        let (outer_key, inner_key) = match self.allowance_key_order {
+            AllowanceKeyOrder::OwnerSpender => (owner, spender),
+            AllowanceKeyOrder::SpenderOwner => (spender, owner),
+        };
+        let outer_slot = self
+            .compiler
+            .compute_map_slot(outer_key, self.allowance_slot);
+        let storage_index = self
+            .compiler
+            .compute_map_slot(inner_key, outer_slot);
         self.overwrites
             .insert(storage_index, allowance);
     }
@@ -56,26 +128,27 @@ impl ERC20OverwriteFactory {
             .insert(self.total_supply_slot, supply);
     }
 
+    /// Overwrites a storage slot of the token directly, for state that isn't stored in the
+    /// balances or allowances mappings.
+    pub fn set_slot(&mut self, slot: SlotId, value: U256) {
+        self.overwrites.insert(slot, value);
+    }
+
     pub fn get_overwrites(&self) -> HashMap<rAddress, Overwrites> {
         let mut result = HashMap::new();
         result.insert(self.token_address, self.overwrites.clone());
         result
     }
 
-    pub fn get_geth_overwrites(&self) -> Result<HashMap<rAddress, GethOverwrite>, FileError> {
-        let mut formatted_overwrites = HashMap::new();
-
-        for (key, val) in &self.overwrites {
-            let mut key_bytes = [0u8; 32];
-            key.to_big_endian(&mut key_bytes);
-            let hex_key = hex::encode(key_bytes);
-
-            let mut value_bytes = [0u8; 32];
-            val.to_big_endian(&mut value_bytes);
-            let hex_val = format!("0x{:0>64}", hex::encode(value_bytes));
-
-            formatted_overwrites.insert(hex_key, hex_val);
-        }
+    /// Returns the overwrites as an account override that also replaces the token's code with
+    /// the mocked ERC20 contract. Serialized, it is the token's entry of an `eth_call`'s
+    /// `stateOverride`.
+    pub fn get_geth_overwrites(&self) -> Result<HashMap<rAddress, AccountOverride>, FileError> {
+        let state_diff = self
+            .overwrites
+            .iter()
+            .map(|(slot, value)| (rU256::from_limbs(slot.0), rU256::from_limbs(value.0)))
+            .collect();
 
         let erc20_abi_path = Path::new(file!())
             .parent()
@@ -92,10 +165,15 @@ impl ERC20OverwriteFactory {
                 FileError::FilePath("Failed to convert file path to string.".to_string())
             })?)?;
 
-        let code = format!("0x{}", hex::encode(contract_bytecode.bytes()));
-
         let mut result = HashMap::new();
-        result.insert(self.token_address, GethOverwrite { state_diff: formatted_overwrites, code });
+        result.insert(
+            self.token_address,
+            AccountOverride {
+                code: Some(contract_bytecode.bytes()),
+                storage: Some(StorageOverride::Diff(state_diff)),
+                ..Default::default()
+            },
+        );
 
         Ok(result)
     }
@@ -104,7 +182,8 @@ impl ERC20OverwriteFactory {
 #[cfg(test)]
 mod tests {
     use super::*;
-    use crate::protocol::vm::utils::SlotId;
+    use crate::protocol::vm::utils::{get_storage_slot_index_at_key, SlotId};
+    use rstest::rstest;
 
     fn setup_factory() -> ERC20OverwriteFactory {
         let token_address = rAddress::parse_checksummed(
@@ -159,6 +238,59 @@ mod tests {
         assert_eq!(factory.overwrites[&factory.total_supply_slot], supply);
     }
 
+    #[test]
+    fn test_set_packed_balance() {
+        let flags = U256::from(0b101) << 128;
+        let mut factory = setup_factory().with_packed_balance(PackedValue {
+            offset: 0,
+            bits: 128,
+            other_bits: flags | U256::from(7),
+        });
+        let owner = Address::random();
+
+        factory.set_balance(U256::from(1000), owner);
+        factory.set_balance(U256::MAX, owner);
+        let capped = factory.overwrites[&get_storage_slot_index_at_key(owner, SlotId::from(5))];
+
+        assert_eq!(capped, flags | U256::from(u128::MAX));
+    }
+
+    #[rstest]
+    #[case::owner_spender(AllowanceKeyOrder::OwnerSpender, ContractCompiler::Solidity)]
+    #[case::spender_owner(AllowanceKeyOrder::SpenderOwner, ContractCompiler::Solidity)]
+    #[case::vyper(AllowanceKeyOrder::OwnerSpender, ContractCompiler::Vyper)]
+    fn test_set_allowance_layouts(
+        #[case] order: AllowanceKeyOrder,
+        #[case] compiler: ContractCompiler,
+    ) {
+        let mut factory = setup_factory()
+            .with_allowance_key_order(order)
+            .with_compiler(compiler);
+        let owner = Address::random();
+        let spender = Address::random();
+
+        factory.set_allowance(U256::from(500), spender, owner);
+
+        let (outer, inner) = match order {
+            AllowanceKeyOrder::OwnerSpender => (owner, spender),
+            AllowanceKeyOrder::SpenderOwner => (spender, owner),
+        };
+        let slot =
+            compiler.compute_map_slot(inner, compiler.compute_map_slot(outer, SlotId::from(6)));
+        assert_eq!(factory.overwrites.get(&slot), Some(&U256::from(500)));
+    }
+
+    #[test]
+    fn test_custom_total_supply_slot_and_direct_writes() {
+        let mut factory = setup_factory().with_total_supply_slot(SlotId::from(9));
+
+        factory.set_total_supply(U256::from(1_000_000));
+        factory.set_slot(SlotId::from(42), U256::from(7));
+
+        assert_eq!(factory.overwrites[&SlotId::from(9)], U256::from(1_000_000));
+        assert_eq!(factory.overwrites[&SlotId::from(42)], U256::from(7));
+    }
+
     #[test]
     fn test_get_overwrites() {
         let mut factory = setup_factory();
@@ -189,21 +321,24 @@ mod tests {
 
         assert_eq!(result.len(), 1);
 
-        let geth_overwrite = result
-            .get(&factory.token_address)
-            .expect("Missing token address");
-        assert_eq!(geth_overwrite.state_diff.len(), 1);
-
-        let expected_key =
-            String::from("0000000000000000000000000000000000000000000000000000000000000001");
-        let expected_val =
-            String::from("0x000000000000000000000000000000000000000000000000000000000001e240");
+        let geth_overwrite = serde_json::to_value(
+            result
+                .get(&factory.token_address)
+                .expect("Missing token address"),
+        )
+        .unwrap();
+        assert_eq!(
+            geth_overwrite["stateDiff"],
+            serde_json::json!({
+                "0x0000000000000000000000000000000000000000000000000000000000000001":
+                    "0x000000000000000000000000000000000000000000000000000000000001e240"
+            })
+        );
         assert_eq!(
-            geth_overwrite
-                .state_diff
-                .get(&expected_key),
-            Some(&expected_val)
+            geth_overwrite["code"]
+                .as_str()
+                .map(str::len),
+            Some(8752)
         );
-        assert_eq!(geth_overwrite.code.len(), 8752);
     }
 }
@@ -0,0 +1,239 @@
+//! ERC20 storage slot discovery
+//!
+//! Finds the storage slots of a token's balances and allowances mappings by writing a marker
+//! value to candidate slots and checking whether `balanceOf`/`allowance` return it.
+use std::{collections::HashMap, fmt::Debug, sync::LazyLock};
+
+use ethers::{
+    abi::{encode, Token},
+    types::{Bytes, H160, U256},
+    utils::id,
+};
+use mini_moka::sync::Cache;
+use revm::{
+    primitives::{Address as rAddress, KECCAK_EMPTY},
+    DatabaseRef,
+};
+
+use crate::{
+    evm::{
+        simulation::{SimulationEngine, SimulationEngineError, SimulationParameters},
+        simulation_db::BlockHeader,
+        tycho_models::Chain,
+    },
+    protocol::{
+        errors::SimulationError,
+        vm::{
+            constants::{ADAPTER_ADDRESS, EXTERNAL_ACCOUNT},
+            utils::{ContractCompiler, SlotId},
+        },
+    },
+};
+
+/// The slots of a token's balances and allowances mappings, and the compiler that determines
+/// how their keys are hashed
+pub type TokenSlots = ((SlotId, SlotId), ContractCompiler);
+
+/// Mappings declared after the first `MAX_SLOT` storage variables are not found
+const MAX_SLOT: u64 = 100;
+
+/// The value written to the candidate slots, unlikely to be returned by chance
+const MARKER: U256 = U256([0x1234_5678_9abc_def0, 0x0fed_cba9_8765_4321, 0, 0]);
+
+/// Found slots by chain and token. Tokens with code whose slots were not found are cached as
+/// `None`, so they aren't probed again.
+static TOKEN_SLOTS_CACHE: LazyLock<Cache<(Chain, H160), Option<TokenSlots>>> =
+    LazyLock::new(|| Cache::new(10_000));
+
+/// Finds the storage slots of the balances and allowances mappings of `token`.
+///
+/// Both Solidity and Vyper key ordering are tried for each of the first `MAX_SLOT` slots. Results
+/// are cached per chain and token, as the storage layout of a token doesn't change between blocks.
+/// That no slots were found is only cached if the engine's database holds the token's code, as
+/// the slots of a token whose code isn't loaded yet may still be found later.
+///
+/// The engine's database has to contain the token and the `EXTERNAL_ACCOUNT`, which is used as
+/// the caller of the probing calls.
+///
+/// # Errors
+///
+/// Returns `SimulationError::NotFound` if no candidate slot holds the balances or the allowances,
+/// e.g. for tokens that don't store them in plain mappings.
+pub fn find_token_slots<D: DatabaseRef + Clone>(
+    chain: Chain,
+    token: H160,
+    engine: &SimulationEngine<D>,
+    block: &BlockHeader,
+) -> Result<TokenSlots, SimulationError>
+where
+    D::Error: Debug,
+{
+    let not_found = || SimulationError::NotFound(format!("storage slots of token {:?}", token));
+    if let Some(slots) = TOKEN_SLOTS_CACHE.get(&(chain, token)) {
+        return slots.ok_or_else(not_found);
+    }
+
+    let owner = H160::from_slice(EXTERNAL_ACCOUNT.as_slice());
+    let spender = H160::from_slice(ADAPTER_ADDRESS.as_slice());
+
+    let mut balance_calldata = id("balanceOf(address)").to_vec();
+    balance_calldata.extend(encode(&[Token::Address(owner)]));
+    let Some((balance_slot, compiler)) = find_mapping_slot(
+        engine,
+        block,
+        token,
+        &balance_calldata,
+        &[ContractCompiler::Solidity, ContractCompiler::Vyper],
+        |compiler, slot| compiler.compute_map_slot(owner, slot),
+    )?
+    else {
+        cache_not_found(chain, token, engine);
+        return Err(SimulationError::NotFound(format!("balance slot of token {:?}", token)));
+    };
+
+    let mut allowance_calldata = id("allowance(address,address)").to_vec();
+    allowance_calldata.extend(encode(&[Token::Address(owner), Token::Address(spender)]));
+    let Some((allowance_slot, _)) = find_mapping_slot(
+        engine,
+        block,
+        token,
+        &allowance_calldata,
+        &[compiler],
+        |compiler, slot| compiler.compute_map_slot(spender, compiler.compute_map_slot(owner, slot)),
+    )?
+    else {
+        cache_not_found(chain, token, engine);
+        return Err(SimulationError::NotFound(format!("allowance slot of token {:?}", token)));
+    };
+
+    let slots = ((balance_slot, allowance_slot), compiler);
+    TOKEN_SLOTS_CACHE.insert((chain, token), Some(slots));
+    Ok(slots)
+}
+
+/// Caches that the slots of `token` weren't found, if the engine's database holds its code.
+fn cache_not_found<D: DatabaseRef + Clone>(chain: Chain, token: H160, engine: &SimulationEngine<D>)
+where
+    D::Error: Debug,
+{
+    let has_code = matches!(
+        engine
+            .state
+            .basic_ref(rAddress::from_slice(token.as_bytes())),
+        Ok(Some(info)) if info.code_hash != KECCAK_EMPTY
+    );
+    if has_code {
+        TOKEN_SLOTS_CACHE.insert((chain, token), None);
+    }
+}
+
+/// Returns the first mapping slot for which writing the marker to the value's storage slot makes
+/// the call return it.
+fn find_mapping_slot<D: DatabaseRef + Clone>(
+    engine: &SimulationEngine<D>,
+    block: &BlockHeader,
+    token: H160,
+    calldata: &[u8],
+    compilers: &[ContractCompiler],
+    value_slot: impl Fn(ContractCompiler, SlotId) -> SlotId,
+) -> Result<Option<(SlotId, ContractCompiler)>, SimulationError>
+where
+    D::Error: Debug,
+{
+    for slot in (0..MAX_SLOT).map(SlotId::from) {
+        for compiler in compilers {
+            let overrides = HashMap::from([(
+                rAddress::from_slice(token.as_bytes()),
+                HashMap::from([(value_slot(*compiler, slot), MARKER)]),
+            )]);
+            let params = SimulationParameters {
+                caller: *EXTERNAL_ACCOUNT,
+                to: rAddress::from_slice(token.as_bytes()),
+                data: Bytes::from(calldata.to_vec()),
+                value: U256::zero(),
+                overrides: Some(overrides),
+                account_overrides: None,
+                gas_limit: None,
+                block_number: block.number,
+                timestamp: block.timestamp,
+            };
+            match engine.simulate(&params) {
+                Ok(result)
+                    if result.result.len() >= 32 &&
+                        U256::from_big_endian(&result.result[..32]) == MARKER =>
+                {
+                    return Ok(Some((slot, *compiler)));
+                }
+                Err(err @ SimulationEngineError::StorageError(_)) => {
+                    return Err(SimulationError::SimulationEngineError(err));
+                }
+                // Overwriting an unrelated slot may make the call revert
+                _ => {}
+            }
+        }
+    }
+    Ok(None)
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use std::str::FromStr;
+
+    use revm::primitives::AccountInfo;
+
+    use crate::{
+        evm::{
+            engine_db_interface::EngineDatabaseInterface,
+            test_utils::{deploy, test_block},
+        },
+        protocol::vm::test_utils::{mocked_dai_engine, DAI},
+    };
+
+    #[test]
+    fn test_find_token_slots_of_mocked_token() {
+        let token = H160::from_str(DAI).unwrap();
+        let engine = mocked_dai_engine();
+        let block = test_block();
+
+        let slots = find_token_slots(Chain::Ethereum, token, &engine, &block).unwrap();
+
+        // the mocked ERC20 contract stores balances at slot 0 and allowances at slot 1
+        assert_eq!(slots, ((SlotId::from(0), SlotId::from(1)), ContractCompiler::Solidity));
+        assert_eq!(TOKEN_SLOTS_CACHE.get(&(Chain::Ethereum, token)), Some(Some(slots)));
+        assert_eq!(TOKEN_SLOTS_CACHE.get(&(Chain::ZkSync, token)), None);
+    }
+
+    #[test]
+    fn test_find_token_slots_caches_missing_slots() {
+        let token = H160::repeat_byte(0x42);
+        let engine = mocked_dai_engine();
+        let block = test_block();
+        // a contract returning zero for every call, so no slot returns the marker
+        deploy(&engine.state, rAddress::from(token.0), &hex::decode("60206000f3").unwrap());
+
+        let result = find_token_slots(Chain::Ethereum, token, &engine, &block);
+
+        assert!(matches!(result, Err(SimulationError::NotFound(_))));
+        assert_eq!(TOKEN_SLOTS_CACHE.get(&(Chain::Ethereum, token)), Some(None));
+    }
+
+    #[test]
+    fn test_find_token_slots_without_code_is_not_cached() {
+        let token = H160::repeat_byte(0x43);
+        let engine = mocked_dai_engine();
+        let block = test_block();
+        engine.state.init_account(
+            rAddress::from(token.0),
+            AccountInfo { code_hash: KECCAK_EMPTY, code: None, ..Default::default() },
+            None,
+            true,
+        );
+
+        let result = find_token_slots(Chain::Ethereum, token, &engine, &block);
+
+        assert!(matches!(result, Err(SimulationError::NotFound(_))));
+        assert_eq!(TOKEN_SLOTS_CACHE.get(&(Chain::Ethereum, token)), None);
+    }
+}
@@ -2,9 +2,13 @@ mod adapter_contract;
 mod constants;
 pub mod engine;
 mod erc20_overwrite_factory;
+pub mod erc20_slot_finder;
 pub mod errors;
 mod models;
 pub mod state;
+#[cfg(test)]
+pub(crate) mod test_utils;
+pub mod token_tax_detector;
 pub mod tycho_decoder;
 mod tycho_simulation_contract;
 pub mod utils;
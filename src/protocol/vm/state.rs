@@ -1,6 +1,7 @@
 use std::{
     any::Any,
     collections::{HashMap, HashSet},
+    sync::Arc,
 };
 
 use alloy_primitives::Address;
@@ -20,30 +21,35 @@ use revm::{
     },
     DatabaseRef,
 };
+use serde::{Deserialize, Serialize};
 use tracing::warn;
 
 use tycho_core::dto::ProtocolStateDelta;
 
 use crate::{
     evm::{
+        account_storage::StateUpdate,
         engine_db_interface::EngineDatabaseInterface,
         simulation::{SimulationEngine, SimulationParameters},
         simulation_db::BlockHeader,
         tycho_db::PreCachedDB,
+        tycho_models::Chain,
     },
     models::ERC20Token,
     protocol::{
         errors::{SimulationError, TransitionError},
         events::{EVMLogMeta, LogIndex},
-        models::GetAmountOutResult,
+        models::{GetAmountOutResult, PartialFillResult, QuoteResult},
         state::{ProtocolEvent, ProtocolSim},
         vm::{
+            adapter_contract::Trade,
             constants::{ADAPTER_ADDRESS, EXTERNAL_ACCOUNT, MAX_BALANCE},
             engine::{create_engine, SHARED_TYCHO_DB},
             erc20_overwrite_factory::{ERC20OverwriteFactory, Overwrites},
+            erc20_slot_finder::{find_token_slots, TokenSlots},
             models::Capability,
             tycho_simulation_contract::TychoSimulationContract,
-            utils::{get_code_for_contract, get_contract_bytecode, SlotId},
+            utils::{get_code_for_contract, get_contract_bytecode, ContractCompiler, SlotId},
         },
     },
 };
@@ -52,6 +58,8 @@ use crate::{
 pub struct VMPoolState<D: DatabaseRef + EngineDatabaseInterface + Clone> {
     /// The pool's identifier
     pub id: String,
+    /// The chain the pool is on. Defaults to Ethereum.
+    pub chain: Chain,
     /// The pool's token's addresses
     pub tokens: Vec<H160>,
     /// The current block, will be used to set vm context
@@ -62,33 +70,58 @@ pub struct VMPoolState<D: DatabaseRef + EngineDatabaseInterface + Clone> {
     /// If given, balances will be overwritten here instead of on the pool contract during
     /// simulations
     pub balance_owner: Option<H160>,
-    /// Spot prices of the pool by token pair
-    pub spot_prices: HashMap<(H160, H160), f64>,
+    /// Spot prices of the pool by token pair. Shared between clones and only copied on write.
+    pub spot_prices: Arc<HashMap<(H160, H160), f64>>,
     /// The supported capabilities of this pool
     pub capabilities: HashSet<Capability>,
     /// Storage overwrites that will be applied to all simulations. They will be cleared
     /// when ``clear_all_cache`` is called, i.e. usually at each block. Hence, the name.
-    pub block_lasting_overwrites: HashMap<rAddress, Overwrites>,
+    /// Shared between clones and only copied on write.
+    pub block_lasting_overwrites: Arc<HashMap<rAddress, Overwrites>>,
     /// A set of all contract addresses involved in the simulation of this pool."""
     pub involved_contracts: HashSet<H160>,
     /// Allows the specification of custom storage slots for token allowances and
     /// balances. This is particularly useful for token contracts involved in protocol
-    /// logic that extends beyond simple transfer functionality.
-    pub token_storage_slots: HashMap<H160, (SlotId, SlotId)>,
+    /// logic that extends beyond simple transfer functionality. Slots of involved tokens that
+    /// are not specified are discovered when they are first needed.
+    pub token_storage_slots: HashMap<H160, TokenSlots>,
     /// The address to bytecode map of all stateless contracts used by the protocol
     /// for simulations. If the bytecode is None, an RPC call is done to get the code from our node
-    pub stateless_contracts: HashMap<String, Option<Vec<u8>>>,
+    pub stateless_contracts: Arc<HashMap<String, Option<Vec<u8>>>>,
     /// If set, vm will emit detailed traces about the execution
     pub trace: bool,
     /// Indicates if the protocol uses custom update rules and requires update
     /// triggers to recalculate spot prices ect. Default is to update on all changes on
     /// the pool.
     pub manual_updates: bool,
+    /// The path to the adapter contract's runtime bytecode
+    pub adapter_contract_path: String,
     engine: Option<SimulationEngine<D>>,
     /// The adapter contract. This is used to run simulations
     adapter_contract: Option<TychoSimulationContract<D>>,
 }
 
+/// The serializable parts of a `VMPoolState`, from which the state can be rebuilt.
+///
+/// Spot prices are not included, they have to be set again after restoring the state.
+#[derive(Clone, Debug, PartialEq, Serialize, Deserialize)]
+pub struct VMPoolStateDescriptor {
+    pub id: String,
+    #[serde(default)]
+    pub chain: Chain,
+    pub tokens: Vec<H160>,
+    pub block: BlockHeader,
+    pub balances: HashMap<H160, U256>,
+    pub balance_owner: Option<H160>,
+    pub adapter_contract_path: String,
+    pub involved_contracts: HashSet<H160>,
+    pub stateless_contracts: HashMap<String, Option<Vec<u8>>>,
+    pub block_lasting_overwrites: HashMap<rAddress, Overwrites>,
+    pub token_storage_slots: HashMap<H160, TokenSlots>,
+    pub manual_updates: bool,
+    pub trace: bool,
+}
+
 impl VMPoolState<PreCachedDB> {
     #[allow(clippy::too_many_arguments)]
     pub async fn new(
@@ -100,22 +133,25 @@ impl VMPoolState<PreCachedDB> {
         adapter_contract_path: String,
         involved_contracts: HashSet<H160>,
         stateless_contracts: HashMap<String, Option<Vec<u8>>>,
+        token_storage_slots: HashMap<H160, TokenSlots>,
         manual_updates: bool,
         trace: bool,
     ) -> Result<Self, SimulationError> {
         let mut state = VMPoolState {
             id,
+            chain: Chain::default(),
             tokens,
             block,
             balances,
             balance_owner,
-            spot_prices: HashMap::new(),
+            spot_prices: Arc::new(HashMap::new()),
             capabilities: HashSet::new(),
-            block_lasting_overwrites: HashMap::new(),
+            block_lasting_overwrites: Arc::new(HashMap::new()),
             involved_contracts,
-            token_storage_slots: HashMap::new(),
-            stateless_contracts,
+            token_storage_slots,
+            stateless_contracts: Arc::new(stateless_contracts),
             trace,
+            adapter_contract_path: adapter_contract_path.clone(),
             engine: None,
             adapter_contract: None,
             manual_updates,
@@ -131,10 +167,63 @@ impl VMPoolState<PreCachedDB> {
                 .ok_or_else(|| SimulationError::NotInitialized("Simulation engine".to_string()))?,
         )?);
         state.set_capabilities()?;
-        // TODO: add init_token_storage_slots() in 3796
         Ok(state)
     }
 
+    /// Sets the chain the pool is on.
+    pub fn with_chain(mut self, chain: Chain) -> Self {
+        self.chain = chain;
+        self
+    }
+
+    /// Returns the descriptor from which this state can be rebuilt with `from_descriptor`.
+    pub fn descriptor(&self) -> VMPoolStateDescriptor {
+        VMPoolStateDescriptor {
+            id: self.id.clone(),
+            chain: self.chain,
+            tokens: self.tokens.clone(),
+            block: self.block,
+            balances: self.balances.clone(),
+            balance_owner: self.balance_owner,
+            adapter_contract_path: self.adapter_contract_path.clone(),
+            involved_contracts: self.involved_contracts.clone(),
+            stateless_contracts: self
+                .stateless_contracts
+                .as_ref()
+                .clone(),
+            block_lasting_overwrites: self
+                .block_lasting_overwrites
+                .as_ref()
+                .clone(),
+            token_storage_slots: self.token_storage_slots.clone(),
+            manual_updates: self.manual_updates,
+            trace: self.trace,
+        }
+    }
+
+    /// Rebuilds a state from its descriptor, setting up the simulation engine and the adapter
+    /// contract as `new` does.
+    pub async fn from_descriptor(
+        descriptor: VMPoolStateDescriptor,
+    ) -> Result<Self, SimulationError> {
+        let mut state = VMPoolState::new(
+            descriptor.id,
+            descriptor.tokens,
+            descriptor.block,
+            descriptor.balances,
+            descriptor.balance_owner,
+            descriptor.adapter_contract_path,
+            descriptor.involved_contracts,
+            descriptor.stateless_contracts,
+            descriptor.token_storage_slots,
+            descriptor.manual_updates,
+            descriptor.trace,
+        )
+        .await?;
+        state.block_lasting_overwrites = Arc::new(descriptor.block_lasting_overwrites);
+        Ok(state.with_chain(descriptor.chain))
+    }
+
     async fn set_engine(&mut self, adapter_contract_path: String) -> Result<(), SimulationError> {
         if self.engine.is_none() {
             let token_addresses = self
@@ -270,6 +359,7 @@ impl VMPoolState<PreCachedDB> {
             overrides: Some(HashMap::new()),
             caller: *EXTERNAL_ACCOUNT,
             value: 0.into(),
+            account_overrides: None,
             gas_limit: None,
         };
 
@@ -293,6 +383,62 @@ impl VMPoolState<PreCachedDB> {
             .map_err(|_| SimulationError::DecodingError("Couldn't parse address to string".into()))
     }
 
+    /// Whether the adapter accounts for fee-on-transfer tokens itself.
+    ///
+    /// Otherwise the transfer taxes from the token metadata are applied around the adapter's
+    /// swap: the adapter is asked to sell the amount left after the sell tax and the buy tax is
+    /// deducted from the amount it returns.
+    pub fn supports_fee_on_transfer(&self) -> bool {
+        self.capabilities
+            .contains(&Capability::FeeOnTransfer)
+    }
+
+    /// The amount the adapter swaps when the trader sells `amount_in` of `token_in`
+    fn adapter_sell_amount(&self, amount_in: U256, token_in: &ERC20Token) -> U256 {
+        if self.supports_fee_on_transfer() {
+            amount_in
+        } else {
+            token_in.amount_after_sell_tax(amount_in)
+        }
+    }
+
+    /// The largest amount the trader can sell for the adapter to swap `sell_amount` of `token_in`
+    fn trader_sell_amount(&self, sell_amount: U256, token_in: &ERC20Token) -> U256 {
+        if self.supports_fee_on_transfer() {
+            sell_amount
+        } else {
+            token_in.max_amount_before_sell_tax(sell_amount)
+        }
+    }
+
+    /// The amount the trader receives when the adapter returns `amount_out` of `token_out`
+    fn received_amount(&self, amount_out: U256, token_out: &ERC20Token) -> U256 {
+        if self.supports_fee_on_transfer() {
+            amount_out
+        } else {
+            token_out.amount_after_buy_tax(amount_out)
+        }
+    }
+
+    /// Returns the storage slots of a token's balances and allowances.
+    ///
+    /// Slots of involved tokens that were not specified are discovered on first use, so tokens
+    /// whose slots can't be found only fail the simulations that need them.
+    fn token_slots(&self, token: &H160) -> Result<TokenSlots, SimulationError> {
+        if let Some(slots) = self.token_storage_slots.get(token) {
+            Ok(*slots)
+        } else if self.involved_contracts.contains(token) {
+            let engine = self
+                .engine
+                .as_ref()
+                .ok_or_else(|| SimulationError::NotInitialized("Simulation engine".to_string()))?;
+            find_token_slots(self.chain, *token, engine, &self.block)
+        } else {
+            // the mocked ERC20 contract stores balances at slot 0 and allowances at slot 1
+            Ok(((SlotId::from(0), SlotId::from(1)), ContractCompiler::Solidity))
+        }
+    }
+
     /// Ensures the pool supports the given capability
     fn ensure_capability(&self, capability: Capability) -> Result<(), SimulationError> {
         if !self.capabilities.contains(&capability) {
@@ -387,7 +533,7 @@ impl VMPoolState<PreCachedDB> {
                     10f64.powi(buy_token.decimals as i32)
             };
 
-            self.spot_prices
+            Arc::make_mut(&mut self.spot_prices)
                 .insert((sell_token.address, buy_token.address), price);
         }
         Ok(())
@@ -401,19 +547,29 @@ impl VMPoolState<PreCachedDB> {
         tokens: Vec<H160>,
         overwrites: Option<HashMap<Address, HashMap<U256, U256>>>,
     ) -> Result<U256, SimulationError> {
+        Ok(self
+            .get_pool_limits(tokens, overwrites)?
+            .0)
+    }
+
+    /// Retrieves the sell and buy amount limits for a given pair of tokens, ordered as in
+    /// `get_sell_amount_limit`.
+    fn get_pool_limits(
+        &self,
+        tokens: Vec<H160>,
+        overwrites: Option<HashMap<Address, HashMap<U256, U256>>>,
+    ) -> Result<(U256, U256), SimulationError> {
         let binding = self
             .adapter_contract
             .clone()
             .ok_or_else(|| SimulationError::NotInitialized("Adapter contract".to_string()))?;
-        let limits = binding.get_limits(
+        binding.get_limits(
             self.id.clone()[2..].to_string(),
             tokens[0],
             tokens[1],
             self.block.number,
             overwrites,
-        );
-
-        Ok(limits?.0)
+        )
     }
 
     fn get_overwrites(
@@ -424,8 +580,7 @@ impl VMPoolState<PreCachedDB> {
         let token_overwrites = self.get_token_overwrites(tokens, max_amount)?;
 
         // Merge `block_lasting_overwrites` with `token_overwrites`
-        let merged_overwrites =
-            self.merge(&self.block_lasting_overwrites.clone(), &token_overwrites);
+        let merged_overwrites = self.merge(&self.block_lasting_overwrites, &token_overwrites);
 
         Ok(merged_overwrites)
     }
@@ -443,13 +598,9 @@ impl VMPoolState<PreCachedDB> {
         {
             res.push(self.get_balance_overwrites(tokens)?);
         }
-        let mut overwrites = ERC20OverwriteFactory::new(
-            rAddress::from_slice(&sell_token.0),
-            *self
-                .token_storage_slots
-                .get(sell_token)
-                .unwrap_or(&(SlotId::from(0), SlotId::from(1))),
-        );
+        let (slots, compiler) = self.token_slots(sell_token)?;
+        let mut overwrites = ERC20OverwriteFactory::new(rAddress::from_slice(&sell_token.0), slots)
+            .with_compiler(compiler);
 
         overwrites.set_balance(max_amount, H160::from_slice(&*EXTERNAL_ACCOUNT.0));
 
@@ -482,18 +633,9 @@ impl VMPoolState<PreCachedDB> {
         }?;
 
         for token in &tokens {
-            let slots = if self.involved_contracts.contains(token) {
-                self.token_storage_slots
-                    .get(token)
-                    .cloned()
-                    .ok_or_else(|| {
-                        SimulationError::EncodingError("Token storage slots not found".into())
-                    })?
-            } else {
-                (SlotId::from(0), SlotId::from(1))
-            };
-
-            let mut overwrites = ERC20OverwriteFactory::new(rAddress::from(token.0), slots);
+            let (slots, compiler) = self.token_slots(token)?;
+            let mut overwrites =
+                ERC20OverwriteFactory::new(rAddress::from(token.0), slots).with_compiler(compiler);
             overwrites.set_balance(
                 self.balances
                     .get(token)
@@ -506,87 +648,119 @@ impl VMPoolState<PreCachedDB> {
         Ok(balance_overwrites)
     }
 
-    fn merge(
-        &self,
-        target: &HashMap<rAddress, Overwrites>,
-        source: &HashMap<rAddress, Overwrites>,
-    ) -> HashMap<rAddress, Overwrites> {
-        let mut merged = target.clone();
-
-        for (key, source_inner) in source {
-            merged
-                .entry(*key)
-                .or_default()
-                .extend(source_inner.clone());
-        }
-
-        merged
-    }
-}
-
-impl ProtocolSim for VMPoolState<PreCachedDB> {
-    fn fee(&self) -> f64 {
-        todo!()
-    }
-
-    fn spot_price(&self, base: &ERC20Token, quote: &ERC20Token) -> Result<f64, SimulationError> {
-        self.spot_prices
-            .get(&(base.address, quote.address))
-            .cloned()
-            .ok_or(SimulationError::NotFound("Spot prices".to_string()))
-    }
-
-    fn get_amount_out(
+    /// Computes the overwrites needed to swap between the given tokens and the pool's sell limit.
+    ///
+    /// Neither depends on the sell amount, so they can be reused for several swaps of the pair.
+    fn swap_context(
         &self,
-        amount_in: U256,
-        token_in: &ERC20Token,
-        token_out: &ERC20Token,
-    ) -> Result<GetAmountOutResult, SimulationError> {
-        let sell_token = token_in.address;
-        let buy_token = token_out.address;
-        let sell_amount = amount_in;
+        sell_token: H160,
+        buy_token: H160,
+    ) -> Result<(HashMap<rAddress, Overwrites>, U256), SimulationError> {
         let overwrites = self.get_overwrites(
             vec![sell_token, buy_token],
             U256::from_big_endian(&(*MAX_BALANCE / rU256::from(100)).to_be_bytes::<32>()),
         )?;
         let sell_amount_limit =
             self.get_sell_amount_limit(vec![sell_token, buy_token], Some(overwrites.clone()))?;
-        let (sell_amount_respecting_limit, sell_amount_exceeds_limit) = if self
-            .capabilities
-            .contains(&Capability::HardLimits) &&
-            sell_amount_limit < sell_amount
-        {
-            (sell_amount_limit, true)
-        } else {
-            (sell_amount, false)
-        };
 
         let overwrites_with_sell_limit =
             self.get_overwrites(vec![sell_token, buy_token], sell_amount_limit)?;
         let complete_overwrites = self.merge(&overwrites, &overwrites_with_sell_limit);
-        let pool_id = self.id.clone();
+        Ok((complete_overwrites, sell_amount_limit))
+    }
+
+    /// Runs the adapter's swap for the given amount, respecting the pool's sell limit if it has
+    /// hard limits.
+    ///
+    /// Returns the trade, the state changes caused by it and, if the sell amount had to be capped
+    /// at the sell limit, the amount actually sold.
+    fn simulate_swap(
+        &self,
+        sell_amount: U256,
+        sell_token: H160,
+        buy_token: H160,
+    ) -> Result<(Trade, HashMap<rAddress, StateUpdate>, Option<U256>), SimulationError> {
+        let (overwrites, sell_amount_limit) = self.swap_context(sell_token, buy_token)?;
+        self.simulate_swap_with_context(
+            sell_amount,
+            sell_token,
+            buy_token,
+            overwrites,
+            sell_amount_limit,
+        )
+    }
+
+    fn simulate_swap_with_context(
+        &self,
+        sell_amount: U256,
+        sell_token: H160,
+        buy_token: H160,
+        overwrites: HashMap<rAddress, Overwrites>,
+        sell_amount_limit: U256,
+    ) -> Result<(Trade, HashMap<rAddress, StateUpdate>, Option<U256>), SimulationError> {
+        let capped_sell_amount = (self
+            .capabilities
+            .contains(&Capability::HardLimits) &&
+            sell_amount_limit < sell_amount)
+            .then_some(sell_amount_limit);
+        let sell_amount_respecting_limit = capped_sell_amount.unwrap_or(sell_amount);
 
         let (trade, state_changes) = self
             .adapter_contract
             .as_ref()
             .ok_or_else(|| SimulationError::NotInitialized("Adapter contract".to_string()))?
             .swap(
-                pool_id[2..].to_string(),
+                self.id[2..].to_string(),
                 sell_token,
                 buy_token,
                 false,
                 sell_amount_respecting_limit,
                 self.block.number,
-                Some(complete_overwrites),
+                Some(overwrites),
             )?;
 
+        Ok((trade, state_changes, capped_sell_amount))
+    }
+
+    /// Turns the outcome of a swap into a quote, or into a partial fill if the sell amount was
+    /// capped at the sell limit.
+    fn quote_from_swap(
+        &self,
+        trade: Trade,
+        state_changes: HashMap<rAddress, StateUpdate>,
+        capped_sell_amount: Option<U256>,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<QuoteResult, SimulationError> {
+        let buy_amount = self.received_amount(trade.received_amount, token_out);
+        if let Some(sell_amount) = capped_sell_amount {
+            // The partial fill carries the new state, so it has to be built in this case
+            let new_state =
+                self.apply_trade(&trade, state_changes, token_in.address, token_out.address)?;
+            return Err(SimulationError::SellAmountTooHigh(PartialFillResult::new(
+                self.trader_sell_amount(sell_amount, token_in),
+                buy_amount,
+                trade.gas_used,
+                Box::new(new_state),
+            )));
+        }
+        Ok(QuoteResult::new(buy_amount, trade.gas_used))
+    }
+
+    /// Builds the state resulting from a trade, given the state changes it caused.
+    fn apply_trade(
+        &self,
+        trade: &Trade,
+        state_changes: HashMap<rAddress, StateUpdate>,
+        sell_token: H160,
+        buy_token: H160,
+    ) -> Result<Self, SimulationError> {
         let mut new_state = self.clone();
 
         // Apply state changes to the new state
         for (address, state_update) in state_changes {
             if let Some(storage) = state_update.storage {
-                let block_overwrites = new_state
-                    .block_lasting_overwrites
+                let block_overwrites = Arc::make_mut(&mut new_state.block_lasting_overwrites)
                     .entry(address)
                     .or_default();
                 for (slot, value) in storage {
@@ -606,26 +780,209 @@ impl ProtocolSim for VMPoolState<PreCachedDB> {
         // Update spot prices
         let new_price = trade.price;
         if new_price != 0.0f64 {
-            new_state
-                .spot_prices
-                .insert((sell_token, buy_token), new_price);
-            new_state
-                .spot_prices
-                .insert((buy_token, sell_token), 1.0f64 / new_price);
+            let spot_prices = Arc::make_mut(&mut new_state.spot_prices);
+            spot_prices.insert((sell_token, buy_token), new_price);
+            spot_prices.insert((buy_token, sell_token), 1.0f64 / new_price);
         }
+        Ok(new_state)
+    }
 
-        let buy_amount = trade.received_amount;
+    fn merge(
+        &self,
+        target: &HashMap<rAddress, Overwrites>,
+        source: &HashMap<rAddress, Overwrites>,
+    ) -> HashMap<rAddress, Overwrites> {
+        let mut merged = target.clone();
 
-        if sell_amount_exceeds_limit {
-            return Err(SimulationError::SellAmountTooHigh(
-                // // Partial buy amount and gas used TODO: make this better
-                // buy_amount,
-                // trade.gas_used,
-                // new_state,
-                // sell_amount_limit,
-            ));
+        for (key, source_inner) in source {
+            merged
+                .entry(*key)
+                .or_default()
+                .extend(source_inner.clone());
         }
-        Ok(GetAmountOutResult::new(buy_amount, trade.gas_used, Box::new(new_state.clone())))
+
+        merged
+    }
+}
+
+impl ProtocolSim for VMPoolState<PreCachedDB> {
+    fn fee(&self) -> f64 {
+        todo!()
+    }
+
+    fn spot_price(&self, base: &ERC20Token, quote: &ERC20Token) -> Result<f64, SimulationError> {
+        self.spot_prices
+            .get(&(base.address, quote.address))
+            .cloned()
+            .ok_or(SimulationError::NotFound("Spot prices".to_string()))
+    }
+
+    fn get_amount_out(
+        &self,
+        amount_in: U256,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<GetAmountOutResult, SimulationError> {
+        let sell_token = token_in.address;
+        let buy_token = token_out.address;
+        let (trade, state_changes, capped_sell_amount) = self.simulate_swap(
+            self.adapter_sell_amount(amount_in, token_in),
+            sell_token,
+            buy_token,
+        )?;
+        let new_state = self.apply_trade(&trade, state_changes, sell_token, buy_token)?;
+        let buy_amount = self.received_amount(trade.received_amount, token_out);
+
+        if let Some(sell_amount) = capped_sell_amount {
+            return Err(SimulationError::SellAmountTooHigh(PartialFillResult::new(
+                self.trader_sell_amount(sell_amount, token_in),
+                buy_amount,
+                trade.gas_used,
+                Box::new(new_state),
+            )));
+        }
+        Ok(GetAmountOutResult::new(buy_amount, trade.gas_used, Box::new(new_state)))
+    }
+
+    fn quote_amount_out(
+        &self,
+        amount_in: U256,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<QuoteResult, SimulationError> {
+        let (trade, state_changes, capped_sell_amount) = self.simulate_swap(
+            self.adapter_sell_amount(amount_in, token_in),
+            token_in.address,
+            token_out.address,
+        )?;
+        self.quote_from_swap(trade, state_changes, capped_sell_amount, token_in, token_out)
+    }
+
+    /// Quotes all amounts on the same block, computing the overwrites and the sell limit of the
+    /// pair only once.
+    ///
+    /// If the adapter's prices are the average prices of trading the given amounts, i.e. it has
+    /// the `PriceFunction` capability but neither `MarginalPrice` nor `ScaledPrice`, the amounts
+    /// out of all amounts within the sell limit come from a single `price` call. As `price`
+    /// doesn't report gas, the gas of swapping the largest of them is reported for all of them.
+    ///
+    /// Otherwise, and for zero amounts and amounts above the sell limit, every amount is
+    /// simulated with its own adapter `swap`.
+    fn get_amounts_out(
+        &self,
+        amounts_in: &[U256],
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<Vec<Result<QuoteResult, SimulationError>>, SimulationError> {
+        let (sell_token, buy_token) = (token_in.address, token_out.address);
+        let (overwrites, sell_amount_limit) = self.swap_context(sell_token, buy_token)?;
+        let quote_swap = |amount_in: &U256| {
+            let (trade, state_changes, capped_sell_amount) = self.simulate_swap_with_context(
+                self.adapter_sell_amount(*amount_in, token_in),
+                sell_token,
+                buy_token,
+                overwrites.clone(),
+                sell_amount_limit,
+            )?;
+            self.quote_from_swap(trade, state_changes, capped_sell_amount, token_in, token_out)
+        };
+        let quote_each = || {
+            Ok(amounts_in
+                .iter()
+                .map(quote_swap)
+                .collect())
+        };
+
+        let average_prices = self
+            .capabilities
+            .contains(&Capability::PriceFunction) &&
+            !self
+                .capabilities
+                .contains(&Capability::MarginalPrice) &&
+            !self
+                .capabilities
+                .contains(&Capability::ScaledPrice);
+        let hard_limits = self
+            .capabilities
+            .contains(&Capability::HardLimits);
+        let sell_amounts: Vec<U256> = amounts_in
+            .iter()
+            .map(|amount_in| self.adapter_sell_amount(*amount_in, token_in))
+            .collect();
+        let is_priced = |sell_amount: &U256| {
+            !sell_amount.is_zero() && (!hard_limits || *sell_amount <= sell_amount_limit)
+        };
+        let priced_amounts: Vec<U256> = sell_amounts
+            .iter()
+            .copied()
+            .filter(is_priced)
+            .collect();
+        let max_priced_amount = match priced_amounts.iter().max() {
+            Some(amount) if average_prices => *amount,
+            _ => return quote_each(),
+        };
+
+        let adapter = self
+            .adapter_contract
+            .as_ref()
+            .ok_or_else(|| SimulationError::NotInitialized("Adapter contract".to_string()))?;
+        let prices = adapter.price_fractions(
+            self.id[2..].to_string(),
+            sell_token,
+            buy_token,
+            priced_amounts.clone(),
+            self.block.number,
+            Some(overwrites.clone()),
+        );
+        let gas = self
+            .simulate_swap_with_context(
+                max_priced_amount,
+                sell_token,
+                buy_token,
+                overwrites.clone(),
+                sell_amount_limit,
+            )
+            .map(|(trade, _, _)| trade.gas_used);
+        let (prices, gas) = match (prices, gas) {
+            (Ok(prices), Ok(gas)) if prices.len() == priced_amounts.len() => (prices, gas),
+            // the adapter can't price all amounts at once, they might still be swapped
+            _ => return quote_each(),
+        };
+
+        let mut prices = prices.into_iter();
+        Ok(amounts_in
+            .iter()
+            .zip(sell_amounts)
+            .map(|(amount_in, sell_amount)| {
+                if !is_priced(&sell_amount) {
+                    return quote_swap(amount_in);
+                }
+                let (numerator, denominator) = prices
+                    .next()
+                    .expect("There is a price for every priced amount");
+                let amount_out = U256::try_from(sell_amount.full_mul(numerator) / denominator)
+                    .map_err(|_| SimulationError::ArithmeticOverflow())?;
+                Ok(QuoteResult::new(self.received_amount(amount_out, token_out), gas))
+            })
+            .collect())
+    }
+
+    /// Returns the limits reported by the adapter's `getLimits` for the pair.
+    fn get_limits(
+        &self,
+        token_in: &ERC20Token,
+        token_out: &ERC20Token,
+    ) -> Result<(U256, U256), SimulationError> {
+        let tokens = vec![token_in.address, token_out.address];
+        let overwrites = self.get_overwrites(
+            tokens.clone(),
+            U256::from_big_endian(&(*MAX_BALANCE / rU256::from(100)).to_be_bytes::<32>()),
+        )?;
+        let (max_in, max_out) = self.get_pool_limits(tokens, Some(overwrites))?;
+        if self.supports_fee_on_transfer() {
+            return Ok((max_in, max_out));
+        }
+        Ok((token_in.max_amount_before_sell_tax(max_in), token_out.amount_after_buy_tax(max_out)))
     }
 
     fn delta_transition(
@@ -679,7 +1036,8 @@ mod tests {
     };
 
     use crate::{
-        evm::{simulation_db::BlockHeader, tycho_models::AccountUpdate},
+        evm::{simulation_db::BlockHeader, test_utils::test_block, tycho_models::AccountUpdate},
+        models::TransferBehaviour,
         protocol::vm::models::Capability,
     };
 
@@ -705,13 +1063,7 @@ mod tests {
             false,
         )?;
 
-        let block = BlockHeader {
-            number: 20463609,
-            hash: H256::from_str(
-                "0x4315fd1afc25cc2ebc72029c543293f9fd833eeb305e2e30159459c827733b1b",
-            )?,
-            timestamp: 1722875891,
-        };
+        let block = test_block();
 
         for account in accounts.clone() {
             engine.state.init_account(
@@ -770,6 +1122,7 @@ mod tests {
             "src/protocol/vm/assets/BalancerSwapAdapter.evm.runtime".to_string(),
             HashSet::new(),
             HashMap::new(),
+            HashMap::new(),
             false,
             false,
         )
@@ -848,6 +1201,22 @@ mod tests {
         Ok(())
     }
 
+    #[tokio::test]
+    async fn test_quote_amount_out() {
+        setup_db("src/protocol/vm/assets/balancer_contract_storage_block_20463609.json".as_ref())
+            .await
+            .unwrap();
+
+        let pool_state = setup_pool_state().await;
+
+        let result = pool_state
+            .quote_amount_out(U256::from_dec_str("1000000000000000000").unwrap(), &dai(), &bal())
+            .unwrap();
+
+        assert_eq!(result.amount, U256::from_dec_str("137780051463393923").unwrap());
+        assert_eq!(result.gas, U256::from_dec_str("102770").unwrap());
+    }
+
     #[tokio::test]
     async fn test_get_amount_out_dust() {
         setup_db("src/protocol/vm/assets/balancer_contract_storage_block_20463609.json".as_ref())
@@ -887,10 +1256,43 @@ mod tests {
 
         assert!(result.is_err());
         match result {
-            Err(e) => {
-                assert!(matches!(e, SimulationError::SellAmountTooHigh()));
+            Err(SimulationError::SellAmountTooHigh(partial)) => {
+                assert_eq!(
+                    partial.sell_amount,
+                    U256::from_dec_str("100279494253364362835").unwrap()
+                );
+                assert!(partial.amount > U256::zero());
+                assert!(partial
+                    .new_state
+                    .as_any()
+                    .downcast_ref::<VMPoolState<PreCachedDB>>()
+                    .is_some());
             }
-            _ => panic!("Test failed: was expecting an Err value"),
+            _ => panic!("Test failed: was expecting a SellAmountTooHigh error"),
+        };
+    }
+
+    #[tokio::test]
+    async fn test_get_amount_out_sell_limit_with_transfer_tax() {
+        setup_db("src/protocol/vm/assets/balancer_contract_storage_block_20463609.json".as_ref())
+            .await
+            .unwrap();
+        let pool_state = setup_pool_state().await;
+        let taxed_dai = dai().with_transfer_behaviour(TransferBehaviour {
+            sell_tax_bps: 1000,
+            ..Default::default()
+        });
+        let sell_limit = U256::from_dec_str("100279494253364362835").unwrap();
+
+        let result = pool_state.get_amount_out(sell_limit * 2, &taxed_dai, &bal());
+
+        match result {
+            Err(SimulationError::SellAmountTooHigh(partial)) => {
+                // the trader sells the amount that reaches the pool as the sell limit
+                assert_eq!(partial.sell_amount, taxed_dai.max_amount_before_sell_tax(sell_limit));
+                assert_eq!(taxed_dai.amount_after_sell_tax(partial.sell_amount), sell_limit);
+            }
+            _ => panic!("Test failed: was expecting a SellAmountTooHigh error"),
         };
     }
 
@@ -917,6 +1319,119 @@ mod tests {
         assert_eq!(bal_limit, U256::from_dec_str("13997408640689987484").unwrap());
     }
 
+    #[tokio::test]
+    async fn test_get_amounts_out() {
+        setup_db("src/protocol/vm/assets/balancer_contract_storage_block_20463609.json".as_ref())
+            .await
+            .unwrap();
+
+        let pool_state = setup_pool_state().await;
+        let amounts_in = vec![
+            U256::from_dec_str("1000000000000000000").unwrap(),
+            U256::from_dec_str("2000000000000000000").unwrap(),
+            U256::from_dec_str("100379494253364362835").unwrap(),
+        ];
+
+        let quotes = pool_state
+            .get_amounts_out(&amounts_in, &dai(), &bal())
+            .unwrap();
+
+        assert_eq!(
+            quotes[0].as_ref().unwrap().amount,
+            U256::from_dec_str("137780051463393923").unwrap()
+        );
+        assert_eq!(
+            quotes[1].as_ref().unwrap().amount,
+            pool_state
+                .quote_amount_out(amounts_in[1], &dai(), &bal())
+                .unwrap()
+                .amount
+        );
+        // sell limit is 100279494253364362835
+        assert!(matches!(quotes[2], Err(SimulationError::SellAmountTooHigh(_))));
+    }
+
+    #[tokio::test]
+    async fn test_restore_from_descriptor() {
+        let pool_state = setup_pool_state()
+            .await
+            .with_chain(Chain::ZkSync);
+
+        let json = serde_json::to_string(&pool_state.descriptor()).unwrap();
+        let descriptor: VMPoolStateDescriptor = serde_json::from_str(&json).unwrap();
+        let restored = VMPoolState::<PreCachedDB>::from_descriptor(descriptor)
+            .await
+            .unwrap();
+
+        assert_eq!(restored.descriptor(), pool_state.descriptor());
+        assert_eq!(restored.chain, Chain::ZkSync);
+        assert_eq!(restored.capabilities, pool_state.capabilities);
+    }
+
+    #[tokio::test]
+    async fn test_token_slots() {
+        let mut pool_state = setup_pool_state().await;
+        let specified = H160::repeat_byte(0x11);
+        let without_slots = H160::repeat_byte(0x22);
+        let slots = ((SlotId::from(3), SlotId::from(4)), ContractCompiler::Vyper);
+        pool_state
+            .involved_contracts
+            .extend([specified, without_slots]);
+        pool_state
+            .token_storage_slots
+            .insert(specified, slots);
+
+        assert_eq!(
+            pool_state
+                .token_slots(&specified)
+                .unwrap(),
+            slots
+        );
+        assert_eq!(
+            pool_state
+                .token_slots(&dai().address)
+                .unwrap(),
+            ((SlotId::from(0), SlotId::from(1)), ContractCompiler::Solidity)
+        );
+        // the token isn't in the database, but only the simulations needing its slots fail
+        assert!(pool_state
+            .token_slots(&without_slots)
+            .is_err());
+    }
+
+    #[tokio::test]
+    async fn test_get_limits() {
+        let pool_state = setup_pool_state().await;
+
+        let (max_in, max_out) = pool_state
+            .get_limits(&dai(), &bal())
+            .unwrap();
+
+        assert_eq!(max_in, U256::from_dec_str("100279494253364362835").unwrap());
+        assert!(max_out > U256::zero());
+    }
+
+    #[tokio::test]
+    async fn test_get_limits_with_transfer_taxes() {
+        let pool_state = setup_pool_state().await;
+        let taxed_dai = dai().with_transfer_behaviour(TransferBehaviour {
+            sell_tax_bps: 1000,
+            ..Default::default()
+        });
+        let taxed_bal = bal()
+            .with_transfer_behaviour(TransferBehaviour { buy_tax_bps: 500, ..Default::default() });
+
+        let (pool_max_in, pool_max_out) = pool_state
+            .get_limits(&dai(), &bal())
+            .unwrap();
+        let (max_in, max_out) = pool_state
+            .get_limits(&taxed_dai, &taxed_bal)
+            .unwrap();
+
+        assert_eq!(taxed_dai.amount_after_sell_tax(max_in), pool_max_in);
+        assert_eq!(max_out, taxed_bal.amount_after_buy_tax(pool_max_out));
+    }
+
     #[tokio::test]
     async fn test_set_spot_prices() {
         let mut pool_state = setup_pool_state().await;
@@ -0,0 +1,20 @@
+//! Fixtures shared by tests simulating on the mocked ERC20 contract
+use crate::{
+    evm::{simulation::SimulationEngine, tycho_db::PreCachedDB},
+    protocol::vm::engine::create_engine,
+};
+
+/// Address of DAI, at which `mocked_dai_engine` deploys the mocked ERC20 contract
+pub(crate) const DAI: &str = "0x6B175474E89094C44Da98b954EedeAC495271d0F";
+
+/// Creates an engine on an empty database with the mocked ERC20 contract at `DAI`.
+///
+/// The mocked contract stores balances at slot 0 and allowances at slot 1.
+pub(crate) fn mocked_dai_engine() -> SimulationEngine<PreCachedDB> {
+    create_engine(
+        PreCachedDB::new().expect("Failed to create PreCachedDB"),
+        vec![DAI.to_string()],
+        false,
+    )
+    .unwrap()
+}
@@ -0,0 +1,682 @@
+//! Token tax detection
+//!
+//! Infers transfer taxes and restrictions of a token by simulating transfers of its real
+//! bytecode between synthetic accounts and measuring the balance changes they cause.
+use std::{collections::HashMap, fmt::Debug};
+
+use ethers::{
+    abi::{decode, encode, ParamType, Token},
+    types::{Bytes, H160, U256, U512},
+    utils::id,
+};
+use revm::{
+    primitives::{AccountInfo, Address as rAddress, KECCAK_EMPTY},
+    DatabaseRef,
+};
+
+use crate::{
+    evm::{
+        account_storage::StateUpdate,
+        engine_db_interface::EngineDatabaseInterface,
+        simulation::{SimulationEngine, SimulationEngineError, SimulationParameters},
+        simulation_db::BlockHeader,
+    },
+    models::{ERC20Token, TransferBehaviour},
+    protocol::{
+        errors::SimulationError,
+        vm::{
+            erc20_overwrite_factory::{ERC20OverwriteFactory, Overwrites},
+            erc20_slot_finder::TokenSlots,
+            utils::maybe_coerce_error,
+        },
+    },
+};
+
+/// Sender of the simulated transfers
+const HOLDER: H160 = H160([0xd1; 20]);
+/// Receiver of the simulated transfers
+const RECIPIENT: H160 = H160([0xd2; 20]);
+/// Caller of the simulated `transferFrom`s
+const SPENDER: H160 = H160([0xd3; 20]);
+
+/// Transfers of up to `10^MAX_PROBE_EXPONENT` times the probe amount are checked for a max-tx limit
+const MAX_PROBE_EXPONENT: usize = 9;
+
+/// Basis points, i.e. 1/10_000
+const BPS: u32 = 10_000;
+
+/// Shortfalls of up to this many wei are not counted as tax. Share-based tokens, e.g. stETH,
+/// lose a wei or two to rounding on every transfer.
+const ROUNDING_TOLERANCE: u64 = 2;
+
+/// Outcome of a simulated transfer
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub enum TransferOutcome {
+    /// The transfer succeeded and the receiver got `tax_bps` basis points less than the sender's
+    /// balance dropped by, or than was sent if the balance dropped by less
+    Succeeded { tax_bps: u32 },
+    /// The transfer reverted or returned `false`
+    Reverted(String),
+}
+
+impl TransferOutcome {
+    fn tax_bps(&self) -> Option<u32> {
+        match self {
+            TransferOutcome::Succeeded { tax_bps } => Some(*tax_bps),
+            TransferOutcome::Reverted(_) => None,
+        }
+    }
+}
+
+/// Transfer behaviour of a token, as observed by the `TokenTaxDetector`
+#[derive(Clone, Debug, PartialEq, Eq)]
+pub struct TokenTransferReport {
+    /// `transfer` between two synthetic accounts
+    pub transfer: TransferOutcome,
+    /// `transferFrom` between two synthetic accounts
+    pub transfer_from: TransferOutcome,
+    /// Transfer from a synthetic account into the pool, i.e. a sell. Only checked if a pool is
+    /// given.
+    pub sell: Option<TransferOutcome>,
+    /// Transfer out of the pool to a synthetic account, i.e. a buy. Only checked if a pool is
+    /// given.
+    pub buy: Option<TransferOutcome>,
+    /// The largest amount a transfer was observed to succeed with, if larger transfers revert.
+    /// Not searched for rebasing tokens.
+    pub max_transfer_amount: Option<U256>,
+    /// Whether the balance slot holds shares rather than token amounts, i.e. balances change
+    /// whenever the share price does
+    pub rebasing: bool,
+}
+
+impl TokenTransferReport {
+    /// Whether any of the simulated transfers reverted, e.g. because of a blacklist or because
+    /// the token is a honeypot that can be bought but not sold.
+    pub fn is_restricted(&self) -> bool {
+        [Some(&self.transfer), Some(&self.transfer_from), self.sell.as_ref(), self.buy.as_ref()]
+            .into_iter()
+            .flatten()
+            .any(|outcome| matches!(outcome, TransferOutcome::Reverted(_)))
+    }
+
+    /// Converts the report into token metadata, see `ERC20Token::with_transfer_behaviour`.
+    ///
+    /// Without a pool, buys and sells are assumed to be taxed like plain transfers.
+    pub fn transfer_behaviour(&self) -> TransferBehaviour {
+        let transfer_tax = self
+            .transfer
+            .tax_bps()
+            .unwrap_or_default();
+        let tax_of = |outcome: &Option<TransferOutcome>| {
+            outcome
+                .as_ref()
+                .and_then(TransferOutcome::tax_bps)
+                .unwrap_or(transfer_tax)
+        };
+        TransferBehaviour {
+            buy_tax_bps: tax_of(&self.buy),
+            sell_tax_bps: tax_of(&self.sell),
+            rebasing: self.rebasing,
+            max_transfer_amount: self.max_transfer_amount,
+            restricted: self.is_restricted(),
+        }
+    }
+}
+
+/// Detects transfer taxes, blacklists and max-tx limits of tokens.
+///
+/// The detector overwrites the balances and allowances of synthetic accounts using the token's
+/// storage slots and runs `transfer`/`transferFrom` calls against the token's real bytecode. The
+/// tax of a transfer is the share of the sent amount, or of the sender's balance drop if it is
+/// larger, that the receiver's balance did not increase by.
+///
+/// A token whose `balanceOf` doesn't return the amount written to the balance slot stores shares
+/// instead of amounts and is reported as rebasing. Its transfers are probed with the balance the
+/// shares amount to.
+///
+/// Taxes that only apply to transfers from or to specific addresses, usually the token's pools,
+/// are only detected if such a pool is passed to `detect`.
+pub struct TokenTaxDetector<D: DatabaseRef + EngineDatabaseInterface + Clone> {
+    engine: SimulationEngine<D>,
+    block: BlockHeader,
+    probe_amount: Option<U256>,
+}
+
+impl<D: DatabaseRef + EngineDatabaseInterface + Clone> TokenTaxDetector<D>
+where
+    <D as DatabaseRef>::Error: Debug,
+    <D as EngineDatabaseInterface>::Error: Debug,
+{
+    /// Creates a detector simulating on `engine` at the given block.
+    ///
+    /// The synthetic accounts are initialized on the engine's database.
+    pub fn new(engine: SimulationEngine<D>, block: BlockHeader) -> Self {
+        for account in [HOLDER, RECIPIENT, SPENDER] {
+            engine.state.init_account(
+                rAddress::from_slice(account.as_bytes()),
+                AccountInfo {
+                    balance: Default::default(),
+                    nonce: 0,
+                    code_hash: KECCAK_EMPTY,
+                    code: None,
+                },
+                None,
+                true,
+            );
+        }
+        TokenTaxDetector { engine, block, probe_amount: None }
+    }
+
+    /// Sets the amount of the simulated transfers. Defaults to one whole token.
+    pub fn with_probe_amount(mut self, amount: U256) -> Self {
+        self.probe_amount = Some(amount);
+        self
+    }
+
+    /// Simulates transfers of `token` and reports their taxes and restrictions.
+    ///
+    /// # Arguments
+    ///
+    /// * `token` - The token to check
+    /// * `token_slots` - The storage slots of the token's balances and allowances mappings, see
+    ///   `find_token_slots`
+    /// * `pool` - A pool of the token to measure buy and sell taxes with, if they differ from the
+    ///   tax on plain transfers
+    pub fn detect(
+        &self,
+        token: &ERC20Token,
+        token_slots: TokenSlots,
+        pool: Option<H160>,
+    ) -> Result<TokenTransferReport, SimulationError> {
+        let slot_value = self
+            .probe_amount
+            .unwrap_or_else(|| U256::exp10(token.decimals));
+        let mut overwrites = ERC20OverwriteFactory::new(to_raddress(token.address), token_slots.0)
+            .with_compiler(token_slots.1);
+        overwrites.set_balance(slot_value, HOLDER);
+
+        let amount = self.balance_of(token.address, HOLDER, &overwrites.get_overwrites())?;
+        if amount.is_zero() {
+            return Err(SimulationError::InvalidInput(format!(
+                "Slot {} doesn't hold the balances of token {:?}",
+                token_slots.0 .0, token.address
+            )));
+        }
+        let rebasing = amount != slot_value;
+        overwrites.set_allowance(amount, SPENDER, HOLDER);
+        let overwrites = overwrites.get_overwrites();
+
+        let transfer = self.measure_transfer(
+            token.address,
+            HOLDER,
+            transfer_calldata(RECIPIENT, amount),
+            (HOLDER, RECIPIENT),
+            amount,
+            overwrites.clone(),
+        )?;
+        let transfer_from = self.measure_transfer(
+            token.address,
+            SPENDER,
+            transfer_from_calldata(HOLDER, RECIPIENT, amount),
+            (HOLDER, RECIPIENT),
+            amount,
+            overwrites.clone(),
+        )?;
+
+        let (sell, buy) = match pool {
+            Some(pool) => {
+                let sell = self.measure_transfer(
+                    token.address,
+                    HOLDER,
+                    transfer_calldata(pool, amount),
+                    (HOLDER, pool),
+                    amount,
+                    overwrites,
+                )?;
+                // Pools are contracts and can't send transactions, so the buy is simulated as a
+                // `transferFrom` out of the pool
+                let mut pool_overwrites =
+                    ERC20OverwriteFactory::new(to_raddress(token.address), token_slots.0)
+                        .with_compiler(token_slots.1);
+                pool_overwrites.set_balance(slot_value, pool);
+                pool_overwrites.set_allowance(amount, SPENDER, pool);
+                let buy = self.measure_transfer(
+                    token.address,
+                    SPENDER,
+                    transfer_from_calldata(pool, RECIPIENT, amount),
+                    (pool, RECIPIENT),
+                    amount,
+                    pool_overwrites.get_overwrites(),
+                )?;
+                (Some(sell), Some(buy))
+            }
+            None => (None, None),
+        };
+
+        // the search writes amounts to the balance slot, which only works if it holds amounts
+        let max_transfer_amount = match transfer {
+            TransferOutcome::Succeeded { .. } if !rebasing => {
+                self.max_transfer_amount(token.address, token_slots, amount)?
+            }
+            _ => None,
+        };
+
+        Ok(TokenTransferReport {
+            transfer,
+            transfer_from,
+            sell,
+            buy,
+            max_transfer_amount,
+            rebasing,
+        })
+    }
+
+    /// Checks whether transfers from `account` revert although transfers from a fresh account
+    /// succeed.
+    pub fn is_blacklisted(
+        &self,
+        token: &ERC20Token,
+        token_slots: TokenSlots,
+        account: H160,
+    ) -> Result<bool, SimulationError> {
+        let amount = self
+            .probe_amount
+            .unwrap_or_else(|| U256::exp10(token.decimals));
+        let transfer_succeeds = |owner: H160| -> Result<bool, SimulationError> {
+            let mut overwrites =
+                ERC20OverwriteFactory::new(to_raddress(token.address), token_slots.0)
+                    .with_compiler(token_slots.1);
+            overwrites.set_balance(amount, owner);
+            overwrites.set_allowance(amount, SPENDER, owner);
+            Ok(self
+                .call(
+                    token.address,
+                    SPENDER,
+                    transfer_from_calldata(owner, RECIPIENT, amount),
+                    overwrites.get_overwrites(),
+                )?
+                .is_ok())
+        };
+        Ok(transfer_succeeds(HOLDER)? && !transfer_succeeds(account)?)
+    }
+
+    /// Runs a transfer of `amount` from `sender` to `receiver` and measures how much the
+    /// sender's balance dropped by and how much of it arrived at the receiver.
+    fn measure_transfer(
+        &self,
+        token: H160,
+        caller: H160,
+        calldata: Vec<u8>,
+        (sender, receiver): (H160, H160),
+        amount: U256,
+        mut overwrites: HashMap<rAddress, Overwrites>,
+    ) -> Result<TransferOutcome, SimulationError> {
+        let sender_before = self.balance_of(token, sender, &overwrites)?;
+        let receiver_before = self.balance_of(token, receiver, &overwrites)?;
+        let state_updates = match self.call(token, caller, calldata, overwrites.clone())? {
+            Ok(state_updates) => state_updates,
+            Err(reason) => return Ok(TransferOutcome::Reverted(reason)),
+        };
+        apply_state_updates(&mut overwrites, &state_updates);
+        let sender_after = self.balance_of(token, sender, &overwrites)?;
+        let receiver_after = self.balance_of(token, receiver, &overwrites)?;
+
+        let debited = sender_before.saturating_sub(sender_after);
+        let received = receiver_after.saturating_sub(receiver_before);
+        Ok(TransferOutcome::Succeeded { tax_bps: transfer_tax_bps(amount, debited, received) })
+    }
+
+    /// Searches the largest amount a transfer succeeds with, given that a transfer of `amount`
+    /// succeeds.
+    ///
+    /// Returns `None` if transfers of up to `10^MAX_PROBE_EXPONENT` times `amount` succeed. The
+    /// search stops once the limit is known to within one basis point.
+    fn max_transfer_amount(
+        &self,
+        token: H160,
+        token_slots: TokenSlots,
+        amount: U256,
+    ) -> Result<Option<U256>, SimulationError> {
+        let transfer_succeeds = |amount: U256| -> Result<bool, SimulationError> {
+            let mut overwrites = ERC20OverwriteFactory::new(to_raddress(token), token_slots.0)
+                .with_compiler(token_slots.1);
+            overwrites.set_balance(amount, HOLDER);
+            Ok(self
+                .call(
+                    token,
+                    HOLDER,
+                    transfer_calldata(RECIPIENT, amount),
+                    overwrites.get_overwrites(),
+                )?
+                .is_ok())
+        };
+
+        let mut upper = amount.saturating_mul(U256::exp10(MAX_PROBE_EXPONENT));
+        if transfer_succeeds(upper)? {
+            return Ok(None);
+        }
+        let mut lower = amount;
+        // at least one unit of precision, as the bound of small amounts rounds down to zero
+        while upper - lower > U256::max(lower / U256::from(BPS), U256::one()) {
+            let mid = lower + (upper - lower) / 2;
+            if transfer_succeeds(mid)? {
+                lower = mid;
+            } else {
+                upper = mid;
+            }
+        }
+        Ok(Some(lower))
+    }
+
+    fn balance_of(
+        &self,
+        token: H160,
+        account: H160,
+        overwrites: &HashMap<rAddress, Overwrites>,
+    ) -> Result<U256, SimulationError> {
+        let mut calldata = id("balanceOf(address)").to_vec();
+        calldata.extend(encode(&[Token::Address(account)]));
+        let params = self.simulation_parameters(token, HOLDER, calldata, overwrites.clone());
+        let result = self
+            .engine
+            .simulate(&params)
+            .map_err(|err| {
+                SimulationError::SimulationEngineError(maybe_coerce_error(
+                    &err,
+                    "token_tax_detector",
+                    params.gas_limit,
+                ))
+            })?;
+        match decode(&[ParamType::Uint(256)], &result.result)
+            .map_err(|err| SimulationError::DecodingError(format!("Invalid balance: {:?}", err)))?
+            .first()
+        {
+            Some(Token::Uint(balance)) => Ok(*balance),
+            _ => Err(SimulationError::DecodingError("Invalid balance".to_string())),
+        }
+    }
+
+    /// Simulates a call to the token.
+    ///
+    /// Returns the state updates of the call, or the revert reason if it reverted or returned
+    /// `false`.
+    fn call(
+        &self,
+        token: H160,
+        caller: H160,
+        calldata: Vec<u8>,
+        overwrites: HashMap<rAddress, Overwrites>,
+    ) -> Result<Result<HashMap<rAddress, StateUpdate>, String>, SimulationError> {
+        let params = self.simulation_parameters(token, caller, calldata, overwrites);
+        match self.engine.simulate(&params) {
+            // Tokens that don't return a value signal failure by reverting
+            Ok(result) if result.result.len() == 32 && result.result.iter().all(|b| *b == 0) => {
+                Ok(Err("Transfer returned false".to_string()))
+            }
+            Ok(result) => Ok(Ok(result.state_updates)),
+            Err(err @ SimulationEngineError::StorageError(_)) => {
+                Err(SimulationError::SimulationEngineError(err))
+            }
+            Err(err) => match maybe_coerce_error(&err, "token_tax_detector", params.gas_limit) {
+                SimulationEngineError::TransactionError { data, .. } => Ok(Err(data)),
+                SimulationEngineError::OutOfGas(reason, _) |
+                SimulationEngineError::StorageError(reason) => Ok(Err(reason)),
+            },
+        }
+    }
+
+    fn simulation_parameters(
+        &self,
+        token: H160,
+        caller: H160,
+        calldata: Vec<u8>,
+        overwrites: HashMap<rAddress, Overwrites>,
+    ) -> SimulationParameters {
+        SimulationParameters {
+            caller: to_raddress(caller),
+            to: to_raddress(token),
+            data: Bytes::from(calldata),
+            value: U256::zero(),
+            overrides: Some(overwrites),
+            account_overrides: None,
+            gas_limit: None,
+            block_number: self.block.number,
+            timestamp: self.block.timestamp,
+        }
+    }
+}
+
+fn to_raddress(address: H160) -> rAddress {
+    rAddress::from_slice(address.as_bytes())
+}
+
+fn transfer_calldata(to: H160, amount: U256) -> Vec<u8> {
+    let mut calldata = id("transfer(address,uint256)").to_vec();
+    calldata.extend(encode(&[Token::Address(to), Token::Uint(amount)]));
+    calldata
+}
+
+fn transfer_from_calldata(from: H160, to: H160, amount: U256) -> Vec<u8> {
+    let mut calldata = id("transferFrom(address,address,uint256)").to_vec();
+    calldata.extend(encode(&[Token::Address(from), Token::Address(to), Token::Uint(amount)]));
+    calldata
+}
+
+/// Adds the storage changes of a simulated call to the overwrites, so later calls see them.
+fn apply_state_updates(
+    overwrites: &mut HashMap<rAddress, Overwrites>,
+    state_updates: &HashMap<rAddress, StateUpdate>,
+) {
+    for (address, update) in state_updates {
+        if let Some(storage) = &update.storage {
+            let account_overwrites = overwrites.entry(*address).or_default();
+            for (slot, value) in storage {
+                account_overwrites.insert(U256(*slot.as_limbs()), U256(*value.as_limbs()));
+            }
+        }
+    }
+}
+
+/// The tax of a transfer of `amount` that debited the sender `debited` and credited the receiver
+/// `received`. Taxes charged on top of the amount show as a larger debit, so the tax is measured
+/// against whichever is larger.
+fn transfer_tax_bps(amount: U256, debited: U256, received: U256) -> u32 {
+    tax_bps(U256::max(amount, debited), received)
+}
+
+/// The share of `amount` that was not received, in basis points and rounded up. Shortfalls
+/// within the `ROUNDING_TOLERANCE` are ignored.
+fn tax_bps(amount: U256, received: U256) -> u32 {
+    if amount.is_zero() || received.saturating_add(U256::from(ROUNDING_TOLERANCE)) >= amount {
+        return 0;
+    }
+    let amount_wide = U512::from(amount);
+    let missing = (amount - received).full_mul(U256::from(BPS));
+    let tax = (missing + amount_wide - U512::one()) / amount_wide;
+    // the tax is at most BPS, as received < amount
+    tax.low_u32()
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+
+    use rstest::rstest;
+
+    use crate::{
+        evm::{
+            test_utils::{deploy, test_block, CONTRACT},
+            tycho_db::PreCachedDB,
+        },
+        protocol::vm::{
+            test_utils::{mocked_dai_engine, DAI},
+            utils::{ContractCompiler, SlotId},
+        },
+    };
+
+    const SOLIDITY_SLOTS: TokenSlots =
+        ((U256([0, 0, 0, 0]), U256([1, 0, 0, 0])), ContractCompiler::Solidity);
+
+    /// Bytecode of a minimal token storing balances in a mapping at slot 0, with `balanceOf`,
+    /// `transfer` and `transferFrom` (without allowances). Transfers of more than `max_amount`
+    /// revert and `tax_bps` of every transfer is burned.
+    fn token_code(tax_bps: u16, max_amount: U256) -> Vec<u8> {
+        let mut max_word = [0u8; 32];
+        max_amount.to_big_endian(&mut max_word);
+        hex::decode(format!(
+            concat!(
+                "60003560e01c806370a082311461002b578063a9059cbb1461004557806323b872dd14610051",
+                "57600080fd5b600435600052600060205260406000205460005260206000f35b602435600435",
+                "3361005f565b60443560243560043561005f565b7f{max}83116100c65760005260006020526",
+                "04060002080548084116100c657839003905561271061{tax:04x}8302048203906000526040",
+                "600020805482019055600160005260206000f35b600080fd",
+            ),
+            max = hex::encode(max_word),
+            tax = tax_bps,
+        ))
+        .unwrap()
+    }
+
+    fn detector_with_token(code: &[u8]) -> (ERC20Token, TokenTaxDetector<PreCachedDB>) {
+        let db = PreCachedDB::new().expect("Failed to create PreCachedDB");
+        deploy(&db, CONTRACT, code);
+        let token = ERC20Token::new(
+            "0x0000000000000000000000000000000000001234",
+            18,
+            "TKN",
+            U256::from(10_000),
+        );
+        (token, TokenTaxDetector::new(SimulationEngine::new(db, false), test_block()))
+    }
+
+    #[rstest]
+    #[case::untaxed(1000, 1000, 0)]
+    #[case::five_percent(1000, 950, 500)]
+    #[case::rounded_up(30, 20, 3334)]
+    #[case::share_rounding(1_000_000, 999_998, 0)]
+    #[case::beyond_rounding_tolerance(1_000_000, 999_997, 1)]
+    #[case::nothing_received(1000, 0, 10_000)]
+    #[case::more_received(1000, 1001, 0)]
+    fn test_tax_bps(#[case] amount: u64, #[case] received: u64, #[case] expected: u32) {
+        assert_eq!(tax_bps(U256::from(amount), U256::from(received)), expected);
+    }
+
+    #[rstest]
+    #[case::debited_amount(1000, 1000, 950, 500)]
+    #[case::tax_on_top(1000, 1100, 1000, 910)]
+    #[case::debited_less(1000, 900, 900, 1000)]
+    fn test_transfer_tax_bps(
+        #[case] amount: u64,
+        #[case] debited: u64,
+        #[case] received: u64,
+        #[case] expected: u32,
+    ) {
+        assert_eq!(
+            transfer_tax_bps(U256::from(amount), U256::from(debited), U256::from(received)),
+            expected
+        );
+    }
+
+    #[test]
+    fn test_transfer_behaviour_from_report() {
+        let report = TokenTransferReport {
+            transfer: TransferOutcome::Succeeded { tax_bps: 100 },
+            transfer_from: TransferOutcome::Succeeded { tax_bps: 100 },
+            sell: Some(TransferOutcome::Reverted("Revert! Reason: blacklisted".to_string())),
+            buy: Some(TransferOutcome::Succeeded { tax_bps: 500 }),
+            max_transfer_amount: Some(U256::from(1000)),
+            rebasing: true,
+        };
+
+        let behaviour = report.transfer_behaviour();
+
+        assert!(report.is_restricted());
+        assert_eq!(behaviour.buy_tax_bps, 500);
+        assert_eq!(behaviour.sell_tax_bps, 100);
+        assert_eq!(behaviour.max_transfer_amount, Some(U256::from(1000)));
+        assert!(behaviour.restricted);
+        assert!(behaviour.rebasing);
+    }
+
+    #[test]
+    fn test_detect_untaxed_token() {
+        let token = ERC20Token::new(DAI, 18, "DAI", U256::from(10_000));
+        let detector = TokenTaxDetector::new(mocked_dai_engine(), test_block());
+
+        // the mocked ERC20 contract stores balances at slot 0 and allowances at slot 1
+        let report = detector
+            .detect(&token, ((SlotId::from(0), SlotId::from(1)), ContractCompiler::Solidity), None)
+            .unwrap();
+
+        assert_eq!(report.transfer, TransferOutcome::Succeeded { tax_bps: 0 });
+        assert_eq!(report.transfer_from, TransferOutcome::Succeeded { tax_bps: 0 });
+        assert_eq!(report.max_transfer_amount, None);
+        assert_eq!(report.transfer_behaviour(), TransferBehaviour::default());
+        assert!(detector
+            .detect(&token, ((SlotId::from(5), SlotId::from(1)), ContractCompiler::Solidity), None)
+            .is_err());
+    }
+
+    #[test]
+    fn test_detect_taxed_token() {
+        let (token, detector) = detector_with_token(&token_code(500, U256::MAX));
+
+        let report = detector
+            .detect(&token, SOLIDITY_SLOTS, None)
+            .unwrap();
+
+        assert_eq!(report.transfer, TransferOutcome::Succeeded { tax_bps: 500 });
+        assert_eq!(report.transfer_from, TransferOutcome::Succeeded { tax_bps: 500 });
+        assert_eq!(report.max_transfer_amount, None);
+        assert!(report.transfer_behaviour().has_tax());
+        assert!(!report.is_restricted());
+    }
+
+    #[test]
+    fn test_detect_reverting_token() {
+        let (token, detector) = detector_with_token(&token_code(0, U256::zero()));
+
+        let report = detector
+            .detect(&token, SOLIDITY_SLOTS, None)
+            .unwrap();
+
+        assert!(matches!(report.transfer, TransferOutcome::Reverted(_)));
+        assert!(matches!(report.transfer_from, TransferOutcome::Reverted(_)));
+        assert_eq!(report.max_transfer_amount, None);
+        assert!(report.is_restricted());
+    }
+
+    #[test]
+    fn test_detect_max_transfer_amount() {
+        let (token, detector) = detector_with_token(&token_code(0, U256::from(1000)));
+
+        // small enough for the search to end on a precision of a single unit
+        let report = detector
+            .with_probe_amount(U256::from(10))
+            .detect(&token, SOLIDITY_SLOTS, None)
+            .unwrap();
+
+        assert_eq!(report.transfer, TransferOutcome::Succeeded { tax_bps: 0 });
+        assert_eq!(report.max_transfer_amount, Some(U256::from(1000)));
+        assert!(!report.transfer_behaviour().has_tax());
+    }
+
+    #[test]
+    fn test_is_blacklisted() {
+        let blacklisted = H160([0xba; 20]);
+        // `transferFrom` reverts if `from` is the blacklisted account and returns true otherwise
+        let code = hex::decode(format!(
+            "60043573{}14602657600160005260206000f35b600080fd",
+            hex::encode(blacklisted.as_bytes())
+        ))
+        .unwrap();
+        let (token, detector) = detector_with_token(&code);
+
+        assert!(detector
+            .is_blacklisted(&token, SOLIDITY_SLOTS, blacklisted)
+            .unwrap());
+        assert!(!detector
+            .is_blacklisted(&token, SOLIDITY_SLOTS, H160([0xbb; 20]))
+            .unwrap());
+    }
+}
@@ -135,6 +135,7 @@ impl TryFromWithBlock<ComponentWithState> for VMPoolState<PreCachedDB> {
             adapter_file_path,
             involved_contracts,
             stateless_contracts,
+            HashMap::new(),
             manual_updates,
             false,
         )
@@ -1,4 +1,4 @@
-use std::collections::HashMap;
+use std::{collections::HashMap, sync::Arc};
 
 use chrono::Utc;
 use ethers::{
@@ -45,7 +45,7 @@ pub struct TychoSimulationResponse {
 ///
 /// # Fields
 /// - `abi`: The Application Binary Interface of the contract, which defines its functions and event
-///   signatures.
+///   signatures. It is shared between clones of the contract.
 /// - `address`: The address of the contract being simulated.
 /// - `engine`: The `SimulationEngine` instance responsible for simulating transactions and managing
 ///   the contract's state.
@@ -55,7 +55,7 @@ pub struct TychoSimulationResponse {
 /// fail. These errors provide detailed feedback on potential issues.
 #[derive(Clone, Debug)]
 pub struct TychoSimulationContract<D: DatabaseRef + std::clone::Clone> {
-    abi: Abi,
+    abi: Arc<Abi>,
     address: Address,
     engine: SimulationEngine<D>,
 }
@@ -65,7 +65,7 @@ where
     D::Error: std::fmt::Debug,
 {
     pub fn new(address: Address, engine: SimulationEngine<D>) -> Result<Self, SimulationError> {
-        let abi = load_swap_abi()?;
+        let abi = Arc::new(load_swap_abi()?);
         Ok(Self { address, abi, engine })
     }
     fn encode_input(&self, fname: &str, args: Vec<Token>) -> Result<Vec<u8>, SimulationError> {
@@ -161,6 +161,7 @@ where
             overrides,
             caller: caller.unwrap_or(*EXTERNAL_ACCOUNT),
             value,
+            account_overrides: None,
             gas_limit: None,
         };
 
@@ -12,11 +12,12 @@ use ethers::{
     abi::Abi,
     core::utils::keccak256,
     providers::{Http, Middleware, Provider},
-    types::{Address, H160, U256},
+    types::{Address, H160, H256, U256},
 };
 use hex::FromHex;
 use mini_moka::sync::Cache;
 use revm::primitives::{Bytecode, Bytes};
+use serde::{Deserialize, Serialize};
 
 use crate::{
     evm::simulation::SimulationEngineError,
@@ -33,13 +34,14 @@ pub fn maybe_coerce_error(
 ) -> SimulationEngineError {
     match err {
         // Check for revert situation (if error message starts with "0x")
-        SimulationEngineError::TransactionError { ref data, ref gas_used }
+        SimulationEngineError::TransactionError { ref data, ref gas_used, ref trace }
             if data.starts_with("0x") =>
         {
             let reason = parse_solidity_error_message(data);
             let err = SimulationEngineError::TransactionError {
                 data: format!("Revert! Reason: {}", reason),
                 gas_used: *gas_used,
+                trace: trace.clone(),
             };
 
             // Check if we are running out of gas
@@ -60,7 +62,7 @@ pub fn maybe_coerce_error(
             err
         }
         // Check if "OutOfGas" is part of the error message
-        SimulationEngineError::TransactionError { ref data, ref gas_used }
+        SimulationEngineError::TransactionError { ref data, ref gas_used, .. }
             if data.contains("OutOfGas") =>
         {
             let usage_msg = if let (Some(gas_limit), Some(gas_used)) = (gas_limit, gas_used) {
@@ -183,6 +185,36 @@ pub fn get_storage_slot_index_at_key(key: Address, mapping_slot: SlotId) -> Slot
     SlotId::from_big_endian(&slot_bytes)
 }
 
+/// The compiler of a contract, which determines how the storage slots of mapping values are
+/// derived.
+#[derive(Clone, Copy, Debug, Default, PartialEq, Eq, Hash, Serialize, Deserialize)]
+pub enum ContractCompiler {
+    /// Hashes the key followed by the mapping slot
+    #[default]
+    Solidity,
+    /// Hashes the mapping slot followed by the key
+    Vyper,
+}
+
+impl ContractCompiler {
+    /// Get storage slot index of a value stored at a certain key in a mapping, see
+    /// `get_storage_slot_index_at_key`.
+    pub fn compute_map_slot(&self, key: Address, mapping_slot: SlotId) -> SlotId {
+        match self {
+            ContractCompiler::Solidity => get_storage_slot_index_at_key(key, mapping_slot),
+            ContractCompiler::Vyper => {
+                let mut mapping_slot_bytes = [0u8; 32];
+                mapping_slot.to_big_endian(&mut mapping_slot_bytes);
+                let key_bytes = H256::from(key);
+
+                let slot_bytes =
+                    keccak256([&mapping_slot_bytes[..], key_bytes.as_bytes()].concat());
+                SlotId::from_big_endian(&slot_bytes)
+            }
+        }
+    }
+}
+
 fn get_solidity_panic_codes() -> HashMap<u64, String> {
     let mut panic_codes = HashMap::new();
     panic_codes.insert(0, "GenericCompilerPanic".to_string());
@@ -336,16 +368,36 @@ mod tests {
         assert!(!code.bytes().is_empty(), "Code should not be empty");
     }
 
+    #[test]
+    fn test_compute_map_slot() {
+        let key: Address = "0xC63135E4bF73F637AF616DFd64cf701866BB2628"
+            .parse()
+            .unwrap();
+        let mut expected_vyper = [0u8; 64];
+        expected_vyper[31] = 3;
+        expected_vyper[44..].copy_from_slice(key.as_bytes());
+
+        assert_eq!(
+            ContractCompiler::Solidity.compute_map_slot(key, U256::from(3)),
+            get_storage_slot_index_at_key(key, U256::from(3))
+        );
+        assert_eq!(
+            ContractCompiler::Vyper.compute_map_slot(key, U256::from(3)),
+            SlotId::from_big_endian(&keccak256(expected_vyper))
+        );
+    }
+
     #[test]
     fn test_maybe_coerce_error_revert_no_gas_info() {
         let err = SimulationEngineError::TransactionError{
             data: "0x08c379a000000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000011496e76616c6964206f7065726174696f6e000000000000000000000000000000".to_string(),
-            gas_used: None
+            gas_used: None,
+            trace: None,
         };
 
         let result = maybe_coerce_error(&err, "test_pool", None);
 
-        if let SimulationEngineError::TransactionError { ref data, gas_used: _ } = result {
+        if let SimulationEngineError::TransactionError { ref data, .. } = result {
             assert!(data.contains("Revert! Reason: Invalid operation"));
         } else {
             panic!("Expected SolidityError error");
@@ -357,7 +409,8 @@ mod tests {
         // Test out-of-gas situation with gas limit and gas used provided
         let err = SimulationEngineError::TransactionError{
             data: "0x08c379a000000000000000000000000000000000000000000000000000000000000000200000000000000000000000000000000000000000000000000000000000000011496e76616c6964206f7065726174696f6e000000000000000000000000000000".to_string(),
-            gas_used: Some(980)
+            gas_used: Some(980),
+            trace: None,
         };
 
         let result = maybe_coerce_error(&err, "test_pool", Some(1000));
@@ -376,6 +429,7 @@ mod tests {
         let err = SimulationEngineError::TransactionError {
             data: "OutOfGas".to_string(),
             gas_used: None,
+            trace: None,
         };
 
         let result = maybe_coerce_error(&err, "test_pool", None);
@@ -407,11 +461,12 @@ mod tests {
         let err = SimulationEngineError::TransactionError {
             data: "Some other error".to_string(),
             gas_used: None,
+            trace: None,
         };
 
         let result = maybe_coerce_error(&err, "test_pool", None);
 
-        if let SimulationEngineError::TransactionError { ref data, gas_used: _ } = result {
+        if let SimulationEngineError::TransactionError { ref data, .. } = result {
             assert_eq!(data, "Some other error");
         } else {
             panic!("Expected solidity error");
@@ -68,6 +68,36 @@ pub mod hex_bytes_option {
     }
 }
 
+/// serde functions for handling numbers as decimal strings, e.g. `u128` values that don't fit
+/// into a JSON number
+pub mod decimal_string {
+    use std::{fmt::Display, str::FromStr};
+
+    use serde::{Deserialize, Deserializer, Serializer};
+
+    /// Serialize a number as a decimal string
+    pub fn serialize<S, T>(x: &T, s: S) -> Result<S::Ok, S::Error>
+    where
+        S: Serializer,
+        T: Display,
+    {
+        s.serialize_str(&x.to_string())
+    }
+
+    /// Deserialize a decimal string into a number
+    pub fn deserialize<'de, T, D>(d: D) -> Result<T, D::Error>
+    where
+        D: Deserializer<'de>,
+        T: FromStr,
+        T::Err: Display,
+    {
+        let value = String::deserialize(d)?;
+        value
+            .parse()
+            .map_err(|e: T::Err| serde::de::Error::custom(e.to_string()))
+    }
+}
+
 #[cfg(test)]
 mod tests {
     use super::*;
@@ -101,6 +131,30 @@ mod tests {
         assert_eq!(deserialized.bytes_option, Some(vec![0u8; 10]));
     }
 
+    #[derive(Debug, Serialize, Deserialize)]
+    struct TestNumbers {
+        #[serde(with = "decimal_string")]
+        unsigned: u128,
+        #[serde(with = "decimal_string")]
+        signed: i128,
+    }
+
+    #[test]
+    fn decimal_string_serialize_deserialize() {
+        let test_struct = TestNumbers { unsigned: u128::MAX, signed: i128::MIN };
+
+        let serialized = serde_json::to_string(&test_struct).unwrap();
+        assert_eq!(
+            serialized,
+            "{\"unsigned\":\"340282366920938463463374607431768211455\",\
+             \"signed\":\"-170141183460469231731687303715884105728\"}"
+        );
+
+        let deserialized: TestNumbers = serde_json::from_str(&serialized).unwrap();
+        assert_eq!(deserialized.unsigned, u128::MAX);
+        assert_eq!(deserialized.signed, i128::MIN);
+    }
+
     #[test]
     fn hex_bytes_option_none() {
         let test_struct = TestStruct { bytes: vec![0u8; 10], bytes_option: None };
@@ -2,6 +2,7 @@ from tycho_simulation_py._tycho_simulation_py import (
     SimulationEngine,
     SimulationParameters,
     SimulationResult,
+    Log,
     AccountInfo,
     AccountUpdate,
     BlockHeader,
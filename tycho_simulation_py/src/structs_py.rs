@@ -11,7 +11,9 @@ use tracing::info;
 use std::{collections::HashMap, str::FromStr, sync::Arc};
 
 use std::fmt::Debug;
-use tycho_simulation::evm::{account_storage, simulation, simulation_db, tycho_db, tycho_models};
+use tycho_simulation::evm::{
+    account_storage, logs, simulation, simulation_db, tycho_db, tycho_models,
+};
 
 /// Data needed to invoke a transaction simulation
 ///
@@ -108,6 +110,7 @@ impl From<SimulationParameters> for simulation::SimulationParameters {
             data: Bytes::from(params.data),
             value: U256::from_big_endian(params.value.to_bytes_be().as_slice()),
             overrides,
+            account_overrides: None,
             gas_limit: params.gas_limit,
             block_number: params.block_number.unwrap_or(0),
             timestamp: params.timestamp.unwrap_or(0),
@@ -293,6 +296,8 @@ impl From<AccountUpdate> for tycho_models::AccountUpdate {
 ///     State changes caused by the transaction
 /// gas_used: int
 ///     Gas used by the transaction (already reduced by the refunded gas)
+/// logs: list[Log]
+///     Logs emitted by the transaction, in emission order
 #[pyclass]
 #[derive(Clone, Debug)]
 pub struct SimulationResult {
@@ -302,6 +307,8 @@ pub struct SimulationResult {
     pub state_updates: HashMap<String, StateUpdate>,
     #[pyo3(get)]
     pub gas_used: u64,
+    #[pyo3(get)]
+    pub logs: Vec<Log>,
 }
 
 #[pymethods]
@@ -321,6 +328,59 @@ impl From<simulation::SimulationResult> for SimulationResult {
             result: rust_result.result.into(),
             state_updates: py_state_updates,
             gas_used: rust_result.gas_used,
+            logs: rust_result
+                .logs
+                .into_iter()
+                .map(Log::from)
+                .collect(),
+        }
+    }
+}
+
+/// A log emitted during a simulated transaction
+///
+/// Attributes
+/// ----------
+/// address: str
+///     Address of the contract that emitted the log
+/// topics: list[str]
+///     Topics of the log as hex strings
+/// data: bytearray
+///     Non-indexed data of the log
+/// depth: Optional[int]
+///     Depth of the call that emitted the log; the transaction's call has depth 0. Only set
+///     for traced simulations
+#[pyclass]
+#[derive(Clone, Debug)]
+pub struct Log {
+    #[pyo3(get)]
+    pub address: String,
+    #[pyo3(get)]
+    pub topics: Vec<String>,
+    #[pyo3(get)]
+    pub data: Vec<u8>,
+    #[pyo3(get)]
+    pub depth: Option<usize>,
+}
+
+#[pymethods]
+impl Log {
+    fn __repr__(&self) -> String {
+        format!("{:#?}", self)
+    }
+}
+
+impl From<logs::SimulationLog> for Log {
+    fn from(log: logs::SimulationLog) -> Self {
+        Log {
+            address: format!("{:#x}", log.address),
+            topics: log
+                .topics
+                .iter()
+                .map(|topic| format!("{:#x}", topic))
+                .collect(),
+            data: log.data.to_vec(),
+            depth: log.depth,
         }
     }
 }
@@ -447,7 +507,7 @@ impl From<simulation::SimulationEngineError> for SimulationErrorDetails {
             simulation::SimulationEngineError::StorageError(reason) => {
                 SimulationErrorDetails { data: reason, gas_used: None }
             }
-            simulation::SimulationEngineError::TransactionError { data, gas_used } => {
+            simulation::SimulationEngineError::TransactionError { data, gas_used, .. } => {
                 SimulationErrorDetails { data, gas_used }
             }
             simulation::SimulationEngineError::OutOfGas(reason, _) => {